@@ -1,7 +1,12 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::{Args, Parser, Subcommand};
 use ext_widget_core::{
-    logger::install_logger,
-    nvim::{start_parent, start_server},
+    doctor::{has_failure, run_checks, DoctorOptions},
+    env::get_tty,
+    logger::{install_logger, LogOptions},
+    nvim::{start_parent, start_server, start_unix_server, HighlightTheme},
+    render::{render_markdown_png, RenderOptions},
 };
 
 #[derive(Parser)]
@@ -9,6 +14,35 @@ use ext_widget_core::{
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    log: LogArgs,
+}
+
+#[derive(Args)]
+struct LogArgs {
+    /// The log file, defaults to `ext-widget.log` in Neovim's log directory.
+    #[arg(long, global = true, env = "EXT_WIDGET_LOG_FILE")]
+    log_file: Option<PathBuf>,
+    /// Log filter directives, e.g. `debug` or `ext_widget_core=debug,warn`.
+    #[arg(long, global = true, env = "EXT_WIDGET_LOG", default_value = "info")]
+    log_filter: String,
+    /// Rotate the log file when it gets larger than this many bytes.
+    #[arg(long, global = true, value_name = "BYTES")]
+    log_max_size: Option<u64>,
+}
+
+impl LogArgs {
+    fn options(&self) -> LogOptions {
+        let mut opts = LogOptions {
+            path: self.log_file.clone(),
+            filter: self.log_filter.clone(),
+            ..Default::default()
+        };
+        if let Some(max_size) = self.log_max_size {
+            opts.max_size = max_size;
+        }
+        opts
+    }
 }
 
 #[derive(Subcommand)]
@@ -17,6 +51,10 @@ enum Commands {
     Serve(ServeOpts),
     /// Start ex-widget in embeded mode.
     Embed,
+    /// Render a markdown file to a png image, without Neovim.
+    Render(RenderOpts),
+    /// Check fonts and terminal needed to show widgets, and list parsers.
+    Doctor(DoctorOpts),
 }
 
 #[derive(Args)]
@@ -24,22 +62,142 @@ struct ServeOpts {
     /// The address to listen on.
     #[arg(long, default_value = "127.0.0.1:7000")]
     addr: String,
+    /// Listen on a unix domain socket at the given path instead of TCP.
+    #[arg(long, conflicts_with = "addr")]
+    socket: Option<PathBuf>,
+    /// Exit after this many seconds without any connection.
+    #[arg(long, value_name = "SECS")]
+    idle_timeout: Option<u64>,
+}
+
+#[derive(Args)]
+struct RenderOpts {
+    /// The markdown file to render.
+    #[arg(long)]
+    input: PathBuf,
+    /// Where to write the png image.
+    #[arg(long)]
+    output: PathBuf,
+    /// A json file of highlight groups, e.g. the output of
+    /// `vim.json.encode(vim.api.nvim_get_hl(0, {}))`.
+    #[arg(long)]
+    theme: Option<PathBuf>,
+    /// Width of the image in logical pixels.
+    #[arg(long, default_value_t = 800.0)]
+    width: f32,
+    /// Device pixels per logical pixel, e.g. 2 for HiDPI screens.
+    #[arg(long, default_value_t = 1.0)]
+    scale: f32,
+    /// Directories to look for `parser/<lang>.so` and
+    /// `queries/<lang>/highlights.scm`, can be given multiple times.
+    #[arg(long = "runtime-path", value_name = "DIR")]
+    runtime_paths: Vec<PathBuf>,
+    /// Font families for normal text.
+    #[arg(long, value_delimiter = ',')]
+    normal_font: Option<Vec<String>>,
+    /// Font families for code.
+    #[arg(long, value_delimiter = ',')]
+    mono_font: Option<Vec<String>>,
+    /// Font size of both normal text and code.
+    #[arg(long)]
+    font_size: Option<f32>,
+}
+
+#[derive(Args)]
+struct DoctorOpts {
+    /// Directories to look for `parser/<lang>.so`, can be given multiple
+    /// times.
+    #[arg(long = "runtime-path", value_name = "DIR")]
+    runtime_paths: Vec<PathBuf>,
+    /// Font families for normal text.
+    #[arg(long, value_delimiter = ',')]
+    normal_font: Option<Vec<String>>,
+    /// Font families for code.
+    #[arg(long, value_delimiter = ',')]
+    mono_font: Option<Vec<String>>,
+    /// The tty to check, defaults to the tty of this process.
+    #[arg(long)]
+    tty: Option<String>,
+}
+
+/// Returns whether all checks passed without failure.
+async fn doctor(opts: DoctorOpts) -> anyhow::Result<bool> {
+    let tty = match opts.tty {
+        Some(tty) => Some(tty),
+        None => get_tty().await.ok().filter(|tty| tty.starts_with('/')),
+    };
+    let mut doctor_opts = DoctorOptions {
+        runtime_paths: opts.runtime_paths,
+        tty,
+        ..Default::default()
+    };
+    if let Some(font) = opts.normal_font {
+        doctor_opts.normal_font = font;
+    }
+    if let Some(font) = opts.mono_font {
+        doctor_opts.mono_font = font;
+    }
+    let results = run_checks(&doctor_opts).await;
+    for result in &results {
+        println!("{}", result);
+    }
+    Ok(!has_failure(&results))
+}
+
+async fn render(opts: RenderOpts) -> anyhow::Result<()> {
+    let text = std::fs::read_to_string(&opts.input)?;
+    let theme = match &opts.theme {
+        Some(path) => HighlightTheme::from_file(path)?,
+        None => HighlightTheme::default(),
+    };
+    let mut render_opts = RenderOptions {
+        width: opts.width,
+        scale: opts.scale,
+        runtime_paths: opts.runtime_paths,
+        ..Default::default()
+    };
+    if let Some(font) = opts.normal_font {
+        render_opts.normal_font = font;
+    }
+    if let Some(font) = opts.mono_font {
+        render_opts.mono_font = font;
+    }
+    if let Some(size) = opts.font_size {
+        render_opts.normal_font_size = size;
+        render_opts.mono_font_size = size;
+    }
+    let png = render_markdown_png(&text, theme, &render_opts).await?;
+    std::fs::write(&opts.output, png)?;
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
-    install_logger()?;
+    install_logger(&cli.log.options())?;
 
     match cli.command {
         Commands::Serve(opts) => {
-            let addr: String = opts.addr.parse()?;
-            start_server(&addr).await?;
+            let idle_timeout = opts.idle_timeout.map(Duration::from_secs);
+            if let Some(socket) = opts.socket {
+                start_unix_server(&socket, idle_timeout).await?;
+            } else {
+                let addr: String = opts.addr.parse()?;
+                start_server(&addr, idle_timeout).await?;
+            }
         }
         Commands::Embed => {
             start_parent().await?;
         }
+        Commands::Render(opts) => {
+            render(opts).await?;
+        }
+        Commands::Doctor(opts) => {
+            if !doctor(opts).await? {
+                std::process::exit(1);
+            }
+        }
     }
 
     Ok(())
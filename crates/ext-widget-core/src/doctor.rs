@@ -0,0 +1,253 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use skia_safe::{FontMgr, FontStyle};
+
+use crate::{env::in_tmux, nvim::HoverConfig, tmux::enable_tmux_pass_through};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// Nothing to pass or fail, only reported.
+    Info,
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The result of checking one prerequisite.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    /// How to fix it, if it's not passed.
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn info(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Info,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn pass(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(
+        name: &str, message: impl Into<String>, hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(
+        name: &str, message: impl Into<String>, hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            status: CheckStatus::Fail,
+            ..Self::warn(name, message, hint)
+        }
+    }
+}
+
+impl Display for CheckResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            CheckStatus::Info => "INFO",
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        };
+        write!(f, "[{}] {}: {}", status, self.name, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// What to check, the same things a hover needs.
+#[derive(Debug, Clone)]
+pub struct DoctorOptions {
+    pub normal_font: Vec<String>,
+    pub mono_font: Vec<String>,
+    /// Runtime paths to look for tree-sitter parsers.
+    pub runtime_paths: Vec<PathBuf>,
+    /// The tty images are written to, the pixel size is read from it.
+    pub tty: Option<String>,
+}
+
+impl Default for DoctorOptions {
+    fn default() -> Self {
+        let hover = HoverConfig::default();
+        Self {
+            normal_font: hover.normal_font,
+            mono_font: hover.mono_font,
+            runtime_paths: Vec::new(),
+            tty: None,
+        }
+    }
+}
+
+/// Check all prerequisites.
+pub async fn run_checks(opts: &DoctorOptions) -> Vec<CheckResult> {
+    vec![
+        check_fonts("normal font", &opts.normal_font),
+        check_fonts("mono font", &opts.mono_font),
+        check_tmux_pass_through().await,
+        check_pixel_size(opts.tty.as_deref()),
+        check_parsers(&opts.runtime_paths),
+    ]
+}
+
+/// Whether any check failed.
+pub fn has_failure(results: &[CheckResult]) -> bool {
+    results.iter().any(|r| r.status == CheckStatus::Fail)
+}
+
+fn check_fonts(name: &str, families: &[String]) -> CheckResult {
+    let font_mgr = FontMgr::new();
+    let missing: Vec<_> = families
+        .iter()
+        .filter(|family| {
+            font_mgr
+                .match_family_style(family, FontStyle::normal())
+                .is_none()
+        })
+        .cloned()
+        .collect();
+    let hint = "install the font, or change the font families in the config";
+    if families.is_empty() {
+        CheckResult::fail(name, "no font family configured", hint)
+    } else if missing.len() == families.len() {
+        CheckResult::fail(
+            name,
+            format!("none of {:?} is installed", families),
+            hint,
+        )
+    } else if !missing.is_empty() {
+        CheckResult::warn(name, format!("{:?} not installed", missing), hint)
+    } else {
+        CheckResult::pass(name, format!("{:?}", families))
+    }
+}
+
+async fn check_tmux_pass_through() -> CheckResult {
+    const NAME: &str = "tmux passthrough";
+    if !in_tmux() {
+        return CheckResult::pass(NAME, "not in tmux");
+    }
+    let hint = "add `set -g allow-passthrough on` to tmux.conf";
+    match enable_tmux_pass_through().await {
+        Ok(true) => CheckResult::pass(NAME, "allow-passthrough is on"),
+        Ok(false) => CheckResult::fail(NAME, "allow-passthrough is off", hint),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("failed to read allow-passthrough: {}", e),
+            hint,
+        ),
+    }
+}
+
+fn check_pixel_size(tty: Option<&str>) -> CheckResult {
+    const NAME: &str = "terminal pixel size";
+    let Some(tty) = tty else {
+        return CheckResult::warn(
+            NAME,
+            "no tty found",
+            "run it from the terminal Neovim runs in",
+        );
+    };
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(tty);
+    let size = file
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(rustix::termios::tcgetwinsize(&file)?));
+    match size {
+        Ok(size) if size.ws_xpixel == 0 || size.ws_ypixel == 0 => {
+            CheckResult::fail(
+                NAME,
+                format!("TIOCGWINSZ of {} has no pixel size", tty),
+                "use a terminal that reports its pixel size, e.g. kitty",
+            )
+        }
+        Ok(size) => CheckResult::pass(
+            NAME,
+            format!(
+                "{}x{} cells, {}x{} pixels",
+                size.ws_col, size.ws_row, size.ws_xpixel, size.ws_ypixel
+            ),
+        ),
+        Err(e) => CheckResult::fail(
+            NAME,
+            format!("failed to read the size of {}: {}", tty, e),
+            "check the tty is writable",
+        ),
+    }
+}
+
+/// Parsers are only used to highlight code blocks, none of them is
+/// required, so the languages found are only reported.
+fn check_parsers(rtps: &[PathBuf]) -> CheckResult {
+    const NAME: &str = "tree-sitter parsers";
+    let mut langs: Vec<_> = rtps
+        .iter()
+        .filter_map(|rtp| std::fs::read_dir(rtp.join("parser")).ok())
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "so" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    langs.sort();
+    langs.dedup();
+    if langs.is_empty() {
+        CheckResult::info(NAME, "none found, code blocks are not highlighted")
+    } else {
+        CheckResult::info(NAME, langs.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_results() {
+        let parsers = check_parsers(&[]);
+        assert_eq!(parsers.status, CheckStatus::Info);
+        assert!(!has_failure(&[parsers]));
+
+        let fonts = check_fonts("font", &["No Such Font 42".to_string()]);
+        assert_eq!(fonts.status, CheckStatus::Fail);
+        assert!(has_failure(&[fonts]));
+
+        let size = check_pixel_size(None);
+        assert_eq!(size.status, CheckStatus::Warn);
+        assert!(!has_failure(&[size]));
+    }
+}
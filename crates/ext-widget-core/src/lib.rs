@@ -5,7 +5,13 @@ pub mod painting;
 pub mod test_utils;
 pub mod widgets;
 
+pub mod doctor;
 pub mod env;
+pub mod live;
 pub mod logger;
+pub mod placement;
+pub mod protocol;
+pub mod render;
 pub mod term;
 pub mod tmux;
+pub mod treesitter;
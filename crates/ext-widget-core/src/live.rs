@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    num::NonZeroU32,
+    sync::{
+        mpsc::{channel, Sender},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use tokio::{runtime::Runtime, sync::oneshot};
+use tracing::{info, warn};
+
+use crate::{
+    painting::RectSize,
+    render::{DisplayMetrics, HoverWidget, Repaint},
+};
+
+/// State owned by the render thread.
+struct RenderThread {
+    /// Drives the futures of building documents.
+    runtime: Runtime,
+    widgets: HashMap<NonZeroU32, HoverWidget>,
+}
+
+type Job = Box<dyn FnOnce(&mut RenderThread) + Send>;
+
+type DirtyCallback = Arc<dyn Fn(NonZeroU32) + Send + Sync>;
+
+/// The images of each page of a widget, and the size of a page.
+pub type RenderedImages = (Vec<Vec<u8>>, RectSize<f32>);
+
+/**
+ * Widgets kept after painting, keyed by the id of their image set, so they
+ * can be laid out again when the room for them changes.
+ *
+ * Widget trees are `Rc`, they live on a dedicated thread and all work on
+ * them is sent there through a channel. The thread is started on first use,
+ * and stops once this handle is dropped.
+ */
+#[derive(Default)]
+pub struct LiveWidgets {
+    sender: OnceCell<Sender<Job>>,
+    /// Called on the render thread when the state of a widget changes.
+    on_dirty: Mutex<Option<DirtyCallback>>,
+}
+
+impl Debug for LiveWidgets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LiveWidgets")
+            .field("sender", &self.sender)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LiveWidgets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `callback` with the id of a widget when its state changes, it
+    /// should be painted again with [`LiveWidgets::repaint`]. Only widgets
+    /// rendered after it's set are watched.
+    ///
+    /// It's called on the render thread, once until the widget is painted
+    /// again.
+    pub fn on_dirty(
+        &self, callback: impl Fn(NonZeroU32) + Send + Sync + 'static,
+    ) {
+        *self.on_dirty.lock() = Some(Arc::new(callback));
+    }
+
+    fn sender(&self) -> anyhow::Result<&Sender<Job>> {
+        self.sender.get_or_try_init(|| {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let (sender, receiver) = channel::<Job>();
+            std::thread::Builder::new()
+                .name("ext-widget-render".to_string())
+                .spawn(move || {
+                    let mut state = RenderThread {
+                        runtime,
+                        widgets: HashMap::new(),
+                    };
+                    while let Ok(job) = receiver.recv() {
+                        job(&mut state);
+                    }
+                    info!("Render thread stopped");
+                })?;
+            Ok(sender)
+        })
+    }
+
+    /// Run `job` on the render thread, and wait for its result.
+    async fn run<R, F>(&self, job: F) -> anyhow::Result<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut RenderThread) -> R + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.sender()?
+            .send(Box::new(move |state| {
+                let _ = sender.send(job(state));
+            }))
+            .ok()
+            .context("The render thread is stopped")?;
+        receiver.await.context("The render thread panicked")
+    }
+
+    /**
+     * Build a widget with `build` on the render thread, and render it as the
+     * widget `id`, replacing the previous one with the same id.
+     *
+     * `max_width` is the room for it in logical pixels. Returns the images
+     * of each page and the size of a page, see [`HoverWidget::render`].
+     */
+    pub async fn render<F, Fut>(
+        &self, id: NonZeroU32, build: F, metrics: DisplayMetrics,
+        max_width: f32,
+    ) -> anyhow::Result<RenderedImages>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<HoverWidget>>,
+    {
+        self.run(self.render_job(id, build, metrics, max_width))
+            .await?
+    }
+
+    /// Like [`LiveWidgets::render`], but returns right away. `done` is called
+    /// with the result on the render thread.
+    pub fn spawn_render<F, Fut, D>(
+        &self, id: NonZeroU32, build: F, metrics: DisplayMetrics,
+        max_width: f32, done: D,
+    ) -> anyhow::Result<()>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<HoverWidget>>,
+        D: FnOnce(anyhow::Result<RenderedImages>) + Send + 'static,
+    {
+        let job = self.render_job(id, build, metrics, max_width);
+        self.sender()?
+            .send(Box::new(move |state| done(job(state))))
+            .ok()
+            .context("The render thread is stopped")
+    }
+
+    fn render_job<F, Fut>(
+        &self, id: NonZeroU32, build: F, metrics: DisplayMetrics,
+        max_width: f32,
+    ) -> impl FnOnce(&mut RenderThread) -> anyhow::Result<RenderedImages>
+           + Send
+           + 'static
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<HoverWidget>>,
+    {
+        let on_dirty = self.on_dirty.lock().clone();
+        move |state| {
+            let mut widget = state.runtime.block_on(build())?;
+            if let Some(on_dirty) = on_dirty {
+                widget.set_waker(move || on_dirty(id));
+            }
+            let images = widget.render(metrics, max_width)?;
+            state.widgets.insert(id, widget);
+            Ok(images)
+        }
+    }
+
+    /// Lay out the widget `id` again with new metrics or room, returns the
+    /// new images if the layout changes, see [`LiveWidgets::render`].
+    pub async fn reflow(
+        &self, id: NonZeroU32, metrics: DisplayMetrics, max_width: f32,
+    ) -> anyhow::Result<Option<RenderedImages>> {
+        self.run(move |state| {
+            let Some(widget) = state.widgets.get_mut(&id) else {
+                return Ok(None);
+            };
+            if !widget.needs_layout(metrics, max_width) {
+                return Ok(None);
+            }
+            info!("Reflow widget {} within {}px", id, max_width);
+            widget.render(metrics, max_width).map(Some)
+        })
+        .await?
+    }
+
+    /// Paint the widget `id` again where its state changed, None if there is
+    /// no such widget, see [`HoverWidget::repaint`].
+    pub async fn repaint(
+        &self, id: NonZeroU32,
+    ) -> anyhow::Result<Option<Repaint>> {
+        self.run(move |state| {
+            let Some(widget) = state.widgets.get_mut(&id) else {
+                return Ok(None);
+            };
+            widget.repaint().map(Some)
+        })
+        .await?
+    }
+
+    /// Drop the widget `id`, without waiting for the render thread.
+    pub fn remove(&self, id: NonZeroU32) {
+        // nothing to drop if the thread is not started
+        let Some(sender) = self.sender.get() else {
+            return;
+        };
+        let job: Job = Box::new(move |state| {
+            state.widgets.remove(&id);
+        });
+        if sender.send(job).is_err() {
+            warn!("Failed to drop widget {}, the render thread stopped", id);
+        }
+    }
+}
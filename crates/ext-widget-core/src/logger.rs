@@ -1,36 +1,244 @@
-use std::fmt;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
-use tracing::{level_filters::LevelFilter, Event, Subscriber};
+use anyhow::Context;
+use once_cell::sync::{Lazy, OnceCell};
+use tokio::sync::broadcast;
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
 use tracing_log::NormalizeEvent;
 use tracing_subscriber::{
     fmt::{
         format::Writer, writer::BoxMakeWriter, FmtContext, FormatEvent,
         FormatFields, FormattedFields,
     },
-    layer::SubscriberExt,
+    layer::{self, SubscriberExt},
     registry::LookupSpan,
+    reload,
     util::SubscriberInitExt,
-    Registry,
+    EnvFilter, Layer, Registry,
 };
 
-pub fn install_logger() -> anyhow::Result<()> {
-    let writer = BoxMakeWriter::new(
-        std::fs::File::options()
-            .append(true)
-            .create(true)
-            .write(true)
-            .open("/tmp/ew-log.txt")?,
-    );
+const DEFAULT_FILTER: &str = "info";
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_KEEP: usize = 3;
+
+/// Handle to change the filter of the installed logger.
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// WARN and ERROR events, forwarded to Neovim.
+static WARNINGS: Lazy<broadcast::Sender<LogMessage>> =
+    Lazy::new(|| broadcast::channel(64).0);
+
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// The log file, defaults to [`default_log_path`].
+    pub path: Option<PathBuf>,
+    /// `EnvFilter` directives, e.g. `info` or `ext_widget_core=debug,warn`.
+    pub filter: String,
+    /// The log file is rotated when it gets larger than this many bytes.
+    pub max_size: u64,
+    /// How many rotated files to keep, as `<path>.1` to `<path>.<keep>`.
+    pub keep: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            path: None,
+            filter: DEFAULT_FILTER.to_string(),
+            max_size: DEFAULT_MAX_SIZE,
+            keep: DEFAULT_KEEP,
+        }
+    }
+}
+
+/// The same directory as `stdpath('log')` of Neovim, used if Neovim doesn't
+/// pass one.
+pub fn default_log_path() -> PathBuf {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .map(|home| PathBuf::from(home).join(".local/state"))
+        });
+    match state {
+        Some(state) => state.join("nvim/ext-widget.log"),
+        None => std::env::temp_dir().join(format!(
+            "ext-widget-{}.log",
+            rustix::process::getuid().as_raw()
+        )),
+    }
+}
+
+pub fn install_logger(opts: &LogOptions) -> anyhow::Result<()> {
+    let path = opts.path.clone().unwrap_or_else(default_log_path);
+    let file = RotatingFile::open(path, opts.max_size, opts.keep)?;
+    let writer = BoxMakeWriter::new(Mutex::new(file));
     let ra_fmt_layer = tracing_subscriber::fmt::layer()
         .event_format(LoggerFormatter)
         .with_writer(writer);
-    let filter = LevelFilter::INFO;
+    let (filter, handle) = reload::Layer::new(parse_filter(&opts.filter)?);
 
-    Registry::default().with(ra_fmt_layer).with(filter).init();
+    Registry::default()
+        .with(filter)
+        .with(ra_fmt_layer)
+        .with(WarningLayer)
+        .try_init()?;
+    let _ = FILTER.set(handle);
 
     Ok(())
 }
 
+fn parse_filter(directives: &str) -> anyhow::Result<EnvFilter> {
+    EnvFilter::try_new(directives)
+        .with_context(|| format!("Invalid log filter: {:?}", directives))
+}
+
+/// Replace the filter of the installed logger, `directives` are the same as
+/// [`LogOptions::filter`]. There is one logger per process, clients sharing
+/// a server replace each other's filter, the last one wins.
+pub fn set_log_filter(directives: &str) -> anyhow::Result<()> {
+    let filter = parse_filter(directives)?;
+    FILTER
+        .get()
+        .context("Logger is not installed")?
+        .reload(filter)?;
+    Ok(())
+}
+
+/// A WARN or ERROR event.
+#[derive(Debug, Clone)]
+pub struct LogMessage {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// Receive WARN and ERROR events logged from now on, from all sessions.
+pub fn subscribe_warnings() -> broadcast::Receiver<LogMessage> {
+    WARNINGS.subscribe()
+}
+
+struct WarningLayer;
+
+impl<S: Subscriber> Layer<S> for WarningLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: layer::Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > Level::WARN || WARNINGS.receiver_count() == 0 {
+            return;
+        }
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        let _ = WARNINGS.send(LogMessage {
+            level,
+            target: event.metadata().target().to_string(),
+            message: visitor.0,
+        });
+    }
+}
+
+/// Collects the fields of an event, the message comes first.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        use std::fmt::Write;
+
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = if field.name() == "message" {
+            write!(self.0, "{:?}", value)
+        } else {
+            write!(self.0, "{}={:?}", field.name(), value)
+        };
+    }
+}
+
+/// A log file rotated by size. When it's full, `<path>.<n>` is renamed to
+/// `<path>.<n + 1>` and `<path>` to `<path>.1`.
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, keep: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = Self::open_file(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        // logs may contain file contents, keep them private
+        File::options()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
 #[derive(Debug)]
 struct LoggerFormatter;
 
@@ -93,3 +301,28 @@ where
         writeln!(writer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("ext-widget-log-test-{}", std::process::id()));
+        let path = dir.join("test.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2)?;
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "dddddddd\n");
+        assert_eq!(read(&file.rotated_path(1)), "cccccccc\n");
+        assert_eq!(read(&file.rotated_path(2)), "bbbbbbbb\n");
+        assert!(!file.rotated_path(3).exists());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
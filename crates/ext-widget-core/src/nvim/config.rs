@@ -1,35 +1,81 @@
-use once_cell::sync::Lazy;
-use parking_lot::Mutex;
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::RangeInclusive,
+};
+
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    painting::Color, placement::PlacementConfig,
+    term::image::DEFAULT_MAX_CACHE_BYTES,
+};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ExtWidgetConfig {
+    /// Device pixels per logical pixel of widgets.
+    #[serde(default)]
+    pub scale: Scale,
     pub hover: HoverConfig,
+    #[serde(default)]
+    pub image: ImageConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub theme: ThemeConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Log filter directives, replaces the one given on the command line.
+    /// The filter is global to the server, with several clients the last
+    /// one set wins.
+    #[serde(default)]
+    pub level: Option<String>,
+    /// Forward WARN and ERROR logs to `vim.notify`. Logs are not tied to a
+    /// client, every client with it set gets all of them.
+    #[serde(default)]
+    pub notify: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageConfig {
+    /// Max total size (in bytes) of cached images, least recently used images
+    /// are evicted when exceeded.
+    #[serde(default = "default_max_cache_bytes")]
+    pub max_cache_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HoverConfig {
     #[serde(default = "default_normal_font")]
     pub normal_font: Vec<String>,
     #[serde(default = "default_font_size")]
-    pub normal_font_size: f32,
+    pub normal_font_size: FontSize,
     #[serde(default = "default_mono_font")]
     pub mono_font: Vec<String>,
     #[serde(default = "default_font_size")]
-    pub mono_font_size: f32,
+    pub mono_font_size: FontSize,
+    #[serde(default)]
     pub window: WindowConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowConfig {
     #[serde(default = "default_window_size_width")]
     pub max_width: f32,
     #[serde(default = "default_window_size_height")]
     pub max_height: f32,
+    /// Cells to move the hover away from its anchor, mirrored when it's
+    /// flipped to the other side.
     #[serde(default = "default_x_offset")]
     pub x_offset: i32,
     #[serde(default = "default_y_offset")]
     pub y_offset: i32,
+    /// Where to put the hover around the cursor.
+    #[serde(default)]
+    pub placement: PlacementConfig,
 }
 
 impl Default for HoverConfig {
@@ -44,6 +90,14 @@ impl Default for HoverConfig {
     }
 }
 
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            max_cache_bytes: default_max_cache_bytes(),
+        }
+    }
+}
+
 impl Default for WindowConfig {
     fn default() -> Self {
         Self {
@@ -51,10 +105,240 @@ impl Default for WindowConfig {
             max_height: default_window_size_height(),
             x_offset: default_x_offset(),
             y_offset: default_y_offset(),
+            placement: PlacementConfig::default(),
+        }
+    }
+}
+
+/// Device pixels per logical pixel, `1` unless set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scale {
+    /// Derived from the height of terminal cells, opt-in with `"auto"`.
+    Auto,
+    #[serde(untagged)]
+    Fixed(f32),
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self::Fixed(1.0)
+    }
+}
+
+/// A font size in logical pixels (`20` or `"20px"`), points (`"12pt"`), or
+/// heights of a terminal cell (`"1.0 cell"`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "FontSizeRepr", into = "FontSizeRepr")]
+pub enum FontSize {
+    Pixels(f32),
+    Points(f32),
+    Cells(f32),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid font size {0:?}, expected e.g. 20, \"12pt\" or \"1.0 cell\"")]
+pub struct ParseFontSizeError(String);
+
+impl FontSize {
+    pub fn new_from_str(s: &str) -> Result<Self, ParseFontSizeError> {
+        let err = || ParseFontSizeError(s.to_string());
+        let s = s.trim();
+        let (value, unit): (&str, fn(f32) -> Self) =
+            if let Some(v) = s.strip_suffix("px") {
+                (v, Self::Pixels)
+            } else if let Some(v) = s.strip_suffix("pt") {
+                (v, Self::Points)
+            } else if let Some(v) =
+                s.strip_suffix("cells").or_else(|| s.strip_suffix("cell"))
+            {
+                (v, Self::Cells)
+            } else {
+                (s, Self::Pixels)
+            };
+        let value: f32 = value.trim().parse().map_err(|_| err())?;
+        Ok(unit(value))
+    }
+
+    fn validate(&self, issues: &mut Vec<ConfigIssue>, path: &str) {
+        match *self {
+            Self::Pixels(v) => check_range(issues, path, v, 1.0..=200.0),
+            Self::Points(v) => check_range(issues, path, v, 1.0..=150.0),
+            Self::Cells(v) => check_range(issues, path, v, 0.1..=10.0),
+        }
+    }
+}
+
+impl Display for FontSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pixels(v) => write!(f, "{}px", v),
+            Self::Points(v) => write!(f, "{}pt", v),
+            Self::Cells(v) => write!(f, "{} cell", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum FontSizeRepr {
+    Number(f32),
+    String(String),
+}
+
+impl TryFrom<FontSizeRepr> for FontSize {
+    type Error = ParseFontSizeError;
+
+    fn try_from(value: FontSizeRepr) -> Result<Self, Self::Error> {
+        match value {
+            FontSizeRepr::Number(v) => Ok(Self::Pixels(v)),
+            FontSizeRepr::String(s) => Self::new_from_str(&s),
+        }
+    }
+}
+
+impl From<FontSize> for FontSizeRepr {
+    fn from(value: FontSize) -> Self {
+        match value {
+            FontSize::Pixels(v) => Self::Number(v),
+            size => Self::String(size.to_string()),
+        }
+    }
+}
+
+/// The chrome around each kind of widget.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThemeConfig {
+    #[serde(default = "ChromeTheme::hover")]
+    pub hover: ChromeTheme,
+    #[serde(default = "ChromeTheme::float")]
+    pub diagnostics: ChromeTheme,
+    #[serde(default = "ChromeTheme::float")]
+    pub notify: ChromeTheme,
+}
+
+/// A color, or a highlight group to take the color from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ColorSource {
+    /// `#rrggbb`, `#rrggbbaa`, a color name or an integer.
+    Color(Color),
+    /// A highlight group, e.g. `NormalFloat`. Its background is used for
+    /// backgrounds, and its foreground for borders.
+    Highlight(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChromeTheme {
+    pub background: ColorSource,
+    pub border: BorderTheme,
+    /// Padding between the border and the content, in pixels.
+    pub padding: f32,
+    #[serde(default)]
+    pub shadow: Option<ShadowTheme>,
+    /// Opacity of the background, the content is always opaque.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BorderTheme {
+    pub width: f32,
+    pub color: ColorSource,
+    #[serde(default)]
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowTheme {
+    pub color: ColorSource,
+    /// The sigma of the blur, in pixels.
+    #[serde(default)]
+    pub blur: f32,
+    #[serde(default)]
+    pub x_offset: f32,
+    #[serde(default)]
+    pub y_offset: f32,
+}
+
+impl ChromeTheme {
+    /// The chrome hovers have always had.
+    pub fn hover() -> Self {
+        Self {
+            background: ColorSource::Highlight("Normal".to_string()),
+            border: BorderTheme {
+                width: 1.0,
+                color: ColorSource::Color(Color::new(0)),
+                radius: 0.0,
+            },
+            padding: 8.0,
+            shadow: None,
+            opacity: default_opacity(),
+        }
+    }
+
+    /// Looks like the floating windows of Neovim.
+    pub fn float() -> Self {
+        Self {
+            background: ColorSource::Highlight("NormalFloat".to_string()),
+            border: BorderTheme {
+                width: 1.0,
+                color: ColorSource::Highlight("FloatBorder".to_string()),
+                radius: 0.0,
+            },
+            ..Self::hover()
+        }
+    }
+
+    fn validate(&self, issues: &mut Vec<ConfigIssue>, path: &str) {
+        let path = |key: &str| join_path(path, key);
+        check_range(issues, &path("padding"), self.padding, 0.0..=128.0);
+        check_range(issues, &path("opacity"), self.opacity, 0.0..=1.0);
+        check_range(
+            issues,
+            &path("border.width"),
+            self.border.width,
+            0.0..=32.0,
+        );
+        check_range(
+            issues,
+            &path("border.radius"),
+            self.border.radius,
+            0.0..=64.0,
+        );
+        if let Some(shadow) = &self.shadow {
+            check_range(issues, &path("shadow.blur"), shadow.blur, 0.0..=64.0);
+            let offsets = -64.0..=64.0;
+            check_range(
+                issues,
+                &path("shadow.x_offset"),
+                shadow.x_offset,
+                offsets.clone(),
+            );
+            check_range(
+                issues,
+                &path("shadow.y_offset"),
+                shadow.y_offset,
+                offsets,
+            );
+        }
+    }
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        Self {
+            hover: ChromeTheme::hover(),
+            diagnostics: ChromeTheme::float(),
+            notify: ChromeTheme::float(),
         }
     }
 }
 
+fn default_opacity() -> f32 {
+    1.0
+}
+
 fn default_window_size_width() -> f32 {
     1000.0
 }
@@ -68,7 +352,7 @@ fn default_x_offset() -> i32 {
 }
 
 fn default_y_offset() -> i32 {
-    1
+    0
 }
 
 fn default_hover_window_size() -> WindowConfig {
@@ -76,10 +360,15 @@ fn default_hover_window_size() -> WindowConfig {
         max_width: 1000.0,
         max_height: 1000.0,
         x_offset: 0,
-        y_offset: 1,
+        y_offset: 0,
+        placement: PlacementConfig::default(),
     }
 }
 
+fn default_max_cache_bytes() -> usize {
+    DEFAULT_MAX_CACHE_BYTES
+}
+
 fn default_normal_font() -> Vec<String> {
     vec!["LXGW WenKai".to_string()]
 }
@@ -88,9 +377,342 @@ fn default_mono_font() -> Vec<String> {
     vec!["MonoLisa".to_string()]
 }
 
-fn default_font_size() -> f32 {
-    20.0
+fn default_font_size() -> FontSize {
+    FontSize::Pixels(20.0)
+}
+
+/// A problem with one key of the config.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{path}: {message}")]
+pub struct ConfigIssue {
+    /// Dotted path of the key, e.g. `hover.window.max_width`.
+    pub path: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Why a config update is rejected, the current config is kept.
+#[derive(Debug, Clone, thiserror::Error)]
+pub struct ConfigErrors(pub Vec<ConfigIssue>);
+
+impl Display for ConfigErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "external-widget: invalid config")?;
+        for issue in &self.0 {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// A merged config, with keys that were ignored.
+#[derive(Debug)]
+pub struct ConfigUpdate {
+    pub config: ExtWidgetConfig,
+    pub unknown_keys: Vec<ConfigIssue>,
+}
+
+impl ExtWidgetConfig {
+    /**
+     * Merge a partial config into this one. Keys missing in `patch` keep
+     * their current values, unknown keys are ignored and reported.
+     *
+     * The merged config is validated as a whole, nothing is changed on
+     * errors.
+     */
+    pub fn merge(&self, patch: Value) -> Result<ConfigUpdate, ConfigErrors> {
+        let mut merged = serde_json::to_value(self).map_err(|e| {
+            ConfigErrors(vec![ConfigIssue::new("", e.to_string())])
+        })?;
+        let mut unknown_keys = Vec::new();
+        merge_value(&mut merged, patch, "", &mut unknown_keys);
+
+        let config: Self =
+            serde_path_to_error::deserialize(merged).map_err(|e| {
+                let issue = ConfigIssue::new(
+                    e.path().to_string(),
+                    e.inner().to_string(),
+                );
+                ConfigErrors(vec![issue])
+            })?;
+        let issues = config.validate();
+        if !issues.is_empty() {
+            return Err(ConfigErrors(issues));
+        }
+        Ok(ConfigUpdate {
+            config,
+            unknown_keys,
+        })
+    }
+
+    /// Check values are in their ranges.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let hover = &self.hover;
+        check_fonts(&mut issues, "hover.normal_font", &hover.normal_font);
+        check_fonts(&mut issues, "hover.mono_font", &hover.mono_font);
+        if let Scale::Fixed(scale) = self.scale {
+            check_range(&mut issues, "scale", scale, 0.25..=8.0);
+        }
+        hover
+            .normal_font_size
+            .validate(&mut issues, "hover.normal_font_size");
+        hover
+            .mono_font_size
+            .validate(&mut issues, "hover.mono_font_size");
+
+        let window = &hover.window;
+        let sizes = 16.0..=16384.0;
+        check_range(
+            &mut issues,
+            "hover.window.max_width",
+            window.max_width,
+            sizes.clone(),
+        );
+        check_range(
+            &mut issues,
+            "hover.window.max_height",
+            window.max_height,
+            sizes,
+        );
+        // in cells
+        let offsets = -1000..=1000;
+        check_range(
+            &mut issues,
+            "hover.window.x_offset",
+            window.x_offset,
+            offsets.clone(),
+        );
+        check_range(
+            &mut issues,
+            "hover.window.y_offset",
+            window.y_offset,
+            offsets,
+        );
+
+        if self.image.max_cache_bytes == 0 {
+            issues.push(ConfigIssue::new(
+                "image.max_cache_bytes",
+                "should be greater than 0",
+            ));
+        }
+        let theme = &self.theme;
+        theme.hover.validate(&mut issues, "theme.hover");
+        theme.diagnostics.validate(&mut issues, "theme.diagnostics");
+        theme.notify.validate(&mut issues, "theme.notify");
+
+        if let Some(level) = &self.log.level {
+            if let Err(e) = EnvFilter::try_new(level) {
+                issues.push(ConfigIssue::new("log.level", e.to_string()));
+            }
+        }
+        issues
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
 }
 
-pub static CONFIG: Lazy<Mutex<ExtWidgetConfig>> =
-    Lazy::new(|| Mutex::new(ExtWidgetConfig::default()));
+/// Merge `patch` into `base` recursively, tables are merged key by key,
+/// other values are replaced.
+fn merge_value(
+    base: &mut Value, patch: Value, path: &str, unknown: &mut Vec<ConfigIssue>,
+) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                let path = join_path(path, &key);
+                match base.get_mut(&key) {
+                    Some(base) => merge_value(base, value, &path, unknown),
+                    None => unknown.push(ConfigIssue::new(path, "unknown key")),
+                }
+            }
+        }
+        // an empty Lua table is sent as an empty array
+        (Value::Object(_), Value::Array(patch)) if patch.is_empty() => {}
+        (base, patch) => *base = patch,
+    }
+}
+
+fn check_fonts(issues: &mut Vec<ConfigIssue>, path: &str, fonts: &[String]) {
+    if fonts.is_empty() {
+        issues.push(ConfigIssue::new(path, "should not be empty"));
+    }
+}
+
+fn check_range<T>(
+    issues: &mut Vec<ConfigIssue>, path: &str, value: T,
+    range: RangeInclusive<T>,
+) where
+    T: PartialOrd + Display,
+{
+    // NaN is never contained
+    if !range.contains(&value) {
+        issues.push(ConfigIssue::new(
+            path,
+            format!(
+                "{} is out of range [{}, {}]",
+                value,
+                range.start(),
+                range.end()
+            ),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::placement::{Anchor, Side};
+
+    use super::*;
+
+    #[test]
+    fn test_merge_partial_config() {
+        let config = ExtWidgetConfig::default();
+        let update = config
+            .merge(json!({
+                "hover": {
+                    "window": {
+                        "y_offset": 2,
+                        "placement": { "sides": ["above", "below"] },
+                    },
+                    "mono_fnot_size": 14,
+                },
+                "log": [],
+            }))
+            .unwrap();
+        let window = &update.config.hover.window;
+        assert_eq!(window.y_offset, 2);
+        assert_eq!(window.max_width, 1000.0);
+        assert_eq!(window.placement.sides, [Side::Above, Side::Below]);
+        assert_eq!(window.placement.anchor, Anchor::Cursor);
+        assert_eq!(update.config.hover.mono_font_size, FontSize::Pixels(20.0));
+        assert_eq!(
+            update.unknown_keys,
+            vec![ConfigIssue::new("hover.mono_fnot_size", "unknown key")]
+        );
+
+        // merged into the current config, not the default
+        let update = update
+            .config
+            .merge(json!({ "hover": { "normal_font_size": 16 } }))
+            .unwrap();
+        assert_eq!(update.config.hover.window.y_offset, 2);
+        assert_eq!(
+            update.config.hover.normal_font_size,
+            FontSize::Pixels(16.0)
+        );
+    }
+
+    #[test]
+    fn test_merge_errors() {
+        let config = ExtWidgetConfig::default();
+        let errors = config
+            .merge(json!({ "hover": { "window": { "max_width": "wide" } } }))
+            .unwrap_err();
+        assert_eq!(errors.0.len(), 1);
+        assert_eq!(errors.0[0].path, "hover.window.max_width");
+
+        let errors = config
+            .merge(json!({
+                "hover": { "normal_font_size": 0, "mono_font": [] },
+                "image": { "max_cache_bytes": 0 },
+            }))
+            .unwrap_err();
+        let paths: Vec<_> = errors.0.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "hover.mono_font",
+                "hover.normal_font_size",
+                "image.max_cache_bytes"
+            ]
+        );
+        assert!(errors.to_string().contains("hover.normal_font_size: 0 is"));
+    }
+
+    #[test]
+    fn test_font_size_and_scale() {
+        let config = ExtWidgetConfig::default();
+        assert_eq!(config.scale, Scale::Fixed(1.0));
+        let update = config
+            .merge(json!({
+                "scale": 2,
+                "hover": {
+                    "normal_font_size": "12pt",
+                    "mono_font_size": "1 cell",
+                },
+            }))
+            .unwrap();
+        assert_eq!(update.config.scale, Scale::Fixed(2.0));
+        let hover = &update.config.hover;
+        assert_eq!(hover.normal_font_size, FontSize::Points(12.0));
+        assert_eq!(hover.mono_font_size, FontSize::Cells(1.0));
+
+        let update = update.config.merge(json!({ "scale": "auto" })).unwrap();
+        assert_eq!(update.config.scale, Scale::Auto);
+        // still in points after the round trip of merging
+        let hover = &update.config.hover;
+        assert_eq!(hover.normal_font_size, FontSize::Points(12.0));
+
+        let errors = config
+            .merge(json!({ "hover": { "normal_font_size": "12em" } }))
+            .unwrap_err();
+        assert_eq!(errors.0[0].path, "hover.normal_font_size");
+    }
+
+    #[test]
+    fn test_merge_theme() {
+        let config = ExtWidgetConfig::default();
+        let update = config
+            .merge(json!({
+                "theme": {
+                    "hover": {
+                        "background": "#1a1b26",
+                        "border": { "color": "FloatBorder", "radius": 4 },
+                        "shadow": { "color": "#00000080", "blur": 4 },
+                    },
+                },
+            }))
+            .unwrap();
+        let hover = &update.config.theme.hover;
+        assert_eq!(hover.background, ColorSource::Color(Color::new(0x1a1b26)));
+        assert_eq!(
+            hover.border.color,
+            ColorSource::Highlight("FloatBorder".to_string())
+        );
+        assert_eq!(hover.border.width, 1.0);
+        assert_eq!(hover.shadow.as_ref().unwrap().blur, 4.0);
+
+        let errors = config
+            .merge(json!({ "theme": { "hover": { "opacity": 1.5 } } }))
+            .unwrap_err();
+        assert_eq!(errors.0[0].path, "theme.hover.opacity");
+
+        // the other kinds look like floats, and are validated the same way
+        let diagnostics = &update.config.theme.diagnostics;
+        assert_eq!(
+            diagnostics.background,
+            ColorSource::Highlight("NormalFloat".to_string())
+        );
+        let errors = config
+            .merge(json!({ "theme": { "notify": { "padding": -1 } } }))
+            .unwrap_err();
+        assert_eq!(errors.0[0].path, "theme.notify.padding");
+    }
+}
@@ -5,9 +5,13 @@ use nvim_rs::{Handler, Neovim};
 use rmpv::Value;
 use tracing::{info, instrument, warn};
 
+use crate::protocol::unknown_method_error;
+
 use super::{
     handlers::{
-        ConfigNotify, ScrollDownHoverNotification, StartHoverReq, StopHoverReq,
+        ClearImagesReq, ConfigNotify, HealthReq, HelloReq,
+        LayoutChangedNotification, ListImagesReq, ScrollDownHoverNotification,
+        ScrollUpHoverNotification, SetLogLevelReq, StartHoverReq, StopHoverReq,
     },
     NeovimSession, NvimWriter,
 };
@@ -28,6 +32,12 @@ impl NeovimHandler {
 
         req_handlers.insert("start_hover".to_string(), Box::new(StartHoverReq));
         req_handlers.insert("stop_hover".to_string(), Box::new(StopHoverReq));
+        req_handlers.insert("list_images".to_string(), Box::new(ListImagesReq));
+        req_handlers
+            .insert("clear_images".to_string(), Box::new(ClearImagesReq));
+        req_handlers.insert("health".to_string(), Box::new(HealthReq));
+        req_handlers
+            .insert("set_log_level".to_string(), Box::new(SetLogLevelReq));
 
         noti_handlers
             .insert("update_config".to_string(), Box::new(ConfigNotify));
@@ -37,9 +47,23 @@ impl NeovimHandler {
         );
         noti_handlers.insert(
             "scroll_up_hover".to_string(),
-            Box::new(ScrollDownHoverNotification),
+            Box::new(ScrollUpHoverNotification),
+        );
+        noti_handlers.insert(
+            "layout_changed".to_string(),
+            Box::new(LayoutChangedNotification),
         );
 
+        let hello = HelloReq {
+            methods: req_handlers
+                .keys()
+                .cloned()
+                .chain(["hello".to_string()])
+                .collect(),
+            notifications: noti_handlers.keys().cloned().collect(),
+        };
+        req_handlers.insert("hello".to_string(), Box::new(hello));
+
         Self {
             session: Arc::new(NeovimSession::new()),
             req_handlers: Arc::new(req_handlers),
@@ -47,6 +71,10 @@ impl NeovimHandler {
         }
     }
 
+    pub fn session(&self) -> Arc<NeovimSession> {
+        self.session.clone()
+    }
+
     #[instrument(skip(self, nvim))]
     pub async fn post_instance(
         &self, nvim: &Neovim<NvimWriter>,
@@ -85,7 +113,7 @@ impl Handler for NeovimHandler {
             Some(handler) => {
                 handler.call(name, args, neovim, self.session.clone()).await
             }
-            None => Err(Value::from(format!("Unknown request: {}", name))),
+            None => Err(Value::from(unknown_method_error(&name))),
         }
     }
 
@@ -103,7 +131,21 @@ impl Handler for NeovimHandler {
                     warn!("Error: {}", err);
                 }
             }
-            None => warn!("Unknown notify: {}", name),
+            None => warn!("{}", unknown_method_error(&name)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::NeovimHandler;
+
+    #[test]
+    fn test_notification_handlers() {
+        let handler = NeovimHandler::new();
+        let name =
+            |method: &str| format!("{:?}", handler.noti_handlers[method]);
+        assert_eq!(name("scroll_down_hover"), "ScrollDownHoverNotification");
+        assert_eq!(name("scroll_up_hover"), "ScrollUpHoverNotification");
+    }
+}
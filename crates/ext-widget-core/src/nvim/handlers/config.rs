@@ -3,33 +3,60 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use nvim_rs::Neovim;
 use rmpv::{ext::from_value, Value};
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::nvim::{
-    handler::NeovimService, ExtWidgetConfig, NeovimSession, NvimWriter, CONFIG,
-};
+use crate::nvim::{handler::NeovimService, NeovimSession, NvimWriter};
+
+/// Expect name: "update_config", args: [partial_config]
+///
+/// Errors are written with `nvim_err_writeln` as well, nobody sees the
+/// result of a notification.
+async fn process_update_config(
+    args: Vec<Value>, nvim: &Neovim<NvimWriter>, session: &NeovimSession,
+) -> Result<(), String> {
+    if args.len() != 1 {
+        return Err(format!(
+            "config_notify expects 1 argument, got {}",
+            args.len()
+        ));
+    }
+    let patch: serde_json::Value = from_value(args[0].clone())
+        .map_err(|e| format!("Deserialize config failed: {}", e))?;
+    match session.update_config(patch) {
+        Ok(unknown_keys) => {
+            for issue in unknown_keys {
+                let msg = format!("external-widget: ignored config {}", issue);
+                warn!("{}", msg);
+                // `vim.log.levels.WARN`
+                if let Err(e) = nvim.notify(&msg, 3, vec![]).await {
+                    warn!("Failed to notify: {}", e);
+                }
+            }
+            Ok(())
+        }
+        Err(errors) => {
+            let msg = errors.to_string();
+            if let Err(e) = nvim.err_writeln(&msg).await {
+                warn!("Failed to write error: {}", e);
+            }
+            Err(msg)
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct ConfigNotify;
 
 #[async_trait]
 impl NeovimService for ConfigNotify {
-    #[instrument(skip(self, _neovim, _session))]
+    #[instrument(skip(self, neovim, session))]
     async fn call(
-        &self, _name: String, args: Vec<Value>, _neovim: Neovim<NvimWriter>,
-        _session: Arc<NeovimSession>,
+        &self, _name: String, args: Vec<Value>, neovim: Neovim<NvimWriter>,
+        session: Arc<NeovimSession>,
     ) -> Result<Value, Value> {
-        if args.len() != 1 {
-            return Err(Value::from(format!(
-                "config_notify expects 1 argument, got {}",
-                args.len()
-            )));
-        }
-        let new_config: ExtWidgetConfig =
-            from_value(args[0].clone()).map_err(|e| {
-                Value::from(format!("Deserialize config failed: {}", e))
-            })?;
-        *CONFIG.lock() = new_config;
+        process_update_config(args, &neovim, &session)
+            .await
+            .map_err(Value::from)?;
         Ok(Value::from(true))
     }
 }
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use nvim_rs::Neovim;
+use rmpv::Value;
+use tracing::instrument;
+
+use crate::{
+    doctor::{run_checks, DoctorOptions},
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter},
+};
+
+/// Expect name: "health"
+async fn process_req_health(
+    nvim: &Neovim<NvimWriter>, session: &NeovimSession,
+) -> anyhow::Result<Value> {
+    let runtime_paths = NeovimSession::runtime_paths(nvim).await?;
+    let tty = NeovimSession::get_tty(nvim)
+        .await
+        .ok()
+        .filter(|tty| !tty.is_empty());
+    let opts = {
+        let config = session.config.lock();
+        DoctorOptions {
+            normal_font: config.hover.normal_font.clone(),
+            mono_font: config.hover.mono_font.clone(),
+            runtime_paths,
+            tty,
+        }
+    };
+    let results = run_checks(&opts).await;
+    Ok(rmpv::ext::to_value(results)?)
+}
+
+#[derive(Debug)]
+pub(crate) struct HealthReq;
+
+#[async_trait]
+impl NeovimService for HealthReq {
+    #[instrument(skip(self, neovim, session))]
+    async fn call(
+        &self, _name: String, _args: Vec<Value>, neovim: Neovim<NvimWriter>,
+        session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        process_req_health(&neovim, &session)
+            .await
+            .map_err(|e| Value::from(e.to_string()))
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use nvim_rs::Neovim;
+use rmpv::{ext::from_value, Value};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter},
+    protocol::{Capabilities, HelloInfo, PROTOCOL_VERSION, VERSION},
+};
+
+/// What the Lua plugin sends in `hello`, read from the sources it ships
+/// with.
+#[derive(Debug, Deserialize)]
+struct ClientInfo {
+    version: String,
+    protocol: u32,
+}
+
+impl ClientInfo {
+    /// None if the plugin can't tell its version.
+    fn from_args(args: Vec<Value>) -> anyhow::Result<Option<Self>> {
+        match args.into_iter().next() {
+            Some(info) if !info.is_nil() => Ok(Some(from_value(info)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn matches_server(&self) -> bool {
+        self.protocol == PROTOCOL_VERSION && self.version == VERSION
+    }
+}
+
+/// Expect name: "hello", args: [client_info]?
+async fn process_req_hello(
+    req: &HelloReq, args: Vec<Value>, nvim: &Neovim<NvimWriter>,
+) -> anyhow::Result<Value> {
+    if let Some(client) = ClientInfo::from_args(args)? {
+        if !client.matches_server() {
+            warn!(
+                "Client {} (protocol {}) differs from server {} (protocol {})",
+                client.version, client.protocol, VERSION, PROTOCOL_VERSION
+            );
+        }
+    }
+    let term_size = NeovimSession::get_term_size(nvim).await.ok();
+    let capabilities = Capabilities::detect(term_size.as_ref()).await;
+    Ok(rmpv::ext::to_value(req.info(capabilities))?)
+}
+
+#[derive(Debug)]
+pub(crate) struct HelloReq {
+    pub methods: Vec<String>,
+    pub notifications: Vec<String>,
+}
+
+impl HelloReq {
+    fn info(&self, capabilities: Capabilities) -> HelloInfo {
+        HelloInfo::new(
+            self.methods.clone(),
+            self.notifications.clone(),
+            capabilities,
+        )
+    }
+}
+
+#[async_trait]
+impl NeovimService for HelloReq {
+    #[instrument(skip(self, neovim, _session))]
+    async fn call(
+        &self, _name: String, args: Vec<Value>, neovim: Neovim<NvimWriter>,
+        _session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        process_req_hello(self, args, &neovim)
+            .await
+            .map_err(|e| Value::from(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rmpv::Value;
+
+    use crate::protocol::{Capabilities, PROTOCOL_VERSION, VERSION};
+
+    use super::{ClientInfo, HelloReq};
+
+    fn client_args(version: &str, protocol: u32) -> Vec<Value> {
+        vec![Value::Map(vec![
+            (Value::from("version"), Value::from(version)),
+            (Value::from("protocol"), Value::from(protocol)),
+        ])]
+    }
+
+    #[test]
+    fn test_client_info() {
+        let client = ClientInfo::from_args(client_args(VERSION, 1))
+            .unwrap()
+            .unwrap();
+        assert!(client.matches_server());
+        let client = ClientInfo::from_args(client_args("0.0.0", 1))
+            .unwrap()
+            .unwrap();
+        assert!(!client.matches_server());
+        let args = client_args(VERSION, PROTOCOL_VERSION + 1);
+        let client = ClientInfo::from_args(args).unwrap().unwrap();
+        assert!(!client.matches_server());
+
+        // a plugin without its sources sends nothing, or nil
+        assert!(ClientInfo::from_args(vec![]).unwrap().is_none());
+        assert!(ClientInfo::from_args(vec![Value::Nil]).unwrap().is_none());
+        assert!(ClientInfo::from_args(vec![Value::from(1)]).is_err());
+    }
+
+    #[test]
+    fn test_hello_req_info() {
+        let req = HelloReq {
+            methods: vec!["stop_hover".to_string(), "hello".to_string()],
+            notifications: vec![
+                "update_config".to_string(),
+                "layout_changed".to_string(),
+            ],
+        };
+        let info = req.info(Capabilities::default());
+        assert_eq!(info.version, VERSION);
+        assert_eq!(info.protocol, PROTOCOL_VERSION);
+        assert_eq!(info.methods, ["hello", "stop_hover"]);
+        assert_eq!(info.notifications, ["layout_changed", "update_config"]);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, num::NonZeroU32, rc::Rc, sync::Arc};
+use std::{num::NonZeroU32, sync::Arc};
 
 use anyhow::{bail, Context};
 use async_trait::async_trait;
@@ -8,132 +8,78 @@ use rmpv::Value;
 use tracing::{info, instrument, warn};
 
 use crate::{
-    nvim::{handler::NeovimService, NeovimSession, NvimWriter, CONFIG},
-    painting::{BoxBorder, BoxDecoration, Color, Padding, RectSize, Renderer},
-    term::{
-        image::{Image, ImageManager, IMAGE_MANAGER},
-        TermSizeInfo,
-    },
-    widgets::{BoxOptions, Container, MarkdownDocumentBuilder, WidgetTree},
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter, WindowConfig},
+    painting::RectSize,
+    placement::{CellArea, ImagePlacement, PlacementContext},
+    render::{DisplayMetrics, HoverWidget},
+    term::TermSizeInfo,
+    widgets::NeovimProvider,
 };
 
+use super::{add_image_set, layout::fetch_placement_context};
+
+/// Render `md` as the live widget `id`, as wide as the room around the
+/// cursor allows.
 async fn build_hover_doc_image<W>(
-    nvim: Neovim<W>, session: Arc<NeovimSession>, md: &str,
+    nvim: Neovim<W>, session: Arc<NeovimSession>, id: NonZeroU32, md: String,
+    term_size: &TermSizeInfo, ctx: &PlacementContext,
 ) -> anyhow::Result<(Vec<Vec<u8>>, RectSize<f32>)>
 where
     W: AsyncWrite + Send + Unpin + 'static,
 {
-    let (md_widget_builder, width, height) = {
-        let cfg = CONFIG.lock();
-        let md_widget_builder = MarkdownDocumentBuilder {
-            nvim: nvim.clone(),
-            session: session.clone(),
-            normal_font: cfg.hover.normal_font.clone(),
-            normal_font_size: cfg.hover.normal_font_size,
-            mono_font: cfg.hover.mono_font.clone(),
-            mono_font_size: cfg.hover.mono_font_size,
-        };
-        (
-            md_widget_builder,
-            cfg.hover.window.max_width,
-            cfg.hover.window.max_height,
-        )
+    let (cfg, theme, scale) = {
+        let config = session.config.lock();
+        (config.hover.clone(), config.theme.hover.clone(), config.scale)
     };
-    let background_color = session
-        .get_highlight_info(&nvim, "Normal")
-        .await
-        .unwrap_or_default();
-    let widget = md_widget_builder.build(md).await?;
-    let background_color = background_color
-        .guibg
-        .unwrap_or_else(|| background_color.bg.unwrap_or_default());
-    let container = Container::new_with_child(
-        BoxDecoration {
-            color: background_color,
-            border: BoxBorder {
-                width: 1.0,
-                color: Color::new(0),
-                radius: 0.0.into(),
-            },
-        },
-        BoxOptions {
-            padding: Padding::all(8.0.into()),
-            ..Default::default()
-        },
-        widget,
-    );
-
-    let mut widget_tree = WidgetTree::new();
-    widget_tree.new_root(Rc::new(container))?;
-    widget_tree.compute_layout(width, height)?;
-    let image_size = widget_tree.result_size()?;
-
-    let renderer = Rc::new(RefCell::new(Renderer::new(
-        width.ceil() as u32,
-        image_size.height.ceil() as u32,
-    )?));
-    widget_tree.paint(renderer.clone())?;
-
-    let renderer = renderer.as_ref();
-    let data = renderer.borrow_mut().snapshot_png_raw_with_steps(
-        image_size.width,
-        image_size.height,
-        height,
-        200.,
-    )?;
-
-    Ok((data, image_size))
-}
-
-#[instrument(skip(nvim))]
-async fn image_offset_to_term(
-    nvim: &Neovim<NvimWriter>, image_size: RectSize<f32>, offset: (i32, i32),
-) -> anyhow::Result<(u32, u32)> {
-    let term_size = NeovimSession::get_term_size(nvim).await?;
-    let term_size = TermSizeInfo::new_from_nvim_term(term_size);
-    let image_cell_width =
-        (image_size.width / term_size.cell_width).ceil() as i32;
-    let image_cell_height =
-        (image_size.height / term_size.cell_height).ceil() as i32;
-    let (cur_row, cur_col) =
-        NeovimSession::cursor_position_to_client(nvim).await?;
-    let x_offset = if cur_col + offset.0 + image_cell_width > term_size.cols {
-        term_size.cols - image_cell_width
-    } else {
-        cur_col + offset.0
+    let metrics = DisplayMetrics::new(scale, term_size);
+    let max_width =
+        metrics.width_of_cells(ctx.bounds(&cfg.window.placement).width);
+    let provider = NeovimProvider {
+        nvim,
+        session: session.clone(),
     };
-    let y_offset = if cur_row + offset.1 + image_cell_height > term_size.rows {
-        term_size.rows - image_cell_height
-    } else {
-        cur_row + offset.1
+    let build = move || async move {
+        HoverWidget::build(provider, &md, &cfg, &theme, metrics).await
     };
+    session.widgets.render(id, build, metrics, max_width).await
+}
+
+#[instrument]
+fn place_image(
+    ctx: &PlacementContext, term_size: &TermSizeInfo,
+    image_size: RectSize<f32>, window: &WindowConfig,
+) -> ImagePlacement {
+    let offset = (window.x_offset, window.y_offset);
+    let placement = ImagePlacement::new(
+        ctx,
+        window.placement.clone(),
+        offset,
+        term_size,
+        image_size,
+    );
     info!(
-        "cursor: (r:{}, c:{}), image: (w:{}, h:{}), offset: (x:{}, y:{})",
-        cur_row,
-        cur_col,
-        image_cell_width,
-        image_cell_height,
-        x_offset,
-        y_offset
+        "cursor: {:?}, image: {:?}, area: {:?}",
+        ctx.cursor, image_size, placement.area
     );
-    Ok((
-        (x_offset as f32 * term_size.cell_width) as u32,
-        (y_offset as f32 * term_size.cell_height) as u32,
-    ))
+    placement
 }
 
-async fn open_dummy_window(nvim: Neovim<NvimWriter>) -> anyhow::Result<()> {
+/// Open the window under the image set `id`, it covers the same cells so no
+/// text shows through the image.
+async fn open_dummy_window(
+    nvim: Neovim<NvimWriter>, id: NonZeroU32, area: CellArea,
+) -> anyhow::Result<()> {
     let buf = nvim.create_buf(false, true).await?;
     let win = nvim
         .open_win(
             &buf,
             true,
             vec![
-                ("relative".into(), "cursor".into()),
-                ("row".into(), 1.into()),
-                ("col".into(), 1.into()),
-                ("width".into(), 1.into()),
-                ("height".into(), 1.into()),
+                ("relative".into(), "editor".into()),
+                ("row".into(), area.row.into()),
+                ("col".into(), area.col.into()),
+                ("width".into(), area.width.max(1).into()),
+                ("height".into(), area.height.max(1).into()),
                 ("style".into(), "minimal".into()),
                 ("zindex".into(), 1.into()),
             ],
@@ -146,7 +92,7 @@ async fn open_dummy_window(nvim: Neovim<NvimWriter>) -> anyhow::Result<()> {
     end
     setup(...)
     "#,
-        vec![win.into_val(), buf.into_val()],
+        vec![win.into_val(), buf.into_val(), id.get().into()],
     )
     .await?;
 
@@ -167,36 +113,41 @@ async fn process_req_start_hover(
     if md.is_empty() {
         bail!("hover expects non-empty markdown");
     }
-    let id = ImageManager::alloc_set_id();
+    let id = session.images.lock().alloc_set_id();
+    let term_size = NeovimSession::get_term_size(&nvim).await?;
+    let term_size = TermSizeInfo::new_from_nvim_term(term_size);
+    let ctx = fetch_placement_context(&nvim, 0)
+        .await?
+        .context("The cursor is not visible")?;
     tokio::spawn(async move {
         let st = std::time::Instant::now();
-        let images =
-            build_hover_doc_image(nvim.clone(), session.clone(), &md).await;
+        let images = build_hover_doc_image(
+            nvim.clone(),
+            session.clone(),
+            id,
+            md,
+            &term_size,
+            &ctx,
+        )
+        .await;
         let ed = std::time::Instant::now();
         info!("build hover doc image cost: {:?}", (ed - st).as_millis());
         match images {
             Ok((images, image_size)) => {
-                let images = images
-                    .into_iter()
-                    .map(|image| Arc::new(Image::new_from_buffer(image)))
-                    .collect::<Vec<_>>();
-                let image_set = IMAGE_MANAGER
-                    .lock()
-                    .new_image_set_with_id(id, images)
-                    .unwrap();
-                let (x, y) = {
-                    let cfg = CONFIG.lock();
-                    (cfg.hover.window.x_offset, cfg.hover.window.y_offset)
-                };
-                let (x, y) = image_offset_to_term(&nvim, image_size, (x, y))
-                    .await
-                    .unwrap();
+                let image_set =
+                    add_image_set(&session, id, images).await.unwrap();
+                let window = session.config.lock().hover.window.clone();
+                let placement =
+                    place_image(&ctx, &term_size, image_size, &window);
+                let area = placement.area.unwrap();
+                session.placements.lock().insert(id, placement);
+                let (x, y) = area.pixel_position(&term_size);
                 let writer = session.get_tty_writer(&nvim).await.unwrap();
                 let mut writer = writer.lock().await;
                 image_set.render_at(&mut writer, x, y).await.unwrap();
 
                 // create the placeholder window
-                open_dummy_window(nvim).await.unwrap();
+                open_dummy_window(nvim, id, area).await.unwrap();
             }
             Err(err) => {
                 warn!("Error building hover doc image: {}", err);
@@ -225,7 +176,9 @@ async fn process_req_stop_hover(
     let id =
         NonZeroU32::try_from(args[0].as_u64().context("Expect u64")? as u32)?;
     tokio::spawn(async move {
-        let image = IMAGE_MANAGER.lock().find_image_set(id);
+        session.placements.lock().remove(&id);
+        session.widgets.remove(id);
+        let image = session.images.lock().remove_image_set(id);
         if let Some(image) = image {
             let writer = session.get_tty_writer(&nvim).await.unwrap();
             let mut writer = writer.lock().await;
@@ -252,7 +205,7 @@ async fn process_req_scroll_down(
     let id =
         NonZeroU32::try_from(args[0].as_u64().context("Expect u64")? as u32)?;
     tokio::spawn(async move {
-        let image = IMAGE_MANAGER.lock().find_image_set(id);
+        let image = session.images.lock().find_image_set(id);
         if let Some(image) = image {
             let writer = session.get_tty_writer(&nvim).await.unwrap();
             let mut writer = writer.lock().await;
@@ -279,7 +232,7 @@ async fn process_req_scroll_up(
     let id =
         NonZeroU32::try_from(args[0].as_u64().context("Expect u64")? as u32)?;
     tokio::spawn(async move {
-        let image = IMAGE_MANAGER.lock().find_image_set(id);
+        let image = session.images.lock().find_image_set(id);
         if let Some(image) = image {
             let writer = session.get_tty_writer(&nvim).await.unwrap();
             let mut writer = writer.lock().await;
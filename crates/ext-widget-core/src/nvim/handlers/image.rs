@@ -0,0 +1,100 @@
+use std::{num::NonZeroU32, sync::Arc};
+
+use async_trait::async_trait;
+use nvim_rs::Neovim;
+use rmpv::Value;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter},
+    term::image::ImageSet,
+};
+
+/// Delete image sets from the terminal, errors are only logged.
+pub(crate) async fn delete_image_sets(
+    session: &NeovimSession, image_sets: Vec<Arc<ImageSet>>,
+) {
+    if image_sets.is_empty() {
+        return;
+    }
+    {
+        let mut placements = session.placements.lock();
+        for image_set in &image_sets {
+            placements.remove(&image_set.id());
+            session.widgets.remove(image_set.id());
+        }
+    }
+    let Some(writer) = session.tty_writer() else {
+        return;
+    };
+    let mut writer = writer.lock().await;
+    for image_set in image_sets {
+        if let Err(e) = image_set.delete_image(&mut writer, true).await {
+            warn!("Error deleting image set {}: {}", image_set.id(), e);
+        }
+    }
+}
+
+/// Add `images` as the image set `id` of the session. Image sets evicted from
+/// the cache are deleted from the terminal.
+pub async fn add_image_set(
+    session: &NeovimSession, id: NonZeroU32, images: Vec<Vec<u8>>,
+) -> anyhow::Result<Arc<ImageSet>> {
+    let (image_set, evicted) = {
+        let mut manager = session.images.lock();
+        let images = images
+            .into_iter()
+            .map(|image| manager.new_image(image))
+            .collect::<Vec<_>>();
+        let image_set = manager.new_image_set_with_id(id, images)?;
+        (image_set, manager.evict())
+    };
+    delete_image_sets(session, evicted).await;
+    Ok(image_set)
+}
+
+/// Remove all image sets of the session, and delete them from the terminal.
+/// Returns the number of removed image sets.
+#[instrument(skip(session))]
+pub async fn clear_session_images(session: &NeovimSession) -> usize {
+    let image_sets = session.images.lock().clear();
+    let count = image_sets.len();
+    info!("Clear {} image sets", count);
+    delete_image_sets(session, image_sets).await;
+    count
+}
+
+/// Expect name: "list_images"
+fn process_req_list_images(session: &NeovimSession) -> anyhow::Result<Value> {
+    let infos = session.images.lock().list_image_sets();
+    Ok(rmpv::ext::to_value(infos)?)
+}
+
+#[derive(Debug)]
+pub(crate) struct ListImagesReq;
+#[derive(Debug)]
+pub(crate) struct ClearImagesReq;
+
+#[async_trait]
+impl NeovimService for ListImagesReq {
+    #[instrument(skip(self, _neovim))]
+    async fn call(
+        &self, _name: String, _args: Vec<Value>, _neovim: Neovim<NvimWriter>,
+        session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        process_req_list_images(&session)
+            .map_err(|e| Value::from(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl NeovimService for ClearImagesReq {
+    #[instrument(skip(self, _neovim))]
+    async fn call(
+        &self, _name: String, _args: Vec<Value>, _neovim: Neovim<NvimWriter>,
+        session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        let count = clear_session_images(&session).await;
+        Ok(Value::from(count as u64))
+    }
+}
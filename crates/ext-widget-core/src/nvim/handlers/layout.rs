@@ -0,0 +1,478 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use nvim_rs::Neovim;
+use rmpv::{
+    ext::{from_value, to_value},
+    Value,
+};
+use serde::Serialize;
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter},
+    placement::{CellArea, PlacementContext},
+    render::{DisplayMetrics, Repaint},
+    term::TermSizeInfo,
+};
+
+use super::add_image_set;
+
+/// The placeholder window of the image set `id` should cover `area`, or be
+/// hidden if it's None.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlaceholderMove {
+    pub id: u32,
+    pub area: Option<CellArea>,
+}
+
+/// Windows the live image sets are anchored to.
+pub fn placement_anchors(session: &NeovimSession) -> Vec<i64> {
+    let mut wins: Vec<_> =
+        session.placements.lock().values().map(|p| p.win).collect();
+    wins.sort_unstable();
+    wins.dedup();
+    wins
+}
+
+/**
+ * Place all live image sets again. `contexts` is the layout around each
+ * window of [`placement_anchors`], None if the window is not visible.
+ *
+ * Widgets are laid out again if the cells or the room for them change, and
+ * their images are replaced. Widgets whose state changed are painted again,
+ * and only the damaged pixels of their images are sent. Moved images are
+ * rendered at the new position, and images whose anchor is not visible are
+ * hidden. Returns how the placeholder windows should follow.
+ */
+pub async fn reposition_image_sets(
+    session: &NeovimSession, term_size: &TermSizeInfo,
+    contexts: &HashMap<i64, Option<PlacementContext>>,
+) -> anyhow::Result<Vec<PlaceholderMove>> {
+    let Some(writer) = session.tty_writer() else {
+        return Ok(vec![]);
+    };
+    let metrics = DisplayMetrics::new(session.config.lock().scale, term_size);
+    let placements: Vec<_> = session
+        .placements
+        .lock()
+        .iter()
+        .map(|(id, placement)| (*id, placement.clone()))
+        .collect();
+    let mut moves = vec![];
+    for (id, mut placement) in placements {
+        let Some(mut image_set) = session.images.lock().get_image_set(id)
+        else {
+            session.placements.lock().remove(&id);
+            continue;
+        };
+        let ctx = contexts.get(&placement.win).and_then(Option::as_ref);
+
+        let mut update = None;
+        if let Some(ctx) = ctx {
+            let bounds = ctx.bounds(&placement.config);
+            let max_width = metrics.width_of_cells(bounds.width);
+            update = session.widgets.reflow(id, metrics, max_width).await?;
+        }
+        if update.is_none() {
+            match session.widgets.repaint(id).await? {
+                Some(Repaint::Resized(images, size)) => {
+                    update = Some((images, size));
+                }
+                Some(Repaint::Patches(patches)) => {
+                    let mut writer = writer.lock().await;
+                    for p in patches {
+                        image_set
+                            .patch(
+                                &mut writer,
+                                p.page,
+                                p.x,
+                                p.y,
+                                &p.patch,
+                                p.image,
+                            )
+                            .await?;
+                    }
+                }
+                Some(Repaint::Unchanged) | None => {}
+            }
+        }
+        let reflowed = update.is_some();
+        if let Some((images, size)) = update {
+            image_set.delete_image(&mut *writer.lock().await, true).await?;
+            image_set = add_image_set(session, id, images).await?;
+            placement.size = size;
+        }
+
+        let previous = placement.area;
+        let area = placement.update(ctx, term_size);
+        // the cell size changes with the font size of the terminal
+        let position = area.map(|area| area.pixel_position(term_size));
+        if !reflowed
+            && area == previous
+            && position == image_set.last_rendered_pos()
+        {
+            continue;
+        }
+        {
+            let mut writer = writer.lock().await;
+            match position {
+                Some((x, y)) => image_set.render_at(&mut writer, x, y).await?,
+                None => image_set.hide(&mut writer).await?,
+            }
+        }
+        // the image set may be stopped meanwhile
+        if let Some(live) = session.placements.lock().get_mut(&id) {
+            *live = placement;
+        }
+        moves.push(PlaceholderMove { id: id.get(), area });
+    }
+    Ok(moves)
+}
+
+/// Collect the layout around `win` (0 for the current window), None if the
+/// window or its cursor is not visible.
+pub(super) async fn fetch_placement_context(
+    nvim: &Neovim<NvimWriter>, win: i64,
+) -> anyhow::Result<Option<PlacementContext>> {
+    let ctx = nvim
+        .exec_lua(
+            r#"return require("external-widget.utils").placement_context(...)"#,
+            vec![win.into()],
+        )
+        .await?;
+    Ok(from_value(ctx)?)
+}
+
+async fn move_placeholders(
+    nvim: &Neovim<NvimWriter>, moves: &[PlaceholderMove],
+) -> anyhow::Result<()> {
+    nvim.exec_lua(
+        r#"require("external-widget.hover").move_placeholders(...)"#,
+        vec![to_value(moves)?],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Reposition the image sets of `session` and move their placeholders, see
+/// [`reposition_image_sets`].
+async fn relayout(
+    nvim: &Neovim<NvimWriter>, session: &NeovimSession,
+) -> anyhow::Result<()> {
+    let anchors = placement_anchors(session);
+    if anchors.is_empty() {
+        return Ok(());
+    }
+    let term_size = NeovimSession::get_term_size(nvim).await?;
+    let term_size = TermSizeInfo::new_from_nvim_term(term_size);
+    let mut contexts = HashMap::new();
+    for win in anchors {
+        contexts.insert(win, fetch_placement_context(nvim, win).await?);
+    }
+    let moves = reposition_image_sets(session, &term_size, &contexts).await?;
+    info!("Reposition {} image sets", moves.len());
+    if !moves.is_empty() {
+        move_placeholders(nvim, &moves).await?;
+    }
+    Ok(())
+}
+
+/// Expect name: "layout_changed", args: [event], the autocmd event is only
+/// logged.
+#[instrument(skip(nvim, session))]
+async fn process_layout_changed(
+    args: Vec<Value>, nvim: Neovim<NvimWriter>, session: Arc<NeovimSession>,
+) -> anyhow::Result<()> {
+    relayout(&nvim, &session).await
+}
+
+/// Repaint the widgets of `session` when their state changes. Widgets that
+/// change together are repainted at once, a relayout repaints all dirty
+/// widgets. Runs until the session is dropped, abort it when the channel is
+/// closed.
+pub(crate) async fn repaint_dirty_widgets(
+    nvim: Neovim<NvimWriter>, session: Arc<NeovimSession>,
+) {
+    let (sender, mut receiver) = unbounded_channel();
+    session.widgets.on_dirty(move |id| {
+        let _ = sender.send(id);
+    });
+    while receiver.recv().await.is_some() {
+        while receiver.try_recv().is_ok() {}
+        if let Err(e) = relayout(&nvim, &session).await {
+            warn!("Failed to repaint widgets: {}", e);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct LayoutChangedNotification;
+
+#[async_trait]
+impl NeovimService for LayoutChangedNotification {
+    #[instrument(skip(self, neovim, session))]
+    async fn call(
+        &self, _name: String, args: Vec<Value>, neovim: Neovim<NvimWriter>,
+        session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        match process_layout_changed(args, neovim, session).await {
+            Ok(()) => Ok(Value::from(true)),
+            Err(e) => Err(Value::from(e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, num::NonZeroU32, rc::Rc, sync::Arc};
+
+    use crate::{
+        nvim::{ChromeTheme, HoverConfig, NeovimSession, NvimTermSize},
+        painting::{
+            BoxBorder, BoxConstraints, BoxDecoration, Color,
+            FlexibleLengthAuto, RectSize,
+        },
+        placement::{
+            CellArea, ImagePlacement, PlacementConfig, PlacementContext,
+        },
+        render::{Chrome, DisplayMetrics, HoverWidget},
+        term::{
+            proto::{decode_commands, Action},
+            TermRecorder, TermSizeInfo, TermWriter,
+        },
+        widgets::{BoxOptions, Container, StaticProvider},
+    };
+
+    use super::{add_image_set, reposition_image_sets};
+
+    fn term_size() -> TermSizeInfo {
+        // 10x20 cells
+        TermSizeInfo::new_from_nvim_term(NvimTermSize {
+            row: 40,
+            col: 100,
+            xpixel: 1000,
+            ypixel: 800,
+        })
+    }
+
+    fn area(row: i32, col: i32, width: i32, height: i32) -> CellArea {
+        CellArea {
+            row,
+            col,
+            width,
+            height,
+        }
+    }
+
+    /// The editor is `width` cells wide.
+    fn context(cursor: (i32, i32), width: i32) -> PlacementContext {
+        PlacementContext {
+            win: 1000,
+            cursor,
+            window: area(0, 0, width, 38),
+            editor: area(0, 0, width, 38),
+            cmdline: area(39, 0, width, 1),
+            pum: None,
+            floats: vec![],
+        }
+    }
+
+    fn contexts(
+        ctx: Option<PlacementContext>,
+    ) -> HashMap<i64, Option<PlacementContext>> {
+        HashMap::from([(1000, ctx)])
+    }
+
+    fn record_tty(session: &NeovimSession) -> TermRecorder {
+        let (writer, recorder) = TermWriter::new_recorder(false);
+        *session.tty_writer.lock() =
+            Some(Arc::new(tokio::sync::Mutex::new(writer)));
+        recorder
+    }
+
+    /// Actions written to the tty, with the id of their image.
+    fn commands(recorder: &TermRecorder) -> Vec<(Action, u32)> {
+        decode_commands(&recorder.take())
+            .unwrap()
+            .into_iter()
+            .map(|d| (d.command.action, d.command.id.unwrap().0.get()))
+            .collect()
+    }
+
+    fn puts(commands: &[(Action, u32)]) -> Vec<(u32, u32, u32)> {
+        commands
+            .iter()
+            .filter_map(|(action, id)| match action {
+                Action::Put(put) => Some((*id, put.x_offset, put.y_offset)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn deletes(commands: &[(Action, u32)]) -> Vec<u32> {
+        commands
+            .iter()
+            .filter(|(action, _)| matches!(action, Action::Delete(_)))
+            .map(|(_, id)| *id)
+            .collect()
+    }
+
+    /// Place the image set `id` at `ctx` and render it like a hover.
+    async fn show(
+        session: &NeovimSession, id: NonZeroU32, images: Vec<Vec<u8>>,
+        size: RectSize<f32>, ctx: &PlacementContext,
+    ) -> anyhow::Result<()> {
+        let term_size = term_size();
+        let image_set = add_image_set(session, id, images).await?;
+        let placement = ImagePlacement::new(
+            ctx,
+            PlacementConfig::default(),
+            (0, 0),
+            &term_size,
+            size,
+        );
+        let (x, y) = placement.area.unwrap().pixel_position(&term_size);
+        let writer = session.tty_writer().unwrap();
+        image_set.render_at(&mut *writer.lock().await, x, y).await?;
+        session.placements.lock().insert(id, placement);
+        Ok(())
+    }
+
+    fn image_size() -> RectSize<f32> {
+        RectSize {
+            width: 100.0,
+            height: 40.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reposition_moved() -> anyhow::Result<()> {
+        let session = NeovimSession::new();
+        let recorder = record_tty(&session);
+        let term_size = term_size();
+        let id = session.images.lock().alloc_set_id();
+        let ctx = context((2, 4), 100);
+        show(&session, id, vec![b"a".to_vec()], image_size(), &ctx).await?;
+        let image = puts(&commands(&recorder))[0].0;
+
+        // the cursor moved down and right
+        let ctx = context((10, 20), 100);
+        let mut expected = session.placements.lock().get(&id).unwrap().clone();
+        let area = expected.update(Some(&ctx), &term_size);
+        let moves =
+            reposition_image_sets(&session, &term_size, &contexts(Some(ctx)))
+                .await?;
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].id, moves[0].area), (id.get(), area));
+        let (x, y) = area.unwrap().pixel_position(&term_size);
+        assert_eq!(puts(&commands(&recorder)), [(image, x, y)]);
+        assert_eq!(session.placements.lock()[&id].area, area);
+
+        // nothing is sent if the layout is kept
+        let ctx = context((10, 20), 100);
+        let moves =
+            reposition_image_sets(&session, &term_size, &contexts(Some(ctx)))
+                .await?;
+        assert!(moves.is_empty());
+        assert!(recorder.take().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reposition_hidden() -> anyhow::Result<()> {
+        let session = NeovimSession::new();
+        let recorder = record_tty(&session);
+        let term_size = term_size();
+        let id = session.images.lock().alloc_set_id();
+        let ctx = context((2, 4), 100);
+        show(&session, id, vec![b"a".to_vec()], image_size(), &ctx).await?;
+        let image = puts(&commands(&recorder))[0].0;
+
+        // the anchor window is not visible
+        let moves =
+            reposition_image_sets(&session, &term_size, &contexts(None))
+                .await?;
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].id, moves[0].area), (id.get(), None));
+        assert_eq!(deletes(&commands(&recorder)), [image]);
+        let image_set = session.images.lock().get_image_set(id).unwrap();
+        assert_eq!(image_set.last_rendered_pos(), None);
+
+        // and shown again when it is
+        let ctx = context((2, 4), 100);
+        let moves =
+            reposition_image_sets(&session, &term_size, &contexts(Some(ctx)))
+                .await?;
+        assert!(moves[0].area.is_some());
+        let commands = commands(&recorder);
+        assert_eq!(puts(&commands).len(), 1);
+        assert_eq!(puts(&commands)[0].0, image);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reposition_rerendered() -> anyhow::Result<()> {
+        let session = NeovimSession::new();
+        let recorder = record_tty(&session);
+        let term_size = term_size();
+        let scale = session.config.lock().scale;
+        let metrics = DisplayMetrics::new(scale, &term_size);
+        let id = session.images.lock().alloc_set_id();
+        // wider than the narrow editor below
+        let build = || async {
+            let width = FlexibleLengthAuto::Fixed(600.0);
+            let height = FlexibleLengthAuto::Fixed(20.0);
+            let bar = Container::new(
+                BoxDecoration {
+                    color: Color::new(0x7aa2f7),
+                    border: BoxBorder::NONE,
+                    shadow: None,
+                },
+                BoxOptions {
+                    constraints: BoxConstraints {
+                        min_width: width,
+                        max_width: width,
+                        min_height: height,
+                        max_height: height,
+                    },
+                    ..Default::default()
+                },
+            );
+            let provider = StaticProvider::default();
+            let chrome =
+                Chrome::resolve(&provider, &ChromeTheme::hover()).await;
+            HoverWidget::new(chrome, Rc::new(bar), &HoverConfig::default())
+        };
+        let max_width = metrics.width_of_cells(100);
+        let (images, size) = session
+            .widgets
+            .render(id, build, metrics, max_width)
+            .await?;
+        let ctx = context((2, 4), 100);
+        show(&session, id, images, size, &ctx).await?;
+        let image = puts(&commands(&recorder))[0].0;
+
+        // the editor shrinks to 30 cells, the widget is laid out again
+        let ctx = context((2, 4), 30);
+        let moves =
+            reposition_image_sets(&session, &term_size, &contexts(Some(ctx)))
+                .await?;
+        assert_eq!(moves.len(), 1);
+        let placed = session.placements.lock()[&id].clone();
+        assert!(placed.size.width < size.width);
+        assert_eq!(placed.size.width, metrics.width_of_cells(30));
+        assert_eq!(moves[0].area, placed.area);
+
+        // the old image is deleted, the new one is rendered in its place
+        let commands = commands(&recorder);
+        assert_eq!(deletes(&commands), [image]);
+        let puts = puts(&commands);
+        assert_eq!(puts.len(), 1);
+        assert_ne!(puts[0].0, image);
+        let (x, y) = placed.area.unwrap().pixel_position(&term_size);
+        assert_eq!((puts[0].1, puts[0].2), (x, y));
+        Ok(())
+    }
+}
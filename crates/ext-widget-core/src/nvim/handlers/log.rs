@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use async_trait::async_trait;
+use nvim_rs::Neovim;
+use rmpv::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, instrument, Level};
+
+use crate::{
+    logger::{set_log_filter, subscribe_warnings},
+    nvim::{handler::NeovimService, NeovimSession, NvimWriter},
+};
+
+/// Forward WARN and ERROR logs to `vim.notify` while `log.notify` is set in
+/// the config. Logs are not scoped to a session, a server shared by several
+/// clients forwards all of its warnings to each of them. Runs until the
+/// logger is gone, abort it when the channel is closed.
+pub(crate) async fn forward_warnings(
+    nvim: Neovim<NvimWriter>, session: Arc<NeovimSession>,
+) {
+    let mut warnings = subscribe_warnings();
+    loop {
+        let msg = match warnings.recv().await {
+            Ok(msg) => msg,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        if !session.config.lock().log.notify {
+            continue;
+        }
+        // `vim.log.levels`
+        let level = if msg.level == Level::ERROR { 4 } else { 3 };
+        let text = format!("{}: {}", msg.target, msg.message);
+        let res = nvim
+            .exec_lua(
+                r#"vim.notify(..., { title = "external-widget" })"#,
+                vec![text.into(), level.into()],
+            )
+            .await;
+        // not a warning, or it would be forwarded again
+        if let Err(e) = res {
+            debug!("Failed to forward log: {}", e);
+        }
+    }
+}
+
+/// Expect name: "set_log_level", args: [directives]
+fn process_req_set_log_level(args: &[Value]) -> anyhow::Result<Value> {
+    let [directives] = args else {
+        bail!("set_log_level expects 1 argument, got {}", args.len());
+    };
+    let directives =
+        directives.as_str().context("log level should be a string")?;
+    set_log_filter(directives)?;
+    Ok(Value::from(true))
+}
+
+#[derive(Debug)]
+pub(crate) struct SetLogLevelReq;
+
+#[async_trait]
+impl NeovimService for SetLogLevelReq {
+    #[instrument(skip(self, _neovim, _session))]
+    async fn call(
+        &self, _name: String, args: Vec<Value>, _neovim: Neovim<NvimWriter>,
+        _session: Arc<NeovimSession>,
+    ) -> Result<Value, Value> {
+        process_req_set_log_level(&args)
+            .map_err(|e| Value::from(format!("{:#}", e)))
+    }
+}
@@ -1,6 +1,18 @@
 mod config;
+mod health;
+mod hello;
 mod hover;
+mod image;
+mod layout;
+mod log;
 mod notify;
 
 pub(super) use config::ConfigNotify;
+pub(super) use health::HealthReq;
+pub(super) use hello::HelloReq;
 pub(super) use hover::*;
+pub use image::{add_image_set, clear_session_images};
+pub(super) use image::{ClearImagesReq, ListImagesReq};
+pub use layout::{placement_anchors, reposition_image_sets, PlaceholderMove};
+pub(super) use layout::{repaint_dirty_widgets, LayoutChangedNotification};
+pub(super) use log::{forward_warnings, SetLogLevelReq};
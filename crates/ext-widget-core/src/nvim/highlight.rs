@@ -1,3 +1,6 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
 use skia_safe::font_style::{
     Slant as SkSlant, Weight as SkWeight, Width as SkWidth,
@@ -144,9 +147,91 @@ impl HighlightInfos {
     }
 }
 
+/// Max depth of `link` chains, guards against cycles.
+const MAX_LINK_DEPTH: usize = 16;
+
+/// A standalone set of highlight groups, used when there is no Neovim to ask.
+///
+/// The JSON form is a map from group name to the `nvim_get_hl` fields, so
+/// `vim.json.encode(vim.api.nvim_get_hl(0, {}))` can be loaded directly.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HighlightTheme {
+    groups: HashMap<String, HighlightInfos>,
+}
+
+impl HighlightTheme {
+    pub fn new(groups: HashMap<String, HighlightInfos>) -> Self {
+        Self { groups }
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read theme {:?}", path))?;
+        Self::from_json(&json)
+            .with_context(|| format!("Failed to parse theme {:?}", path))
+    }
+
+    /// Find a group, following links. Like Neovim, `@a.b.c` falls back to
+    /// `@a.b` and `@a` when it's not defined.
+    pub fn get(&self, name: &str) -> Option<&HighlightInfos> {
+        let mut name = name;
+        loop {
+            if let Some(hl) = self.get_linked(name) {
+                return Some(hl);
+            }
+            match name.rsplit_once('.') {
+                Some((parent, _)) if name.starts_with('@') => name = parent,
+                _ => return None,
+            }
+        }
+    }
+
+    fn get_linked(&self, name: &str) -> Option<&HighlightInfos> {
+        let mut hl = self.groups.get(name)?;
+        for _ in 0..MAX_LINK_DEPTH {
+            match &hl.link {
+                Some(link) => hl = self.groups.get(link)?,
+                None => return Some(hl),
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{nvim::NeovimSession, test_utils::EmbedNvim};
+    use crate::{
+        nvim::{HighlightTheme, NeovimSession},
+        painting::Color,
+        test_utils::EmbedNvim,
+    };
+
+    #[test]
+    fn test_theme_links_and_fallback() -> anyhow::Result<()> {
+        let theme = HighlightTheme::from_json(
+            r##"{
+                "Normal": { "fg": "#c0caf5", "bg": 1710617 },
+                "@keyword": { "link": "Keyword" },
+                "Keyword": { "fg": "#bb9af7", "italic": true },
+                "A": { "link": "B" },
+                "B": { "link": "A" }
+            }"##,
+        )?;
+        let normal = theme.get("Normal").unwrap();
+        assert_eq!(normal.fg, Some(Color::new(0xc0caf5)));
+        assert_eq!(normal.bg, Some(Color::new(0x1a1a19)));
+        let keyword = theme.get("@keyword.function.rust").unwrap();
+        assert_eq!(keyword.fg, Some(Color::new(0xbb9af7)));
+        assert_eq!(keyword.italic, Some(true));
+        assert!(theme.get("keyword.function").is_none());
+        assert!(theme.get("A").is_none());
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_get_normal_highlight() -> anyhow::Result<()> {
@@ -0,0 +1,83 @@
+use std::{sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::info;
+
+use super::{handlers::clear_session_images, NeovimSession};
+
+/// Resolve when the process receives SIGTERM, SIGHUP or SIGINT.
+pub(super) async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut term = signal(SignalKind::terminate())?;
+    let mut hup = signal(SignalKind::hangup())?;
+    let mut int = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = term.recv() => info!("Received SIGTERM"),
+        _ = hup.recv() => info!("Received SIGHUP"),
+        _ = int.recv() => info!("Received SIGINT"),
+    }
+    Ok(())
+}
+
+/// Live connections of a server, used to clean up on shutdown and to detect
+/// idle servers.
+#[derive(Debug)]
+pub(super) struct Connections {
+    sessions: Mutex<Vec<Arc<NeovimSession>>>,
+    count: watch::Sender<usize>,
+}
+
+impl Connections {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(Vec::new()),
+            count: watch::Sender::new(0),
+        }
+    }
+
+    pub fn add(&self, session: Arc<NeovimSession>) {
+        let mut sessions = self.sessions.lock();
+        sessions.push(session);
+        self.count.send_replace(sessions.len());
+    }
+
+    pub fn remove(&self, session: &Arc<NeovimSession>) {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|s| !Arc::ptr_eq(s, session));
+        self.count.send_replace(sessions.len());
+    }
+
+    /// Resolve once there has been no connection for `timeout`.
+    pub async fn wait_idle(&self, timeout: Duration) {
+        let mut count = self.count.subscribe();
+        loop {
+            // the sender lives in self, so these never fail
+            let _ = count.wait_for(|n| *n == 0).await;
+            if tokio::time::timeout(timeout, count.changed()).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Delete images of all live connections.
+    pub async fn clear_images(&self) {
+        let sessions = self.sessions.lock().clone();
+        for session in sessions {
+            clear_session_images(&session).await;
+        }
+    }
+}
+
+/// Resolve after `timeout` without connections, or never if `timeout` is
+/// `None`.
+pub(super) async fn idle_timeout(
+    connections: &Connections, timeout: Option<Duration>,
+) {
+    match timeout {
+        Some(timeout) => connections.wait_idle(timeout).await,
+        None => std::future::pending().await,
+    }
+}
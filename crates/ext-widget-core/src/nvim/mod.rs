@@ -2,40 +2,114 @@ mod config;
 mod handler;
 mod handlers;
 mod highlight;
+mod lifecycle;
 mod session;
+mod socket;
+
+use std::{fmt::Debug, future::Future, path::Path, sync::Arc, time::Duration};
 
 use futures::AsyncWrite;
-pub use highlight::HighlightInfos;
+pub use highlight::{HighlightInfos, HighlightTheme};
 use nvim_rs::Neovim;
 pub use session::{NeovimSession, NvimTermSize};
 use tokio::{
-    io::{split, stdin, stdout},
-    net::{TcpListener, TcpStream},
+    io::{
+        split, stdin, stdout, AsyncRead as TokioAsyncRead,
+        AsyncWrite as TokioAsyncWrite,
+    },
+    net::TcpListener,
 };
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 use tracing::{error, info, instrument};
 
-pub use config::{ExtWidgetConfig, CONFIG};
+use self::{
+    handlers::{forward_warnings, repaint_dirty_widgets},
+    lifecycle::{shutdown_signal, Connections},
+};
+
+pub use config::{
+    BorderTheme, ChromeTheme, ColorSource, ConfigErrors, ConfigIssue,
+    ConfigUpdate, ExtWidgetConfig, FontSize, HoverConfig, Scale, ShadowTheme,
+    ThemeConfig, WindowConfig,
+};
+pub use handlers::{
+    add_image_set, clear_session_images, placement_anchors,
+    reposition_image_sets, PlaceholderMove,
+};
 pub(crate) use handler::NeovimHandler;
 
 type NvimWriter = Box<dyn AsyncWrite + Send + Unpin + 'static>;
 
-/// Start a TCP server on the given address.
+/// Start a TCP server on the given address. The server exits on SIGTERM or
+/// SIGHUP, or after `idle_timeout` without connections.
 #[instrument(skip(addr))]
-pub async fn start_server(addr: &str) -> anyhow::Result<()> {
+pub async fn start_server(
+    addr: &str, idle_timeout: Option<Duration>,
+) -> anyhow::Result<()> {
     let listener = TcpListener::bind(addr).await?;
+    info!("Listening on {}", addr);
+
+    let listener = &listener;
+    serve(idle_timeout, move || async move { listener.accept().await }).await
+}
+
+/// Start a server on the unix domain socket at the given path. The server
+/// exits on SIGTERM or SIGHUP, or after `idle_timeout` without connections.
+#[instrument]
+pub async fn start_unix_server(
+    path: &Path, idle_timeout: Option<Duration>,
+) -> anyhow::Result<()> {
+    let (_socket, listener) = socket::SocketFile::bind(path)?;
+    info!("Listening on {:?}", path);
+
+    let listener = &listener;
+    serve(idle_timeout, move || async move { listener.accept().await }).await
+}
+
+async fn serve<F, Fut, S, A>(
+    idle_timeout: Option<Duration>, mut accept: F,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::io::Result<(S, A)>>,
+    S: TokioAsyncRead + TokioAsyncWrite + Debug + Send + 'static,
+    A: Debug,
+{
+    let connections = Arc::new(Connections::new());
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
 
     loop {
-        let (tcp, addr) = listener.accept().await.unwrap();
-        info!("Accepted connection, {:?}, {:?}", tcp, addr);
-        tokio::spawn(async {
-            if let Err(e) = create_neovim_from_tcp(tcp).await {
-                error!("Error: '{}'", e);
+        tokio::select! {
+            accepted = accept() => {
+                let (stream, addr) = accepted?;
+                info!("Accepted connection, {:?}, {:?}", stream, addr);
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        create_neovim_from_stream(stream, connections).await
+                    {
+                        error!("Error: '{}'", e);
+                    }
+                });
+            }
+            _ = lifecycle::idle_timeout(&connections, idle_timeout) => {
+                info!("No connection for {:?}, exit", idle_timeout);
+                break;
+            }
+            res = &mut shutdown => {
+                res?;
+                break;
             }
-        });
+        }
     }
+
+    connections.clear_images().await;
+    Ok(())
 }
 
+/// Serve the parent Neovim over stdio. Returns when the channel is closed or
+/// the process receives SIGTERM or SIGHUP.
 pub async fn start_parent() -> anyhow::Result<()> {
     let handler = NeovimHandler::new();
     let (neovim, io) = Neovim::<NvimWriter>::new(
@@ -44,38 +118,68 @@ pub async fn start_parent() -> anyhow::Result<()> {
         handler.clone(),
     );
 
-    tokio::spawn(async move {
+    let io = tokio::spawn(async move {
         if let Err(error) = io.await {
             if !error.is_channel_closed() {
                 error!("Error: '{}'", error);
             }
         };
+        info!("Channel closed");
     });
 
-    handler.post_instance(&neovim).await?;
+    let session = handler.session();
+    let forwarder =
+        tokio::spawn(forward_warnings(neovim.clone(), session.clone()));
+    let repainter =
+        tokio::spawn(repaint_dirty_widgets(neovim.clone(), session.clone()));
+    let run = async {
+        handler.post_instance(&neovim).await?;
+        io.await?;
+        anyhow::Ok(())
+    };
+    let res = tokio::select! {
+        res = run => res,
+        res = shutdown_signal() => res,
+    };
 
-    loop {
-        // tokio sleep
-        tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-    }
+    forwarder.abort();
+    repainter.abort();
+    clear_session_images(&session).await;
+    res
 }
 
-#[instrument]
-async fn create_neovim_from_tcp(tcp: TcpStream) -> anyhow::Result<()> {
+#[instrument(skip(stream, connections))]
+async fn create_neovim_from_stream<S>(
+    stream: S, connections: Arc<Connections>,
+) -> anyhow::Result<()>
+where
+    S: TokioAsyncRead + TokioAsyncWrite + Send + 'static,
+{
     let handler = NeovimHandler::new();
-    let (reader, writer) = split(tcp);
+    let (reader, writer) = split(stream);
     let (neovim, io) = Neovim::<NvimWriter>::new(
         reader.compat(),
         Box::new(writer.compat_write()),
         handler.clone(),
     );
 
+    let session = handler.session();
+    connections.add(session.clone());
+    let forwarder =
+        tokio::spawn(forward_warnings(neovim.clone(), session.clone()));
+    let repainter =
+        tokio::spawn(repaint_dirty_widgets(neovim.clone(), session.clone()));
     tokio::spawn(async move {
         if let Err(error) = io.await {
             if !error.is_channel_closed() {
                 error!("Error: '{}'", error);
             }
         };
+        info!("Channel closed, clean up images");
+        forwarder.abort();
+        repainter.abort();
+        connections.remove(&session);
+        clear_session_images(&session).await;
     });
 
     handler.post_instance(&neovim).await?;
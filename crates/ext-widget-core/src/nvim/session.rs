@@ -1,51 +1,94 @@
 use std::{
     collections::HashMap,
-    fmt::Write,
+    num::NonZeroU32,
     path::{Path, PathBuf},
-    str::FromStr,
     sync::Arc,
 };
 
 use anyhow::{bail, Context};
-use async_recursion::async_recursion;
 use futures::AsyncWrite;
-use libloading::Library;
 use nvim_rs::Neovim;
-use once_cell::sync::Lazy;
 use parking_lot::Mutex;
-use regex::Regex;
 use rmpv::ext::from_value;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
-use tree_sitter::{Language, Parser};
-
-use crate::{env::in_tmux, term::TermWriter};
+use tracing::{instrument, warn};
+use tree_sitter::Parser;
+
+use crate::{
+    env::in_tmux,
+    live::LiveWidgets,
+    logger::set_log_filter,
+    placement::ImagePlacement,
+    term::{image::ImageManager, TermWriter},
+    treesitter::{find_file_in_runtime_paths, TreeSitterLoader},
+};
 
-use super::HighlightInfos;
+use super::{ConfigErrors, ConfigIssue, ExtWidgetConfig, HighlightInfos};
 
 /**
  * A session with Neovim. This will be saved in the `session` field in the
  * NvimHandler. Do some caching here.
+ *
+ * All states of a connection live here, so multiple Neovim instances can
+ * share one server.
  */
 #[derive(Debug)]
 pub struct NeovimSession {
-    pub ts_libs: Mutex<HashMap<PathBuf, Arc<Library>>>,
-    pub ts_queries: Mutex<HashMap<String, String>>,
+    pub ts: TreeSitterLoader,
     pub tty_writer: Mutex<Option<Arc<tokio::sync::Mutex<TermWriter>>>>,
+    pub config: Mutex<ExtWidgetConfig>,
+    /// Images of this connection.
+    pub images: Mutex<ImageManager>,
+    /// Placements of live image sets, they are placed again when the layout
+    /// of Neovim changes.
+    pub placements: Mutex<HashMap<NonZeroU32, ImagePlacement>>,
+    /// Widgets of live image sets, they are laid out again when the room
+    /// for them changes.
+    pub widgets: LiveWidgets,
 }
 
-static INHERITS_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r";+\s*inherits\s*:?\s*([a-z_,()-]+)\s*").unwrap());
-
 impl NeovimSession {
     pub fn new() -> Self {
         Self {
-            ts_libs: Mutex::new(HashMap::new()),
-            ts_queries: Mutex::new(HashMap::new()),
+            ts: TreeSitterLoader::new(),
             tty_writer: Mutex::new(None),
+            config: Mutex::new(ExtWidgetConfig::default()),
+            images: Mutex::new(ImageManager::new()),
+            placements: Mutex::new(HashMap::new()),
+            widgets: LiveWidgets::new(),
         }
     }
 
+    /// Replace the config, and apply the parts that take effect right away.
+    pub fn set_config(&self, config: ExtWidgetConfig) {
+        let mut current = self.config.lock();
+        self.apply_config(&config);
+        *current = config;
+    }
+
+    /// Merge a partial config into the current one, returns unknown keys.
+    ///
+    /// The config stays locked from merging to storing, so concurrent
+    /// updates are not lost.
+    pub fn update_config(
+        &self, patch: serde_json::Value,
+    ) -> Result<Vec<ConfigIssue>, ConfigErrors> {
+        let mut current = self.config.lock();
+        let update = current.merge(patch)?;
+        self.apply_config(&update.config);
+        *current = update.config;
+        Ok(update.unknown_keys)
+    }
+
+    fn apply_config(&self, config: &ExtWidgetConfig) {
+        if let Some(level) = &config.log.level {
+            if let Err(e) = set_log_filter(level) {
+                warn!("Failed to set log level: {:#}", e);
+            }
+        }
+        self.images.lock().set_max_bytes(config.image.max_cache_bytes);
+    }
+
     async fn get_highlight_info_impl<W>(
         &self, nvim: &Neovim<W>, name: &str,
     ) -> anyhow::Result<HighlightInfos>
@@ -97,13 +140,8 @@ impl NeovimSession {
     where
         W: AsyncWrite + Send + Unpin + 'static,
     {
-        let lang = self
-            .load_language(nvim, lang)
-            .await?
-            .context("No language parser")?;
-        let mut parser = Parser::new();
-        parser.set_language(lang)?;
-        Ok(Some(parser))
+        let rtps = Self::runtime_paths(nvim).await?;
+        self.ts.load_parser(&rtps, lang)
     }
 
     pub async fn load_ts_query<W>(
@@ -112,120 +150,22 @@ impl NeovimSession {
     where
         W: AsyncWrite + Send + Unpin + 'static,
     {
-        let query = self.read_query(nvim, lang, query).await;
-        let lang = self
-            .load_language(nvim, lang)
-            .await?
-            .context("No language parser")?;
-        let query = tree_sitter::Query::new(lang, &query)?;
-        Ok(query)
-    }
-
-    async fn load_language<W>(
-        &self, nvim: &Neovim<W>, lang: &str,
-    ) -> anyhow::Result<Option<Language>>
-    where
-        W: AsyncWrite + Send + Unpin + 'static,
-    {
-        let path = PathBuf::from_str(&format!("parser/{}.so", lang))?;
-        let lib = self.load_lib(nvim, path).await?;
-        if lib.is_none() {
-            return Ok(None);
-        }
-        let lib = lib.unwrap();
-        let func_name = format!("tree_sitter_{}", lang.replace('-', "_"));
-        unsafe {
-            let entry = lib.get::<unsafe extern "C" fn() -> Language>(
-                func_name.as_bytes(),
-            )?;
-            let parser = entry();
-            Ok(Some(parser))
-        }
-    }
-
-    async fn load_lib<W>(
-        &self, nvim: &Neovim<W>, path: PathBuf,
-    ) -> anyhow::Result<Option<Arc<Library>>>
-    where
-        W: AsyncWrite + Send + Unpin + 'static,
-    {
-        {
-            if let Some(lib) = self.ts_libs.lock().get(&path) {
-                return Ok(Some(lib.clone()));
-            }
-        }
-
-        let file = Self::find_file_in_runtime_path(nvim, &path).await?;
-        if file.is_none() {
-            return Ok(None);
-        }
-        let file = unsafe { file.unwrap_unchecked() };
-        unsafe {
-            let lib = Arc::new(libloading::Library::new(file)?);
-            self.ts_libs.lock().insert(path, lib.clone());
-            Ok(Some(lib))
-        }
-    }
-
-    #[async_recursion]
-    async fn read_query<W>(
-        &self, nvim: &Neovim<W>, lang: &str, filename: &str,
-    ) -> String
-    where
-        W: AsyncWrite + Send + Unpin + 'static,
-    {
-        let key = format!("{}/{}", lang, filename);
-        if let Some(query) = self.ts_queries.lock().get(&key) {
-            return query.clone();
-        }
-
-        let query = self
-            .read_query_raw(nvim, lang, filename)
-            .await
-            .unwrap_or_default();
-
-        // replaces all "; inherits <language>(,<language>)*" with the queries of the given language(s)
-        let replaced = match INHERITS_REGEX.captures(&query) {
-            Some(captures) => {
-                let mut ret = String::new();
-                let parts = captures.get(1).unwrap().as_str();
-                for part in parts.split(',') {
-                    let _ = write!(
-                        &mut ret,
-                        "\n{}\n",
-                        self.read_query(nvim, part, filename).await
-                    );
-                }
-                ret
-            }
-            None => "".to_string(),
-        };
-        let ret = INHERITS_REGEX
-            .replace_all(&query, replaced.as_str())
-            .to_string();
-
-        {
-            self.ts_queries.lock().insert(key, ret.clone());
-        }
-
-        ret
+        let rtps = Self::runtime_paths(nvim).await?;
+        self.ts.load_query(&rtps, lang, query)
     }
 
-    async fn read_query_raw<W>(
-        &self, nvim: &Neovim<W>, lang: &str, filename: &str,
-    ) -> anyhow::Result<String>
+    pub async fn runtime_paths<W>(
+        nvim: &Neovim<W>,
+    ) -> anyhow::Result<Vec<PathBuf>>
     where
         W: AsyncWrite + Send + Unpin + 'static,
     {
-        let path =
-            PathBuf::from_str(&format!("queries/{}/{}.scm", lang, filename))
-                .unwrap();
-        let query_file = Self::find_file_in_runtime_path(nvim, path).await?;
-        if query_file.is_none() {
-            return Ok(String::new());
-        }
-        let query_file = query_file.unwrap();
-        Ok(std::fs::read_to_string(query_file)?)
+        Ok(nvim
+            .list_runtime_paths()
+            .await?
+            .into_iter()
+            .map(PathBuf::from)
+            .collect())
     }
 
     /**
@@ -240,14 +180,8 @@ impl NeovimSession {
         P: AsRef<Path>,
         W: AsyncWrite + Send + Unpin + 'static,
     {
-        for rtp in nvim.list_runtime_paths().await? {
-            let rtp = PathBuf::from(rtp);
-            let p = rtp.join(path.as_ref());
-            if p.exists() && p.is_file() {
-                return Ok(Some(p));
-            }
-        }
-        Ok(None)
+        let rtps = Self::runtime_paths(nvim).await?;
+        Ok(find_file_in_runtime_paths(&rtps, path))
     }
 
     pub async fn get_tty<W>(nvim: &Neovim<W>) -> anyhow::Result<String>
@@ -303,6 +237,11 @@ impl NeovimSession {
         Ok(tty_writer.clone().unwrap())
     }
 
+    /// Returns the tty writer if it has been created.
+    pub fn tty_writer(&self) -> Option<Arc<tokio::sync::Mutex<TermWriter>>> {
+        self.tty_writer.lock().clone()
+    }
+
     pub async fn cursor_position_to_client<W>(
         nvim: &Neovim<W>,
     ) -> anyhow::Result<(i32, i32)>
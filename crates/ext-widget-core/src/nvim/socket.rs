@@ -0,0 +1,104 @@
+use std::{
+    fs::Permissions,
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use tokio::net::UnixListener;
+use tracing::{info, warn};
+
+/// A unix domain socket bound by this process, the socket file is removed on
+/// drop.
+#[derive(Debug)]
+pub(super) struct SocketFile {
+    path: PathBuf,
+}
+
+impl SocketFile {
+    /// Bind the socket at `path`, only the owner can connect to it.
+    pub fn bind(path: &Path) -> anyhow::Result<(Self, UnixListener)> {
+        prepare(path)?;
+        let listener = UnixListener::bind(path)?;
+        // owned from here, so the file is removed if anything below fails
+        let socket = Self {
+            path: path.to_path_buf(),
+        };
+        // set the mode after binding instead of narrowing the umask, which
+        // is shared by all threads
+        std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+        Ok((socket, listener))
+    }
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove socket {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Check the socket path before binding. Refuse directories that other users
+/// can write into, and remove stale sockets left by dead servers.
+fn prepare(path: &Path) -> anyhow::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let meta = std::fs::metadata(parent)
+        .with_context(|| format!("Socket directory {:?} not found", parent))?;
+    if !meta.is_dir() {
+        bail!("{:?} is not a directory", parent);
+    }
+    let mode = meta.permissions().mode();
+    // world-writable directories are only allowed with the sticky bit,
+    // like `/tmp`
+    if mode & 0o002 != 0 && mode & 0o1000 == 0 {
+        bail!("Socket directory {:?} is world-writable", parent);
+    }
+
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) => {
+            if !meta.file_type().is_socket() {
+                bail!("{:?} exists and is not a socket", path);
+            }
+            if meta.uid() != rustix::process::getuid().as_raw() {
+                bail!("Socket {:?} is owned by another user", path);
+            }
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                bail!("Socket {:?} is in use by another server", path);
+            }
+            info!("Remove stale socket {:?}", path);
+            std::fs::remove_file(path)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::SocketFile;
+
+    #[tokio::test]
+    async fn test_bind_and_remove() -> anyhow::Result<()> {
+        let path = std::env::temp_dir()
+            .join(format!("ext-widget-test-{}.sock", std::process::id()));
+        let (socket, _listener) = SocketFile::bind(&path)?;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // the socket of a running server is kept
+        assert!(SocketFile::bind(&path).is_err());
+        assert!(path.exists());
+
+        drop(socket);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
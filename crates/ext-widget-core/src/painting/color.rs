@@ -60,6 +60,11 @@ impl Color {
         T: AsRef<str>,
     {
         if let Some(v) = s.as_ref().strip_prefix('#') {
+            if !v.is_ascii() || (v.len() != 6 && v.len() != 8) {
+                return Err(ParseColorError::InvalidColorFormat(
+                    s.as_ref().to_string(),
+                ));
+            }
             let r = u8::from_str_radix(&v[0..2], 16)?;
             let g = u8::from_str_radix(&v[2..4], 16)?;
             let b = u8::from_str_radix(&v[4..6], 16)?;
@@ -2,9 +2,11 @@ use std::fmt::Debug;
 
 use anyhow::Context;
 use base64::Engine;
-use skia_safe::{surfaces, Canvas, EncodedImageFormat, IRect, Surface};
+use skia_safe::{
+    surfaces, Canvas, EncodedImageFormat, IRect, Rect, Surface,
+};
 
-use super::{Location, RectSize};
+use super::{Location, RectSize, Region};
 
 /// A renderer that can paint widgets.
 #[derive(Debug)]
@@ -20,6 +22,16 @@ impl Renderer {
         Ok(Self { surface })
     }
 
+    /// A renderer of `width` x `height` device pixels, widgets paint in
+    /// logical pixels which are `scale` device pixels.
+    pub fn new_scaled(
+        width: u32, height: u32, scale: f32,
+    ) -> anyhow::Result<Self> {
+        let mut renderer = Self::new(width, height)?;
+        renderer.canvas().scale((scale, scale));
+        Ok(renderer)
+    }
+
     pub fn canvas(&mut self) -> &Canvas {
         self.surface.canvas()
     }
@@ -59,31 +71,64 @@ impl Renderer {
         if image_height <= max_height {
             return Ok(vec![self.snapshot_png_raw()?]);
         }
+        Self::page_bounds(image_width, image_height, max_height, step)
+            .into_iter()
+            .map(|bounds| self.snapshot_png_bounds(bounds))
+            .collect()
+    }
 
+    /// Bounds of each page taken by
+    /// [`Renderer::snapshot_png_raw_with_steps`], in device pixels.
+    pub fn page_bounds(
+        image_width: f32, image_height: f32, max_height: f32, step: f32,
+    ) -> Vec<IRect> {
+        if image_height <= max_height {
+            return vec![IRect::from_wh(
+                image_width as i32,
+                image_height as i32,
+            )];
+        }
         let mut start: f32 = 0.;
-
         let mut ret = vec![];
         while start < image_height {
-            let image = self
-                .surface
-                .image_snapshot_with_bounds(IRect::from_xywh(
-                    0,
-                    start as i32,
-                    image_width as i32,
-                    max_height as i32,
-                ))
-                .context("Failed to take snapshot")?;
-            let data = image
-                .encode(
-                    &mut self.surface.direct_context(),
-                    EncodedImageFormat::PNG,
-                    None,
-                )
-                .context("Failed to encode png")?;
-            ret.push(data.as_bytes().to_vec());
+            ret.push(IRect::from_xywh(
+                0,
+                start as i32,
+                image_width as i32,
+                max_height as i32,
+            ));
             start += step;
         }
-        Ok(ret)
+        ret
+    }
+
+    /// Take snapshot of the canvas within `bounds` (in device pixels), and
+    /// encode it to png.
+    pub fn snapshot_png_bounds(
+        &mut self, bounds: IRect,
+    ) -> anyhow::Result<Vec<u8>> {
+        let image = self
+            .surface
+            .image_snapshot_with_bounds(bounds)
+            .context("Failed to take snapshot")?;
+        let data = image
+            .encode(
+                &mut self.surface.direct_context(),
+                EncodedImageFormat::PNG,
+                None,
+            )
+            .context("Failed to encode png")?;
+        Ok(data.as_bytes().to_vec())
+    }
+
+    /// Device pixels covering `region` in logical pixels, None if it's out
+    /// of the surface.
+    pub fn device_bounds(&mut self, region: Region) -> Option<IRect> {
+        let matrix = self.canvas().local_to_device_as_3x3();
+        let (rect, _) = matrix.map_rect(Rect::from(region));
+        let surface =
+            IRect::from_wh(self.surface.width(), self.surface.height());
+        IRect::intersect(rect.round_out(), surface)
     }
 }
 
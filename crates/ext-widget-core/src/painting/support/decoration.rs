@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::painting::Color;
 
-use super::FlexibleLength;
+use super::{FlexibleLength, Location};
 
 /// An immutable description of how to paint a box.
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -13,6 +13,9 @@ pub struct BoxDecoration {
     /// The border of the box.
     #[serde(default)]
     pub border: BoxBorder,
+    /// The shadow cast by the box, painted outside of it.
+    #[serde(default)]
+    pub shadow: Option<BoxShadow>,
 }
 
 // TODO(hawtian): support different border widths for different sides.
@@ -36,3 +39,23 @@ impl BoxBorder {
         radius: FlexibleLength::Fixed(0.0),
     };
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoxShadow {
+    /// The color of the shadow.
+    #[serde(default)]
+    pub color: Color,
+    /// The sigma of the gaussian blur, the shadow spreads about 3 times of
+    /// it.
+    #[serde(default)]
+    pub blur_radius: f32,
+    /// Offset of the shadow from the box.
+    pub offset: Location,
+}
+
+impl BoxShadow {
+    /// How far the shadow reaches out of the box.
+    pub fn extent(&self) -> f32 {
+        self.blur_radius * 3.0 + self.offset.x.abs().max(self.offset.y.abs())
+    }
+}
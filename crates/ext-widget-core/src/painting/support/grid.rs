@@ -0,0 +1,333 @@
+use std::{num::ParseFloatError, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use taffy::{
+    GridPlacement, Line, MaxTrackSizingFunction, MinTrackSizingFunction,
+    NonRepeatedTrackSizingFunction, TrackSizingFunction,
+};
+use thiserror::Error;
+
+use super::FlexibleLength;
+
+/// A size a grid track can take.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackBreadth {
+    /// Fixed size, or percent of the grid.
+    Fixed(FlexibleLength),
+    /// Share of the free space, `fr` in css.
+    Fraction(f32),
+    /// Fit the content, within the free space.
+    Auto,
+    MinContent,
+    MaxContent,
+}
+
+/// Size of a grid track, a row or a column.
+///
+/// Written like css, numbers are pixels: `40`, `"40px"`, `"25%"`, `"1fr"`,
+/// `"auto"`, `"min-content"`, `"max-content"` or `"minmax(40, 1fr)"`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "TrackSizeRepr", into = "TrackSizeRepr")]
+pub enum TrackSize {
+    Single(TrackBreadth),
+    /// At least `min` and at most `max`, `min` can't be a fraction.
+    MinMax(TrackBreadth, TrackBreadth),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum TrackSizeRepr {
+    Fixed(f32),
+    Text(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ParseTrackSizeError {
+    #[error("invalid float")]
+    ParseFloatError(#[from] ParseFloatError),
+    #[error("invalid percent")]
+    InvalidPercent,
+    #[error("the min of minmax can't be a fraction")]
+    FractionMin,
+    #[error("invalid format")]
+    InvalidFormat,
+}
+
+/// Where a widget is placed in its grid parent.
+///
+/// Lines are 1-based, negative ones count from the end and are where the
+/// cell ends, so a span never leaves the grid. Widgets without a line are
+/// placed in the next free cells.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridCell {
+    #[serde(default)]
+    pub column: Option<i16>,
+    #[serde(default)]
+    pub row: Option<i16>,
+    /// Count of columns taken.
+    #[serde(default = "default_span")]
+    pub column_span: u16,
+    /// Count of rows taken.
+    #[serde(default = "default_span")]
+    pub row_span: u16,
+}
+
+/// Tracks of a grid, rows beyond `rows` fit their content.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridTemplate {
+    #[serde(default)]
+    pub columns: Vec<TrackSize>,
+    #[serde(default)]
+    pub rows: Vec<TrackSize>,
+}
+
+fn default_span() -> u16 {
+    1
+}
+
+impl Default for GridCell {
+    fn default() -> Self {
+        Self {
+            column: None,
+            row: None,
+            column_span: default_span(),
+            row_span: default_span(),
+        }
+    }
+}
+
+impl GridCell {
+    /// The cell at `column`, `row`.
+    pub fn at(column: i16, row: i16) -> Self {
+        Self {
+            column: Some(column),
+            row: Some(row),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_span(self, column_span: u16, row_span: u16) -> Self {
+        Self {
+            column_span,
+            row_span,
+            ..self
+        }
+    }
+
+    pub(crate) fn column_line(&self) -> Line<GridPlacement> {
+        grid_line(self.column, self.column_span)
+    }
+
+    pub(crate) fn row_line(&self) -> Line<GridPlacement> {
+        grid_line(self.row, self.row_span)
+    }
+}
+
+impl FromStr for TrackBreadth {
+    type Err = ParseTrackSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        Ok(match s {
+            "auto" => Self::Auto,
+            "min-content" => Self::MinContent,
+            "max-content" => Self::MaxContent,
+            _ => {
+                if let Some(f) = s.strip_suffix("fr") {
+                    Self::Fraction(f.trim().parse()?)
+                } else if let Some(p) = s.strip_suffix('%') {
+                    let f = p.trim().parse::<f32>()? / 100.0;
+                    if !(0.0..=1.0).contains(&f) {
+                        return Err(ParseTrackSizeError::InvalidPercent);
+                    }
+                    Self::Fixed(FlexibleLength::Percent(f))
+                } else {
+                    let px = s.strip_suffix("px").unwrap_or(s);
+                    Self::Fixed(FlexibleLength::Fixed(px.trim().parse()?))
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for TrackSize {
+    type Err = ParseTrackSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let Some(args) = s.strip_prefix("minmax(") else {
+            return Ok(Self::Single(s.parse()?));
+        };
+        let (min, max) = args
+            .strip_suffix(')')
+            .and_then(|args| args.split_once(','))
+            .ok_or(ParseTrackSizeError::InvalidFormat)?;
+        let min = min.parse()?;
+        if let TrackBreadth::Fraction(_) = min {
+            return Err(ParseTrackSizeError::FractionMin);
+        }
+        Ok(Self::MinMax(min, max.parse()?))
+    }
+}
+
+impl std::fmt::Display for TrackBreadth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed(FlexibleLength::Fixed(px)) => write!(f, "{}px", px),
+            Self::Fixed(FlexibleLength::Percent(p)) => {
+                write!(f, "{}%", p * 100.0)
+            }
+            Self::Fraction(fr) => write!(f, "{}fr", fr),
+            Self::Auto => f.write_str("auto"),
+            Self::MinContent => f.write_str("min-content"),
+            Self::MaxContent => f.write_str("max-content"),
+        }
+    }
+}
+
+impl TryFrom<TrackSizeRepr> for TrackSize {
+    type Error = ParseTrackSizeError;
+
+    fn try_from(value: TrackSizeRepr) -> Result<Self, Self::Error> {
+        match value {
+            TrackSizeRepr::Fixed(px) => {
+                Ok(Self::Single(TrackBreadth::Fixed(px.into())))
+            }
+            TrackSizeRepr::Text(s) => s.parse(),
+        }
+    }
+}
+
+impl From<TrackSize> for TrackSizeRepr {
+    fn from(value: TrackSize) -> Self {
+        match value {
+            TrackSize::Single(TrackBreadth::Fixed(FlexibleLength::Fixed(
+                px,
+            ))) => Self::Fixed(px),
+            TrackSize::Single(breadth) => Self::Text(breadth.to_string()),
+            TrackSize::MinMax(min, max) => {
+                Self::Text(format!("minmax({}, {})", min, max))
+            }
+        }
+    }
+}
+
+impl From<TrackBreadth> for MinTrackSizingFunction {
+    fn from(value: TrackBreadth) -> Self {
+        match value {
+            TrackBreadth::Fixed(length) => Self::Fixed(length.into()),
+            // `1fr` is `minmax(auto, 1fr)`
+            TrackBreadth::Fraction(_) | TrackBreadth::Auto => Self::Auto,
+            TrackBreadth::MinContent => Self::MinContent,
+            TrackBreadth::MaxContent => Self::MaxContent,
+        }
+    }
+}
+
+impl From<TrackBreadth> for MaxTrackSizingFunction {
+    fn from(value: TrackBreadth) -> Self {
+        match value {
+            TrackBreadth::Fixed(length) => Self::Fixed(length.into()),
+            TrackBreadth::Fraction(fr) => Self::Fraction(fr),
+            TrackBreadth::Auto => Self::Auto,
+            TrackBreadth::MinContent => Self::MinContent,
+            TrackBreadth::MaxContent => Self::MaxContent,
+        }
+    }
+}
+
+impl From<TrackSize> for TrackSizingFunction {
+    fn from(value: TrackSize) -> Self {
+        let (min, max) = match value {
+            TrackSize::Single(breadth) => (breadth, breadth),
+            TrackSize::MinMax(min, max) => (min, max),
+        };
+        Self::Single(NonRepeatedTrackSizingFunction {
+            min: min.into(),
+            max: max.into(),
+        })
+    }
+}
+
+/// Placement of a cell on one axis, starting at `line` if any, or ending at
+/// it if it's negative.
+fn grid_line(line: Option<i16>, span: u16) -> Line<GridPlacement> {
+    match (line, span) {
+        // -1 is the end of the grid, starting there would add tracks after it
+        (Some(line), span) if line < 0 => Line {
+            start: GridPlacement::Span(span.max(1)),
+            end: GridPlacement::Line(line.into()),
+        },
+        // the span is explicit, an absolute box would take up to the edge
+        // of the grid otherwise
+        (Some(line), span) => Line {
+            start: GridPlacement::Line(line.into()),
+            end: GridPlacement::Span(span.max(1)),
+        },
+        (None, 0 | 1) => Line {
+            start: GridPlacement::Auto,
+            end: GridPlacement::Auto,
+        },
+        (None, span) => Line {
+            start: GridPlacement::Span(span),
+            end: GridPlacement::Auto,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use taffy::GridPlacement;
+
+    use crate::painting::FlexibleLength;
+
+    use super::{GridCell, TrackBreadth, TrackSize};
+
+    #[test]
+    fn test_parse_track_size() -> anyhow::Result<()> {
+        let sizes: Vec<TrackSize> = serde_json::from_str(
+            r#"[40, "12px", "25%", "2fr", "auto", "minmax(100, 1fr)"]"#,
+        )?;
+        assert_eq!(
+            sizes,
+            [
+                TrackSize::Single(TrackBreadth::Fixed(FlexibleLength::Fixed(
+                    40.0
+                ))),
+                TrackSize::Single(TrackBreadth::Fixed(FlexibleLength::Fixed(
+                    12.0
+                ))),
+                TrackSize::Single(TrackBreadth::Fixed(
+                    FlexibleLength::Percent(0.25)
+                )),
+                TrackSize::Single(TrackBreadth::Fraction(2.0)),
+                TrackSize::Single(TrackBreadth::Auto),
+                TrackSize::MinMax(
+                    TrackBreadth::Fixed(FlexibleLength::Fixed(100.0)),
+                    TrackBreadth::Fraction(1.0)
+                ),
+            ]
+        );
+        // round trip
+        let json = serde_json::to_string(&sizes)?;
+        assert_eq!(serde_json::from_str::<Vec<TrackSize>>(&json)?, sizes);
+
+        assert!("minmax(1fr, 100)".parse::<TrackSize>().is_err());
+        assert!("1em".parse::<TrackSize>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_line() {
+        let line = GridCell::at(-1, 2).with_span(2, 1).column_line();
+        assert_eq!(line.start, GridPlacement::Span(2));
+        assert_eq!(line.end, GridPlacement::Line((-1i16).into()));
+        let line = GridCell::at(-2, 2).row_line();
+        assert_eq!(line.start, GridPlacement::Span(1));
+        assert_eq!(line.end, GridPlacement::Line((-2i16).into()));
+        // positive lines start the cell
+        let line = GridCell::at(1, 2).row_line();
+        assert_eq!(line.start, GridPlacement::Line(2i16.into()));
+        assert_eq!(line.end, GridPlacement::Span(1));
+    }
+}
@@ -17,6 +17,15 @@ pub struct BoxConstraints {
     pub max_height: FlexibleLengthAuto,
 }
 
+/// Fixed size of a box, auto on an axis that is not set.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BoxSize {
+    #[serde(default)]
+    pub width: FlexibleLengthAuto,
+    #[serde(default)]
+    pub height: FlexibleLengthAuto,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Padding {
     #[serde(default)]
@@ -49,7 +58,79 @@ pub enum Axis {
     Vertical,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// How children are aligned on the cross axis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    #[default]
+    Stretch,
+    Start,
+    End,
+    Center,
+    /// Align the first lines of text.
+    Baseline,
+}
+
+/// How children are distributed on the main axis.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Justify {
+    #[default]
+    Start,
+    End,
+    Center,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+}
+
+/// Whether children wrap onto new lines when the main axis is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    #[default]
+    #[serde(rename = "nowrap")]
+    NoWrap,
+    Wrap,
+    WrapReverse,
+}
+
+/// How children are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    /// In a line on the axis.
+    #[default]
+    Flex,
+    /// In the cells of the grid template.
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    /// Laid out with its siblings, then offset by the inset.
+    #[default]
+    Relative,
+    /// Taken out of the layout of its siblings, and placed by the inset
+    /// from the edges of its parent.
+    Absolute,
+}
+
+/// Offsets from the edges of the parent, see [`Position`].
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Inset {
+    #[serde(default)]
+    pub left: FlexibleLengthAuto,
+    #[serde(default)]
+    pub right: FlexibleLengthAuto,
+    #[serde(default)]
+    pub top: FlexibleLengthAuto,
+    #[serde(default)]
+    pub bottom: FlexibleLengthAuto,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Location {
     pub x: f32,
     pub y: f32,
@@ -207,6 +288,79 @@ impl From<Axis> for taffy::FlexDirection {
     }
 }
 
+impl From<Align> for taffy::AlignItems {
+    fn from(align: Align) -> Self {
+        match align {
+            Align::Stretch => Self::Stretch,
+            Align::Start => Self::FlexStart,
+            Align::End => Self::FlexEnd,
+            Align::Center => Self::Center,
+            Align::Baseline => Self::Baseline,
+        }
+    }
+}
+
+impl From<Justify> for taffy::JustifyContent {
+    fn from(justify: Justify) -> Self {
+        match justify {
+            Justify::Start => Self::FlexStart,
+            Justify::End => Self::FlexEnd,
+            Justify::Center => Self::Center,
+            Justify::SpaceBetween => Self::SpaceBetween,
+            Justify::SpaceAround => Self::SpaceAround,
+            Justify::SpaceEvenly => Self::SpaceEvenly,
+        }
+    }
+}
+
+impl From<Wrap> for taffy::FlexWrap {
+    fn from(wrap: Wrap) -> Self {
+        match wrap {
+            Wrap::NoWrap => Self::NoWrap,
+            Wrap::Wrap => Self::Wrap,
+            Wrap::WrapReverse => Self::WrapReverse,
+        }
+    }
+}
+
+impl From<LayoutMode> for taffy::Display {
+    fn from(display: LayoutMode) -> Self {
+        match display {
+            LayoutMode::Flex => Self::Flex,
+            LayoutMode::Grid => Self::Grid,
+        }
+    }
+}
+
+impl From<Position> for taffy::Position {
+    fn from(position: Position) -> Self {
+        match position {
+            Position::Relative => Self::Relative,
+            Position::Absolute => Self::Absolute,
+        }
+    }
+}
+
+impl From<BoxSize> for taffy::Size<taffy::Dimension> {
+    fn from(size: BoxSize) -> Self {
+        Self {
+            width: size.width.into(),
+            height: size.height.into(),
+        }
+    }
+}
+
+impl From<Inset> for Rect<LengthPercentageAuto> {
+    fn from(inset: Inset) -> Self {
+        Self {
+            left: inset.left.into(),
+            right: inset.right.into(),
+            top: inset.top.into(),
+            bottom: inset.bottom.into(),
+        }
+    }
+}
+
 impl From<Point<f32>> for Location {
     fn from(value: Point<f32>) -> Self {
         Self {
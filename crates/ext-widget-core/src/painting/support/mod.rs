@@ -1,14 +1,19 @@
 mod decoration;
 mod flexible_length;
 mod flexible_length_auto;
+mod grid;
 mod layout;
 mod style;
 
 // re-export
-pub use decoration::{BoxBorder, BoxDecoration};
+pub use decoration::{BoxBorder, BoxDecoration, BoxShadow};
 pub use flexible_length::{FlexibleLength, ParseFlexibleLengthError};
 pub use flexible_length_auto::FlexibleLengthAuto;
+pub use grid::{
+    GridCell, GridTemplate, ParseTrackSizeError, TrackBreadth, TrackSize,
+};
 pub use layout::{
-    Axis, BoxConstraints, Location, Margin, Padding, SpacePolicy,
+    Align, Axis, BoxConstraints, BoxSize, Inset, Justify, LayoutMode, Location,
+    Margin, Padding, Position, SpacePolicy, Wrap,
 };
-pub use style::RectSize;
+pub use style::{RectSize, Region};
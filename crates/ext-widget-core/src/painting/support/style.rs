@@ -2,6 +2,8 @@ use std::ops::Sub;
 
 use serde::{Deserialize, Serialize};
 
+use super::Location;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct RectSize<T> {
     pub width: T,
@@ -60,3 +62,78 @@ impl From<RectSize<f32>> for skia_safe::Size {
         }
     }
 }
+
+/// A rectangle in logical pixels of a renderer.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Region {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Region {
+    pub fn new(location: Location, size: RectSize<f32>) -> Self {
+        Self {
+            x: location.x,
+            y: location.y,
+            width: size.width,
+            height: size.height,
+        }
+    }
+
+    pub fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+
+    /// The smallest region containing both.
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Self {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    /// Grow to whole pixels.
+    pub fn round_out(&self) -> Self {
+        let x = self.x.floor();
+        let y = self.y.floor();
+        Self {
+            x,
+            y,
+            width: self.right().ceil() - x,
+            height: self.bottom().ceil() - y,
+        }
+    }
+}
+
+impl From<Region> for skia_safe::Rect {
+    fn from(value: Region) -> Self {
+        skia_safe::Rect::from_xywh(value.x, value.y, value.width, value.height)
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::BTreeMap;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{painting::RectSize, term::TermSizeInfo};
+
+/// What a widget is placed next to.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Anchor {
+    #[default]
+    Cursor,
+    /// The current window.
+    Window,
+    Editor,
+    /// The popup menu, or the cursor if it's not visible.
+    Pum,
+}
+
+/// Which side of the anchor a widget is placed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Below,
+    Above,
+    Right,
+    Left,
+}
+
+/// Regions a widget should not cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Avoid {
+    CursorLine,
+    Cmdline,
+    /// Other floating windows.
+    Floats,
+}
+
+/// The area a widget is kept in.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Constrain {
+    /// The editor without the tabline, statusline and cmdline.
+    #[default]
+    Editor,
+    /// The current window.
+    Window,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlacementConfig {
+    #[serde(default)]
+    pub anchor: Anchor,
+    /// Sides to try in order, the first one that fits wins.
+    #[serde(default = "default_sides")]
+    pub sides: Vec<Side>,
+    #[serde(default = "default_avoid")]
+    pub avoid: Vec<Avoid>,
+    #[serde(default)]
+    pub constrain: Constrain,
+}
+
+impl Default for PlacementConfig {
+    fn default() -> Self {
+        Self {
+            anchor: Anchor::default(),
+            sides: default_sides(),
+            avoid: default_avoid(),
+            constrain: Constrain::default(),
+        }
+    }
+}
+
+fn default_sides() -> Vec<Side> {
+    vec![Side::Below, Side::Above]
+}
+
+fn default_avoid() -> Vec<Avoid> {
+    vec![Avoid::CursorLine, Avoid::Cmdline, Avoid::Floats]
+}
+
+/// Cells of the terminal, 0-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CellArea {
+    pub row: i32,
+    pub col: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl CellArea {
+    /// The top-left corner in pixels of the terminal.
+    pub fn pixel_position(&self, term_size: &TermSizeInfo) -> (u32, u32) {
+        (
+            (self.col as f32 * term_size.cell_width) as u32,
+            (self.row as f32 * term_size.cell_height) as u32,
+        )
+    }
+}
+
+/// The layout of Neovim around the cursor, collected by
+/// `require("external-widget.utils").placement_context()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlacementContext {
+    /// The window the context is collected for, the current one if it's
+    /// not given.
+    #[serde(default)]
+    pub win: i64,
+    /// 0-based (row, col) in the editor.
+    pub cursor: (i32, i32),
+    pub window: CellArea,
+    /// The editor without the tabline, statusline and cmdline.
+    pub editor: CellArea,
+    pub cmdline: CellArea,
+    #[serde(default)]
+    pub pum: Option<CellArea>,
+    #[serde(default, deserialize_with = "lua_list")]
+    pub floats: Vec<CellArea>,
+}
+
+/// A list from Lua, `vim.json.encode` encodes an empty table as `{}`.
+fn lua_list<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum LuaList<T> {
+        List(Vec<T>),
+        Table(BTreeMap<String, T>),
+    }
+
+    match LuaList::deserialize(deserializer)? {
+        LuaList::List(list) => Ok(list),
+        LuaList::Table(table) if table.is_empty() => Ok(vec![]),
+        LuaList::Table(_) => Err(de::Error::custom("expected a list")),
+    }
+}
+
+impl PlacementContext {
+    /// The area a widget is kept in, see [`PlacementConfig::constrain`].
+    pub fn bounds(&self, cfg: &PlacementConfig) -> CellArea {
+        match cfg.constrain {
+            Constrain::Editor => self.editor,
+            Constrain::Window => self.window,
+        }
+    }
+}
+
+/// A placed image, kept to place it again when the layout of Neovim
+/// changes.
+#[derive(Debug, Clone)]
+pub struct ImagePlacement {
+    pub config: PlacementConfig,
+    pub offset: (i32, i32),
+    /// Size of the image in pixels of the terminal.
+    pub size: RectSize<f32>,
+    /// The window the image is anchored to.
+    pub win: i64,
+    /// The cells covered by the image, None if it's hidden.
+    pub area: Option<CellArea>,
+}
+
+impl ImagePlacement {
+    pub fn new(
+        ctx: &PlacementContext, config: PlacementConfig, offset: (i32, i32),
+        term_size: &TermSizeInfo, size: RectSize<f32>,
+    ) -> Self {
+        let area = place(ctx, &config, term_size, size, offset);
+        Self {
+            config,
+            offset,
+            size,
+            win: ctx.win,
+            area: Some(area),
+        }
+    }
+
+    /// Place again in a new layout of the anchor window, `ctx` is None if
+    /// the window is not visible, which hides the image.
+    pub fn update(
+        &mut self, ctx: Option<&PlacementContext>, term_size: &TermSizeInfo,
+    ) -> Option<CellArea> {
+        self.area = ctx.map(|ctx| {
+            place(ctx, &self.config, term_size, self.size, self.offset)
+        });
+        self.area
+    }
+}
+
+/// A rectangle in pixels of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PixelRect {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl PixelRect {
+    fn from_cells(area: CellArea, term_size: &TermSizeInfo) -> Self {
+        Self {
+            x: area.col as f32 * term_size.cell_width,
+            y: area.row as f32 * term_size.cell_height,
+            width: area.width as f32 * term_size.cell_width,
+            height: area.height as f32 * term_size.cell_height,
+        }
+    }
+
+    fn right(&self) -> f32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> f32 {
+        self.y + self.height
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+
+    fn contains(&self, other: &Self) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    /// Move `self` into `bounds` as far as it fits.
+    fn clamp_into(mut self, bounds: &Self) -> Self {
+        self.x = self.x.min(bounds.right() - self.width).max(bounds.x);
+        self.y = self.y.min(bounds.bottom() - self.height).max(bounds.y);
+        self
+    }
+
+    fn to_cells(self, term_size: &TermSizeInfo) -> CellArea {
+        // sizes are snapped to cells, only float errors are rounded up
+        let cells = |len: f32, cell: f32| (len / cell * 1e3).round() / 1e3;
+        CellArea {
+            row: cells(self.y, term_size.cell_height).round() as i32,
+            col: cells(self.x, term_size.cell_width).round() as i32,
+            width: cells(self.width, term_size.cell_width).ceil() as i32,
+            height: cells(self.height, term_size.cell_height).ceil() as i32,
+        }
+    }
+}
+
+/**
+ * Place a widget of `size` pixels, like `nvim_open_win` does for floats.
+ *
+ * The sides of the anchor are tried in order, a side fits if the widget is
+ * inside the bounds and covers no region to avoid. It's moved along the
+ * side to fit, and moved away from the anchor by `offset` cells, which is
+ * mirrored on the above and left sides. If no side fits, the side with the
+ * most room is used and the widget is moved into the bounds.
+ */
+pub fn place(
+    ctx: &PlacementContext, cfg: &PlacementConfig, term_size: &TermSizeInfo,
+    size: RectSize<f32>, offset: (i32, i32),
+) -> CellArea {
+    let rect = |area| PixelRect::from_cells(area, term_size);
+    let cursor = CellArea {
+        row: ctx.cursor.0,
+        col: ctx.cursor.1,
+        width: 1,
+        height: 1,
+    };
+    let anchor = match cfg.anchor {
+        Anchor::Cursor => rect(cursor),
+        Anchor::Window => rect(ctx.window),
+        Anchor::Editor => rect(ctx.editor),
+        Anchor::Pum => rect(ctx.pum.unwrap_or(cursor)),
+    };
+    let bounds = rect(ctx.bounds(cfg));
+    let mut avoid = Vec::new();
+    for region in &cfg.avoid {
+        match region {
+            Avoid::CursorLine => avoid.push(rect(CellArea {
+                col: ctx.editor.col,
+                width: ctx.editor.width,
+                ..cursor
+            })),
+            Avoid::Cmdline => avoid.push(rect(ctx.cmdline)),
+            Avoid::Floats => avoid.extend(ctx.floats.iter().map(|f| rect(*f))),
+        }
+    }
+
+    let offset_x = offset.0 as f32 * term_size.cell_width;
+    let offset_y = offset.1 as f32 * term_size.cell_height;
+    let candidates: Vec<_> = cfg
+        .sides
+        .iter()
+        .map(|side| {
+            let (x, y) = match side {
+                Side::Below => {
+                    (anchor.x + offset_x, anchor.bottom() + offset_y)
+                }
+                Side::Above => {
+                    (anchor.x + offset_x, anchor.y - size.height - offset_y)
+                }
+                Side::Right => (anchor.right() + offset_x, anchor.y + offset_y),
+                Side::Left => {
+                    (anchor.x - size.width - offset_x, anchor.y + offset_y)
+                }
+            };
+            let placed = PixelRect {
+                x,
+                y,
+                width: size.width,
+                height: size.height,
+            };
+            // slide along the side, never onto the anchor
+            let slid = match side {
+                Side::Below | Side::Above => PixelRect {
+                    x: placed.clamp_into(&bounds).x,
+                    ..placed
+                },
+                Side::Right | Side::Left => PixelRect {
+                    y: placed.clamp_into(&bounds).y,
+                    ..placed
+                },
+            };
+            (*side, slid)
+        })
+        .collect();
+
+    let fits = |rect: &PixelRect| {
+        bounds.contains(rect) && !avoid.iter().any(|a| a.intersects(rect))
+    };
+    if let Some((_, rect)) = candidates.iter().find(|(_, rect)| fits(rect)) {
+        return rect.to_cells(term_size);
+    }
+
+    let room = |side: Side| match side {
+        Side::Below => bounds.bottom() - anchor.bottom(),
+        Side::Above => anchor.y - bounds.y,
+        Side::Right => bounds.right() - anchor.right(),
+        Side::Left => anchor.x - bounds.x,
+    };
+    let best = candidates
+        .iter()
+        .max_by(|(a, _), (b, _)| room(*a).total_cmp(&room(*b)))
+        .map(|(_, rect)| *rect)
+        .unwrap_or(PixelRect {
+            x: anchor.x,
+            y: anchor.bottom(),
+            width: size.width,
+            height: size.height,
+        });
+    best.clamp_into(&bounds).to_cells(term_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nvim::NvimTermSize;
+
+    use super::*;
+
+    fn term_size() -> TermSizeInfo {
+        // 10x20 cells
+        TermSizeInfo::new_from_nvim_term(NvimTermSize {
+            row: 40,
+            col: 100,
+            xpixel: 1000,
+            ypixel: 800,
+        })
+    }
+
+    fn area(row: i32, col: i32, width: i32, height: i32) -> CellArea {
+        CellArea {
+            row,
+            col,
+            width,
+            height,
+        }
+    }
+
+    fn context(cursor: (i32, i32)) -> PlacementContext {
+        PlacementContext {
+            win: 1000,
+            cursor,
+            window: area(0, 0, 60, 38),
+            editor: area(0, 0, 100, 39),
+            cmdline: area(39, 0, 100, 1),
+            pum: None,
+            floats: vec![],
+        }
+    }
+
+    fn place_cells(
+        ctx: &PlacementContext, cfg: &PlacementConfig, cells: (i32, i32),
+    ) -> CellArea {
+        let size = RectSize {
+            width: cells.0 as f32 * 10.0,
+            height: cells.1 as f32 * 20.0,
+        };
+        place(ctx, cfg, &term_size(), size, (0, 0))
+    }
+
+    #[test]
+    fn test_place_below_and_flip() {
+        let cfg = PlacementConfig::default();
+        let placed = place_cells(&context((5, 95)), &cfg, (20, 10));
+        // slid left to stay in the editor
+        assert_eq!(placed, area(6, 80, 20, 10));
+
+        // no room below the cursor line
+        let placed = place_cells(&context((35, 10)), &cfg, (20, 10));
+        assert_eq!(placed, area(25, 10, 20, 10));
+    }
+
+    #[test]
+    fn test_place_avoid_floats() {
+        let cfg = PlacementConfig::default();
+        let mut ctx = context((20, 10));
+        ctx.floats.push(area(25, 0, 40, 5));
+        let placed = place_cells(&ctx, &cfg, (20, 10));
+        assert_eq!(placed, area(10, 10, 20, 10));
+
+        // nothing fits, the side with the most room is used
+        let placed = place_cells(&ctx, &cfg, (20, 30));
+        assert_eq!(placed, area(0, 10, 20, 30));
+    }
+
+    #[test]
+    fn test_context_empty_floats() {
+        let ctx: PlacementContext = serde_json::from_value(serde_json::json!({
+            "cursor": [1, 2],
+            "window": { "row": 0, "col": 0, "width": 60, "height": 38 },
+            "editor": { "row": 0, "col": 0, "width": 100, "height": 39 },
+            "cmdline": { "row": 39, "col": 0, "width": 100, "height": 1 },
+            "floats": {},
+        }))
+        .unwrap();
+        assert!(ctx.floats.is_empty());
+    }
+
+    #[test]
+    fn test_place_in_window() {
+        let cfg = PlacementConfig {
+            anchor: Anchor::Pum,
+            sides: vec![Side::Right, Side::Left],
+            constrain: Constrain::Window,
+            ..Default::default()
+        };
+        let mut ctx = context((10, 30));
+        ctx.pum = Some(area(11, 30, 15, 8));
+        let placed = place_cells(&ctx, &cfg, (20, 10));
+        assert_eq!(placed, area(11, 10, 20, 10));
+    }
+
+    #[test]
+    fn test_update_placement() {
+        let size = RectSize {
+            width: 200.0,
+            height: 200.0,
+        };
+        let cfg = PlacementConfig::default();
+        let ctx = context((5, 10));
+        let mut placement =
+            ImagePlacement::new(&ctx, cfg, (0, 0), &term_size(), size);
+        assert_eq!(placement.area, Some(area(6, 10, 20, 10)));
+
+        // the window is scrolled, the cursor line moves up
+        let ctx = context((2, 10));
+        let placed = placement.update(Some(&ctx), &term_size());
+        assert_eq!(placed, Some(area(3, 10, 20, 10)));
+
+        assert_eq!(placement.update(None, &term_size()), None);
+        assert_eq!(placement.area, None);
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    env::{in_ssh, in_tmux},
+    nvim::NvimTermSize,
+    tmux::enable_tmux_pass_through,
+};
+
+/// Version of the binary, the Lua plugin is expected to be the same.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Bumped on incompatible changes of requests, notifications or their
+/// arguments. The Lua plugin reads it from this file, keep it a literal.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The reply of `hello`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HelloInfo {
+    pub version: String,
+    pub protocol: u32,
+    /// Names of supported requests.
+    pub methods: Vec<String>,
+    /// Names of supported notifications.
+    pub notifications: Vec<String>,
+    pub capabilities: Capabilities,
+}
+
+impl HelloInfo {
+    pub fn new(
+        mut methods: Vec<String>, mut notifications: Vec<String>,
+        capabilities: Capabilities,
+    ) -> Self {
+        methods.sort();
+        notifications.sort();
+        Self {
+            version: VERSION.to_string(),
+            protocol: PROTOCOL_VERSION,
+            methods,
+            notifications,
+            capabilities,
+        }
+    }
+}
+
+/// What the terminal of the client supports.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub tmux: bool,
+    /// `allow-passthrough` of tmux, `None` if not in tmux.
+    pub tmux_passthrough: Option<bool>,
+    pub ssh: bool,
+    /// Whether the terminal reports its size in pixels, images can't be
+    /// placed without it.
+    pub pixel_size: bool,
+}
+
+impl Capabilities {
+    pub async fn detect(term_size: Option<&NvimTermSize>) -> Self {
+        let tmux = in_tmux();
+        let tmux_passthrough = match tmux {
+            true => enable_tmux_pass_through().await.ok(),
+            false => None,
+        };
+        Self {
+            tmux,
+            tmux_passthrough,
+            ssh: in_ssh(),
+            pixel_size: term_size
+                .is_some_and(|size| size.xpixel > 0 && size.ypixel > 0),
+        }
+    }
+}
+
+/// The error of an unknown request or notification, with the version so a
+/// stale binary is easy to spot.
+pub fn unknown_method_error(name: &str) -> String {
+    format!(
+        "Unknown method: {} (ext-widget {}, protocol {})",
+        name, VERSION, PROTOCOL_VERSION
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        unknown_method_error, Capabilities, HelloInfo, PROTOCOL_VERSION,
+        VERSION,
+    };
+
+    #[test]
+    fn test_hello_info() {
+        let info = HelloInfo::new(
+            vec!["stop_hover".to_string(), "hello".to_string()],
+            vec!["update_config".to_string(), "layout_changed".to_string()],
+            Capabilities {
+                pixel_size: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.protocol, PROTOCOL_VERSION);
+        assert_eq!(info.methods, ["hello", "stop_hover"]);
+        assert_eq!(info.notifications, ["layout_changed", "update_config"]);
+
+        // the Lua plugin reads the fields by name
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["protocol"], PROTOCOL_VERSION);
+        assert_eq!(json["capabilities"]["pixel_size"], true);
+        assert!(json["capabilities"]["tmux_passthrough"].is_null());
+    }
+
+    #[test]
+    fn test_unknown_method_error() {
+        let msg = unknown_method_error("hello");
+        // the Lua plugin looks for this prefix to tell a stale binary
+        assert!(msg.starts_with("Unknown method: hello "));
+        assert!(msg.contains(VERSION));
+        assert!(msg.contains(&format!("protocol {}", PROTOCOL_VERSION)));
+    }
+}
@@ -0,0 +1,719 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use anyhow::bail;
+use skia_safe::IRect;
+
+use crate::{
+    nvim::{
+        ChromeTheme, ColorSource, FontSize, HighlightTheme, HoverConfig, Scale,
+    },
+    painting::{
+        BoxBorder, BoxConstraints, BoxDecoration, BoxShadow, Color,
+        FlexibleLengthAuto, Location, Margin, Padding, RectSize, Renderer,
+    },
+    term::TermSizeInfo,
+    widgets::{
+        BoxOptions, Container, DocumentProvider, MarkdownDocumentBuilder,
+        StaticProvider, Widget, WidgetTree,
+    },
+};
+
+/// Size of terminal cells at scale 1, in pixels.
+pub const BASE_CELL_WIDTH: f32 = 10.0;
+pub const BASE_CELL_HEIGHT: f32 = 20.0;
+
+/// How logical pixels of widgets map to pixels of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayMetrics {
+    /// Device pixels per logical pixel.
+    pub scale: f32,
+    /// Size of a terminal cell, in device pixels.
+    pub cell_width: f32,
+    pub cell_height: f32,
+}
+
+impl DisplayMetrics {
+    pub fn new(scale: Scale, term_size: &TermSizeInfo) -> Self {
+        let (cell_width, cell_height) =
+            (term_size.cell_width, term_size.cell_height);
+        // no pixel size from the terminal
+        if !cell_width.is_normal() || !cell_height.is_normal() {
+            let scale = match scale {
+                Scale::Auto => 1.0,
+                Scale::Fixed(scale) => scale,
+            };
+            return Self {
+                scale,
+                cell_width: BASE_CELL_WIDTH * scale,
+                cell_height: BASE_CELL_HEIGHT * scale,
+            };
+        }
+        let scale = match scale {
+            Scale::Auto => (cell_height / BASE_CELL_HEIGHT).clamp(0.5, 4.0),
+            Scale::Fixed(scale) => scale,
+        };
+        Self {
+            scale,
+            cell_width,
+            cell_height,
+        }
+    }
+
+    /// A font size in logical pixels.
+    pub fn font_size(&self, size: FontSize) -> f32 {
+        match size {
+            FontSize::Pixels(px) => px,
+            // 96 dpi
+            FontSize::Points(pt) => pt * 4.0 / 3.0,
+            FontSize::Cells(cells) => cells * self.cell_height / self.scale,
+        }
+    }
+
+    /// Logical pixels of `cells` terminal cells in width.
+    pub fn width_of_cells(&self, cells: i32) -> f32 {
+        cells as f32 * self.cell_width / self.scale
+    }
+
+    /// Device pixels of a logical length.
+    pub fn to_device(&self, length: f32) -> f32 {
+        length * self.scale
+    }
+
+    /// Device pixels of a logical size.
+    pub fn size_to_device(&self, size: RectSize<f32>) -> RectSize<f32> {
+        RectSize {
+            width: self.to_device(size.width),
+            height: self.to_device(size.height),
+        }
+    }
+
+    /// Round a logical size up to whole cells.
+    pub fn snap_up(&self, size: RectSize<f32>) -> RectSize<f32> {
+        self.snap(size, |cells| cells.ceil())
+    }
+
+    /// Round a logical size down to whole cells, at least one cell.
+    pub fn snap_down(&self, size: RectSize<f32>) -> RectSize<f32> {
+        self.snap(size, |cells| cells.floor().max(1.0))
+    }
+
+    fn snap(
+        &self, size: RectSize<f32>, round: impl Fn(f32) -> f32,
+    ) -> RectSize<f32> {
+        // logical size of a cell
+        let cell_width = self.cell_width / self.scale;
+        let cell_height = self.cell_height / self.scale;
+        // ignore float errors of sizes already snapped
+        let cells =
+            |len: f32, cell: f32| round((len / cell * 1e3).round() / 1e3);
+        RectSize {
+            width: cells(size.width, cell_width) * cell_width,
+            height: cells(size.height, cell_height) * cell_height,
+        }
+    }
+}
+
+impl Default for DisplayMetrics {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            cell_width: BASE_CELL_WIDTH,
+            cell_height: BASE_CELL_HEIGHT,
+        }
+    }
+}
+
+/// Options of rendering markdown without Neovim.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Width of the image in logical pixels.
+    pub width: f32,
+    /// Images are cut at this height, in logical pixels.
+    pub max_height: f32,
+    /// Device pixels per logical pixel.
+    pub scale: f32,
+    pub normal_font: Vec<String>,
+    pub normal_font_size: f32,
+    pub mono_font: Vec<String>,
+    pub mono_font_size: f32,
+    /// Runtime paths to look for `parser/<lang>.so` and
+    /// `queries/<lang>/highlights.scm`.
+    pub runtime_paths: Vec<PathBuf>,
+    /// The chrome around the document.
+    pub theme: ChromeTheme,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        let hover = HoverConfig::default();
+        let metrics = DisplayMetrics::default();
+        Self {
+            width: 800.0,
+            max_height: 10000.0,
+            scale: 1.0,
+            normal_font: hover.normal_font,
+            normal_font_size: metrics.font_size(hover.normal_font_size),
+            mono_font: hover.mono_font,
+            mono_font_size: metrics.font_size(hover.mono_font_size),
+            runtime_paths: Vec::new(),
+            theme: ChromeTheme::hover(),
+        }
+    }
+}
+
+/// The chrome of a widget, with colors of its theme resolved.
+#[derive(Debug, Clone)]
+pub(crate) struct Chrome {
+    decoration: BoxDecoration,
+    padding: f32,
+}
+
+impl Chrome {
+    pub(crate) async fn resolve<P: DocumentProvider>(
+        provider: &P, theme: &ChromeTheme,
+    ) -> Self {
+        let mut background = resolve_color(provider, &theme.background, false)
+            .await
+            .unwrap_or_default();
+        background.alpha = (background.alpha as f32 * theme.opacity) as u8;
+        let border_color = resolve_color(provider, &theme.border.color, true)
+            .await
+            .unwrap_or(Color::new(0));
+        let shadow = match &theme.shadow {
+            Some(shadow) => Some(BoxShadow {
+                color: resolve_color(provider, &shadow.color, false)
+                    .await
+                    .unwrap_or_default(),
+                blur_radius: shadow.blur,
+                offset: Location {
+                    x: shadow.x_offset,
+                    y: shadow.y_offset,
+                },
+            }),
+            None => None,
+        };
+        Self {
+            decoration: BoxDecoration {
+                color: background,
+                border: BoxBorder {
+                    width: theme.border.width,
+                    color: border_color,
+                    radius: theme.border.radius.into(),
+                },
+                shadow,
+            },
+            padding: theme.padding,
+        }
+    }
+
+    /// Room around the box for the shadow.
+    fn margin(&self) -> f32 {
+        self.decoration
+            .shadow
+            .as_ref()
+            .map(|shadow| shadow.extent().ceil())
+            .unwrap_or(0.0)
+    }
+
+    /// Wrap `child` into the chrome. The shadow needs room, the container
+    /// has a margin as wide as it.
+    pub(crate) fn container(&self, child: Rc<dyn Widget>) -> Container {
+        let margin = self.margin();
+        Container::new_with_child(
+            self.decoration.clone(),
+            BoxOptions {
+                padding: Padding::all(self.padding.into()),
+                margin: Margin::all(margin.into()),
+                ..Default::default()
+            },
+            child,
+        )
+    }
+
+    /// Make the chrome at the root of `tree` exactly `outer_size` with its
+    /// margin, the padding grows to fill it. `None` sizes it to the content.
+    fn fit(
+        &self, tree: &mut WidgetTree, outer_size: Option<RectSize<f32>>,
+    ) -> anyhow::Result<()> {
+        let margin = self.margin();
+        let mut constraints = BoxConstraints::default();
+        if let Some(size) = outer_size {
+            let width = FlexibleLengthAuto::Fixed(size.width - margin * 2.0);
+            let height = FlexibleLengthAuto::Fixed(size.height - margin * 2.0);
+            constraints = BoxConstraints {
+                min_width: width,
+                max_width: width,
+                min_height: height,
+                max_height: height,
+            };
+        }
+        tree.set_root_constraints(constraints)
+    }
+
+    /// The size of a laid out chrome, with its margin.
+    fn outer_size(&self, tree: &WidgetTree) -> anyhow::Result<RectSize<f32>> {
+        let size = tree.result_size()?;
+        let margin = self.margin();
+        Ok(RectSize {
+            width: size.width + margin * 2.0,
+            height: size.height + margin * 2.0,
+        })
+    }
+}
+
+/// The color of `source`, the foreground or background of a highlight
+/// group.
+async fn resolve_color<P: DocumentProvider>(
+    provider: &P, source: &ColorSource, foreground: bool,
+) -> Option<Color> {
+    match source {
+        ColorSource::Color(color) => Some(*color),
+        ColorSource::Highlight(group) => {
+            let hl = provider.highlight(group).await?;
+            if foreground {
+                hl.guifg.or(hl.fg)
+            } else {
+                hl.guibg.or(hl.bg)
+            }
+        }
+    }
+}
+
+/// Render a markdown document to a PNG image, like the hover window but
+/// without Neovim. Highlights come from `theme`.
+pub async fn render_markdown_png(
+    text: &str, theme: HighlightTheme, opts: &RenderOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let provider = StaticProvider::new(theme, opts.runtime_paths.clone());
+    let chrome = Chrome::resolve(&provider, &opts.theme).await;
+    let document = MarkdownDocumentBuilder {
+        provider,
+        normal_font: opts.normal_font.clone(),
+        normal_font_size: opts.normal_font_size,
+        mono_font: opts.mono_font.clone(),
+        mono_font_size: opts.mono_font_size,
+    }
+    .build(text)
+    .await?;
+
+    let mut widget_tree = WidgetTree::new();
+    widget_tree.new_root(Rc::new(chrome.container(document)))?;
+    widget_tree.compute_layout(opts.width, opts.max_height)?;
+    let image_size = widget_tree.result_size()?;
+
+    let renderer = Rc::new(RefCell::new(Renderer::new_scaled(
+        (opts.width * opts.scale).ceil() as u32,
+        (image_size.height.min(opts.max_height) * opts.scale).ceil() as u32,
+        opts.scale,
+    )?));
+    widget_tree.paint(renderer.clone())?;
+
+    let data = renderer.borrow_mut().snapshot_png_raw()?;
+    Ok(data)
+}
+
+/**
+ * A hover document kept after painting, so it can be laid out again when
+ * the room for it changes.
+ *
+ * Font sizes are resolved once when it's built, only the layout follows new
+ * metrics.
+ */
+pub struct HoverWidget {
+    tree: WidgetTree,
+    chrome: Chrome,
+    /// `max_width` and `max_height` of the window config, in logical pixels.
+    max_size: RectSize<f32>,
+    /// The metrics and max size of the last layout.
+    last_layout: Option<(DisplayMetrics, RectSize<f32>)>,
+    painted: Option<PaintedHover>,
+}
+
+/// The canvas of the last paint of a [`HoverWidget`], kept to repaint
+/// where it's damaged.
+struct PaintedHover {
+    renderer: Rc<RefCell<Renderer>>,
+    /// Size of the document in logical pixels, in whole cells.
+    size: RectSize<f32>,
+    /// Bounds of each page in device pixels.
+    pages: Vec<IRect>,
+}
+
+/// A changed part of a page, see [`HoverWidget::repaint`].
+#[derive(Debug)]
+pub struct PagePatch {
+    pub page: usize,
+    /// Top left corner of the patch in the page, in device pixels.
+    pub x: u32,
+    pub y: u32,
+    /// Png of the changed pixels.
+    pub patch: Vec<u8>,
+    /// Png of the whole page after the change.
+    pub image: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Repaint {
+    Unchanged,
+    /// Only parts of the pages changed.
+    Patches(Vec<PagePatch>),
+    /// The size of the document changed, all pages are painted again like
+    /// [`HoverWidget::render`].
+    Resized(Vec<Vec<u8>>, RectSize<f32>),
+}
+
+impl HoverWidget {
+    /// Build a hover document, highlights and parsers come from `provider`.
+    pub async fn build<P: DocumentProvider>(
+        provider: P, md: &str, cfg: &HoverConfig, theme: &ChromeTheme,
+        metrics: DisplayMetrics,
+    ) -> anyhow::Result<Self> {
+        let chrome = Chrome::resolve(&provider, theme).await;
+        let document = MarkdownDocumentBuilder {
+            provider,
+            normal_font: cfg.normal_font.clone(),
+            normal_font_size: metrics.font_size(cfg.normal_font_size),
+            mono_font: cfg.mono_font.clone(),
+            mono_font_size: metrics.font_size(cfg.mono_font_size),
+        }
+        .build(md)
+        .await?;
+        Self::new(chrome, document, cfg)
+    }
+
+    /// Wrap `document` into `chrome`.
+    pub(crate) fn new(
+        chrome: Chrome, document: Rc<dyn Widget>, cfg: &HoverConfig,
+    ) -> anyhow::Result<Self> {
+        let mut tree = WidgetTree::new();
+        tree.new_root(Rc::new(chrome.container(document)))?;
+        Ok(Self {
+            tree,
+            chrome,
+            max_size: RectSize {
+                width: cfg.window.max_width,
+                height: cfg.window.max_height,
+            },
+            last_layout: None,
+            painted: None,
+        })
+    }
+
+    /// Call `waker` when the state of a widget changes, the document should
+    /// be painted again with [`HoverWidget::repaint`].
+    pub fn set_waker(&self, waker: impl Fn() + 'static) {
+        self.tree.set_waker(waker);
+    }
+
+    /// The size the document is laid out in, whole cells within
+    /// `max_width` logical pixels and the window config.
+    fn layout_size(
+        &self, metrics: &DisplayMetrics, max_width: f32,
+    ) -> RectSize<f32> {
+        metrics.snap_down(RectSize {
+            width: self.max_size.width.min(max_width),
+            height: self.max_size.height,
+        })
+    }
+
+    /// Whether laying out with `metrics` within `max_width` changes the
+    /// last layout.
+    pub fn needs_layout(
+        &self, metrics: DisplayMetrics, max_width: f32,
+    ) -> bool {
+        let max_size = self.layout_size(&metrics, max_width);
+        match self.last_layout {
+            Some((last_metrics, last_size)) => {
+                last_metrics != metrics
+                    || last_size.width != max_size.width
+                    || last_size.height != max_size.height
+            }
+            None => true,
+        }
+    }
+
+    /// Lay out the document within `max_size`, returns its size in whole
+    /// cells.
+    fn layout(
+        &mut self, metrics: &DisplayMetrics, max_size: RectSize<f32>,
+    ) -> anyhow::Result<RectSize<f32>> {
+        // layout twice, the second time in whole cells
+        self.chrome.fit(&mut self.tree, None)?;
+        self.tree.compute_layout(max_size.width, max_size.height)?;
+        let mut size = metrics.snap_up(self.chrome.outer_size(&self.tree)?);
+        size.width = size.width.min(max_size.width);
+        self.chrome.fit(&mut self.tree, Some(size))?;
+        self.tree.compute_layout(max_size.width, max_size.height)?;
+        Ok(size)
+    }
+
+    /// Lay out the document within `max_width` logical pixels, and paint it.
+    ///
+    /// The image is snapped to whole cells of the terminal. Returns the
+    /// images of each page, at most `window.max_height` high, and the size
+    /// of a page in device pixels.
+    pub fn render(
+        &mut self, metrics: DisplayMetrics, max_width: f32,
+    ) -> anyhow::Result<(Vec<Vec<u8>>, RectSize<f32>)> {
+        let max_size = self.layout_size(&metrics, max_width);
+        self.last_layout = Some((metrics, max_size));
+        let size = self.layout(&metrics, max_size)?;
+
+        let image_size = metrics.size_to_device(size);
+        let renderer = Rc::new(RefCell::new(Renderer::new_scaled(
+            image_size.width.round() as u32,
+            image_size.height.round() as u32,
+            metrics.scale,
+        )?));
+        self.tree.paint(renderer.clone())?;
+
+        let page_height = metrics.to_device(max_size.height);
+        let step = metrics.to_device(200.);
+        let data = renderer.borrow_mut().snapshot_png_raw_with_steps(
+            image_size.width,
+            image_size.height,
+            page_height,
+            step,
+        )?;
+        self.painted = Some(PaintedHover {
+            renderer,
+            size,
+            pages: Renderer::page_bounds(
+                image_size.width,
+                image_size.height,
+                page_height,
+                step,
+            ),
+        });
+        let page_size = RectSize {
+            width: image_size.width,
+            height: image_size.height.min(page_height),
+        };
+        Ok((data, page_size))
+    }
+
+    /**
+     * Build the widgets whose state changed, and paint again only where the
+     * document is damaged, see [`WidgetTree::damage`].
+     *
+     * The pages are patched if the size of the document is kept, so only
+     * the changed pixels need to be sent to the terminal.
+     */
+    pub fn repaint(&mut self) -> anyhow::Result<Repaint> {
+        let (Some((metrics, max_size)), Some(painted)) =
+            (self.last_layout, self.painted.as_ref())
+        else {
+            bail!("The widget is not rendered yet");
+        };
+        let renderer = painted.renderer.clone();
+        let last_size = painted.size;
+        let pages = painted.pages.clone();
+        if !self.tree.rebuild()? {
+            return Ok(Repaint::Unchanged);
+        }
+
+        let size = self.layout(&metrics, max_size)?;
+        if size.width != last_size.width || size.height != last_size.height {
+            let (images, page_size) = self.render(metrics, max_size.width)?;
+            return Ok(Repaint::Resized(images, page_size));
+        }
+        let Some(damage) = self.tree.damage()? else {
+            return Ok(Repaint::Unchanged);
+        };
+        self.tree.paint_region(renderer.clone(), damage)?;
+
+        let mut renderer = renderer.borrow_mut();
+        let Some(bounds) = renderer.device_bounds(damage) else {
+            return Ok(Repaint::Unchanged);
+        };
+        let mut patches = vec![];
+        for (page, page_bounds) in pages.iter().enumerate() {
+            let Some(patch_bounds) = IRect::intersect(bounds, page_bounds)
+            else {
+                continue;
+            };
+            let image = match pages.len() {
+                1 => renderer.snapshot_png_raw()?,
+                _ => renderer.snapshot_png_bounds(*page_bounds)?,
+            };
+            patches.push(PagePatch {
+                page,
+                x: (patch_bounds.left - page_bounds.left) as u32,
+                y: (patch_bounds.top - page_bounds.top) as u32,
+                patch: renderer.snapshot_png_bounds(patch_bounds)?,
+                image,
+            });
+        }
+        Ok(Repaint::Patches(patches))
+    }
+}
+
+/// Render a hover document, highlights and parsers come from `provider`.
+///
+/// Like [`HoverWidget::render`], but the document is thrown away.
+pub async fn render_hover_images<P: DocumentProvider>(
+    provider: P, md: &str, cfg: &HoverConfig, theme: &ChromeTheme,
+    metrics: DisplayMetrics,
+) -> anyhow::Result<(Vec<Vec<u8>>, RectSize<f32>)> {
+    let mut widget =
+        HoverWidget::build(provider, md, cfg, theme, metrics).await?;
+    widget.render(metrics, f32::INFINITY)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::{
+        nvim::NvimTermSize,
+        test_utils::{test_font_collection, TEST_FONT, TEST_MONO_FONT},
+        widgets::{BuildContext, StatefulWidget, StatefulWidgetPod},
+    };
+
+    use super::*;
+
+    fn term_size(ypixel: u32) -> TermSizeInfo {
+        TermSizeInfo::new_from_nvim_term(NvimTermSize {
+            row: 50,
+            col: 200,
+            xpixel: 2000,
+            ypixel,
+        })
+    }
+
+    #[test]
+    fn test_display_metrics() {
+        // 40px cells, a HiDPI screen
+        let metrics = DisplayMetrics::new(Scale::Auto, &term_size(2000));
+        assert_eq!(metrics.scale, 2.0);
+        assert_eq!(metrics.font_size(FontSize::Pixels(20.0)), 20.0);
+        assert_eq!(metrics.font_size(FontSize::Points(12.0)), 16.0);
+        assert_eq!(metrics.font_size(FontSize::Cells(1.0)), 20.0);
+        assert_eq!(metrics.to_device(100.0), 200.0);
+
+        let metrics = DisplayMetrics::new(Scale::Fixed(1.5), &term_size(2000));
+        assert_eq!(metrics.scale, 1.5);
+
+        // no pixel size
+        let metrics = DisplayMetrics::new(Scale::Auto, &term_size(0));
+        assert_eq!(metrics, DisplayMetrics::default());
+    }
+
+    #[test]
+    fn test_snap_to_cells() {
+        // 10x40 cells at scale 2, 5x20 logical pixels
+        let metrics = DisplayMetrics::new(Scale::Auto, &term_size(2000));
+        let size = RectSize {
+            width: 101.0,
+            height: 39.0,
+        };
+        let up = metrics.snap_up(size);
+        assert_eq!((up.width, up.height), (105.0, 40.0));
+        let down = metrics.snap_down(size);
+        assert_eq!((down.width, down.height), (100.0, 20.0));
+        assert_eq!(metrics.snap_up(up).width, 105.0);
+    }
+
+    #[tokio::test]
+    async fn test_hover_widget_reflow() -> anyhow::Result<()> {
+        let metrics = DisplayMetrics::default();
+        let md = "Long lines wrap when the room shrinks. ".repeat(20);
+        let cfg = HoverConfig {
+            normal_font: vec![TEST_FONT.to_string()],
+            mono_font: vec![TEST_MONO_FONT.to_string()],
+            ..Default::default()
+        };
+        let provider = StaticProvider::default();
+        let chrome = Chrome::resolve(&provider, &ChromeTheme::hover()).await;
+        let document = MarkdownDocumentBuilder {
+            provider,
+            normal_font: cfg.normal_font.clone(),
+            normal_font_size: metrics.font_size(cfg.normal_font_size),
+            mono_font: cfg.mono_font.clone(),
+            mono_font_size: metrics.font_size(cfg.mono_font_size),
+        }
+        .build_with_fonts(&md, &test_font_collection())
+        .await?;
+        let mut widget = HoverWidget::new(chrome, document, &cfg)?;
+        let (_, wide) = widget.render(metrics, 800.0)?;
+        assert!(!widget.needs_layout(metrics, 800.0));
+        // the same cells
+        assert!(!widget.needs_layout(metrics, 805.0));
+
+        assert!(widget.needs_layout(metrics, 305.0));
+        let (_, narrow) = widget.render(metrics, 305.0)?;
+        assert_eq!(narrow.width, 300.0);
+        assert!(narrow.height > wide.height);
+        Ok(())
+    }
+
+    /// A square of the color in its state.
+    #[derive(Debug)]
+    struct Swatch;
+
+    impl StatefulWidget for Swatch {
+        type State = u32;
+
+        fn build(
+            &self, color: &u32, _context: &BuildContext,
+        ) -> Rc<dyn Widget> {
+            let size = FlexibleLengthAuto::Fixed(20.0);
+            Rc::new(Container::new(
+                BoxDecoration {
+                    color: Color::new(*color),
+                    border: BoxBorder::NONE,
+                    shadow: None,
+                },
+                BoxOptions {
+                    constraints: BoxConstraints {
+                        min_width: size,
+                        max_width: size,
+                        min_height: size,
+                        max_height: size,
+                    },
+                    ..Default::default()
+                },
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hover_widget_repaint() -> anyhow::Result<()> {
+        let metrics = DisplayMetrics::default();
+        let provider = StaticProvider::default();
+        let chrome = Chrome::resolve(&provider, &ChromeTheme::hover()).await;
+        let swatch = Rc::new(StatefulWidgetPod::new(Swatch, 0xf7768e));
+        let mut widget =
+            HoverWidget::new(chrome, swatch.clone(), &HoverConfig::default())?;
+        let wakes = Rc::new(Cell::new(0));
+        widget.set_waker({
+            let wakes = wakes.clone();
+            move || wakes.set(wakes.get() + 1)
+        });
+        widget.render(metrics, 800.0)?;
+        assert!(matches!(widget.repaint()?, Repaint::Unchanged));
+
+        // woken once until painted again
+        swatch.set_state(|color| *color = 0x9ece6a);
+        swatch.set_state(|color| *color = 0x7aa2f7);
+        assert_eq!(wakes.get(), 1);
+
+        let Repaint::Patches(patches) = widget.repaint()? else {
+            panic!("the size is kept, only the swatch is patched");
+        };
+        assert_eq!(patches.len(), 1);
+        let patch = &patches[0];
+        // inside the padding of the chrome
+        assert_eq!((patch.page, patch.x, patch.y), (0, 8, 8));
+        let image = skia_safe::Image::from_encoded(skia_safe::Data::new_copy(
+            &patch.patch,
+        ))
+        .expect("the patch is a png");
+        assert_eq!((image.width(), image.height()), (20, 20));
+        assert!(matches!(widget.repaint()?, Repaint::Unchanged));
+
+        swatch.set_state(|color| *color = 0xe0af68);
+        assert_eq!(wakes.get(), 2);
+        Ok(())
+    }
+}
@@ -1,38 +1,97 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     num::NonZeroU32,
     sync::{atomic::AtomicU32, Arc},
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use parking_lot::Mutex;
+use serde::Serialize;
+use skia_safe::{
+    codec::{Options as CodecOptions, ZeroInitialized},
+    Codec, Data, EncodedImageFormat,
+};
 use tracing::instrument;
 
 use crate::term::{
     proto::{
-        delete_image, transmit_image, Action, ActionPut, Command, Placement,
-        Quietness, ID,
+        control_animation, delete_image, edit_frame, transmit_frame,
+        transmit_image, Action, ActionAnimationFrameControl, ActionPut,
+        AnimationMode, Command, Frame, LoopMode, Placement, Quietness, ID,
     },
     writer::TermWriter,
 };
 
-static IMAGE_ID: AtomicU32 = AtomicU32::new(1);
-static IMAGE_SET_ID: AtomicU32 = AtomicU32::new(1);
-pub static IMAGE_MANAGER: once_cell::sync::Lazy<Mutex<ImageManager>> =
-    once_cell::sync::Lazy::new(|| Mutex::new(ImageManager::new()));
+/// Kitty image ids are partitioned per client, the high bits are the client
+/// slot, and the low bits are the image id inside the slot.
+const CLIENT_SLOT_BITS: u32 = 12;
+const CLIENT_IMAGE_ID_BITS: u32 = 32 - CLIENT_SLOT_BITS;
+
+/// Start from the pid, so multiple processes drawing on the same terminal are
+/// unlikely to share a slot.
+static CLIENT_SLOT: once_cell::sync::Lazy<AtomicU32> =
+    once_cell::sync::Lazy::new(|| AtomicU32::new(std::process::id()));
 
+/// Default upper bound of png buffers kept by an [`ImageManager`].
+pub const DEFAULT_MAX_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Manages all images of one client.
 #[derive(Debug)]
 pub struct ImageManager {
+    /// The client slot, all kitty image ids allocated by this manager are in
+    /// `[slot << CLIENT_IMAGE_ID_BITS, (slot + 1) << CLIENT_IMAGE_ID_BITS)`.
+    slot: u32,
+    next_image_id: u32,
+    next_set_id: u32,
     image_sets: HashMap<NonZeroU32, Arc<ImageSet>>,
+    /// Ids of all image sets, the least recently used one comes first.
+    lru: VecDeque<NonZeroU32>,
+    /// Max total size of png buffers in bytes, least recently used image
+    /// sets will be evicted when exceeded.
+    max_bytes: usize,
+}
+
+/// Summary of an image set, for debugging.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageSetInfo {
+    pub id: u32,
+    pub images: usize,
+    pub bytes: usize,
+    pub transmitted: usize,
 }
 
 #[derive(Debug)]
 pub struct Image {
     id: NonZeroU32,
-    buffer: Vec<u8>,
+    /// Png encoded data, replaced by [`Image::patch`].
+    buffer: Mutex<Arc<Vec<u8>>>,
     transmitted: Mutex<bool>,
 }
 
+/// A frame of an [`AnimatedImage`].
+#[derive(Debug)]
+pub struct AnimationFrame {
+    /// Png encoded data of this frame.
+    buffer: Vec<u8>,
+    /// The gap (in milliseconds) of this frame from the next one.
+    gap: i32,
+}
+
+#[derive(Debug)]
+struct AnimatedImageState {
+    transmitted: bool,
+    playing: bool,
+}
+
+/// An image with multiple frames, the animation is played by the terminal.
+#[derive(Debug)]
+pub struct AnimatedImage {
+    id: NonZeroU32,
+    frames: Vec<AnimationFrame>,
+    loop_mode: LoopMode,
+    state: Mutex<AnimatedImageState>,
+}
+
 #[derive(Debug)]
 struct ImageSetDisplayState {
     index: usize,
@@ -48,62 +107,156 @@ pub struct ImageSet {
 }
 
 impl ImageManager {
-    fn new() -> Self {
+    /// Create a manager with a new client slot.
+    pub fn new() -> Self {
+        let slot = CLIENT_SLOT
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            % (1 << CLIENT_SLOT_BITS);
+        Self::new_with_slot(slot)
+    }
+
+    pub fn new_with_slot(slot: u32) -> Self {
         Self {
+            slot,
+            next_image_id: 1,
+            next_set_id: 1,
             image_sets: HashMap::new(),
+            lru: VecDeque::new(),
+            max_bytes: DEFAULT_MAX_CACHE_BYTES,
         }
     }
 
-    pub fn alloc_set_id() -> NonZeroU32 {
-        let id: NonZeroU32 = IMAGE_SET_ID
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-            .try_into()
-            .unwrap();
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+
+    /// Allocate a kitty image id in the range of this client.
+    pub fn alloc_image_id(&mut self) -> NonZeroU32 {
+        let id = self.next_image_id;
+        self.next_image_id = if id + 1 >= (1 << CLIENT_IMAGE_ID_BITS) {
+            1
+        } else {
+            id + 1
+        };
+        NonZeroU32::new((self.slot << CLIENT_IMAGE_ID_BITS) | id).unwrap()
+    }
+
+    pub fn alloc_set_id(&mut self) -> NonZeroU32 {
+        let id = NonZeroU32::new(self.next_set_id).unwrap();
+        self.next_set_id = self.next_set_id.checked_add(1).unwrap_or(1);
         id
     }
 
+    pub fn new_image(&mut self, buffer: Vec<u8>) -> Arc<Image> {
+        Arc::new(Image::new_from_buffer_with_id(self.alloc_image_id(), buffer))
+    }
+
     pub fn new_image_set(
         &mut self, images: Vec<Arc<Image>>,
     ) -> anyhow::Result<Arc<ImageSet>> {
-        let image_set = ImageSet::new(images)?;
-        let id = image_set.id;
-        self.image_sets.insert(id, Arc::new(image_set));
-        Ok(self.image_sets.get(&id).unwrap().clone())
+        let id = self.alloc_set_id();
+        self.new_image_set_with_id(id, images)
     }
 
     pub fn new_image_set_with_id(
         &mut self, id: NonZeroU32, images: Vec<Arc<Image>>,
     ) -> anyhow::Result<Arc<ImageSet>> {
-        let image_set = ImageSet::new_with_id(id, images)?;
-        self.image_sets.insert(id, Arc::new(image_set));
-        Ok(self.image_sets.get(&id).unwrap().clone())
+        let image_set = Arc::new(ImageSet::new_with_id(id, images)?);
+        self.insert(image_set.clone());
+        Ok(image_set)
     }
 
-    pub fn find_image_set(&self, id: NonZeroU32) -> Option<Arc<ImageSet>> {
+    fn insert(&mut self, image_set: Arc<ImageSet>) {
+        let id = image_set.id;
+        if self.image_sets.insert(id, image_set).is_some() {
+            self.lru.retain(|x| *x != id);
+        }
+        self.lru.push_back(id);
+    }
+
+    /// Find an image set, and mark it as the most recently used one.
+    pub fn find_image_set(&mut self, id: NonZeroU32) -> Option<Arc<ImageSet>> {
+        let image_set = self.image_sets.get(&id).cloned()?;
+        self.lru.retain(|x| *x != id);
+        self.lru.push_back(id);
+        Some(image_set)
+    }
+
+    /// Find an image set without marking it as used.
+    pub fn get_image_set(&self, id: NonZeroU32) -> Option<Arc<ImageSet>> {
         self.image_sets.get(&id).cloned()
     }
+
+    /// Remove an image set from the manager. The caller is responsible for
+    /// deleting its images from the terminal.
+    pub fn remove_image_set(
+        &mut self, id: NonZeroU32,
+    ) -> Option<Arc<ImageSet>> {
+        self.lru.retain(|x| *x != id);
+        self.image_sets.remove(&id)
+    }
+
+    /// Remove all image sets from the manager.
+    pub fn clear(&mut self) -> Vec<Arc<ImageSet>> {
+        self.lru.clear();
+        self.image_sets.drain().map(|(_, v)| v).collect()
+    }
+
+    pub fn set_max_bytes(&mut self, max_bytes: usize) {
+        self.max_bytes = max_bytes;
+    }
+
+    /// Total size of all png buffers in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.image_sets.values().map(|x| x.bytes()).sum()
+    }
+
+    /// Evict least recently used image sets until the total size fits into
+    /// `max_bytes`. The most recently used image set is always kept.
+    pub fn evict(&mut self) -> Vec<Arc<ImageSet>> {
+        let mut total = self.total_bytes();
+        let mut ret = vec![];
+        while total > self.max_bytes && self.lru.len() > 1 {
+            let id = self.lru.pop_front().unwrap();
+            if let Some(image_set) = self.image_sets.remove(&id) {
+                total -= image_set.bytes();
+                ret.push(image_set);
+            }
+        }
+        ret
+    }
+
+    pub fn list_image_sets(&self) -> Vec<ImageSetInfo> {
+        self.lru
+            .iter()
+            .filter_map(|id| self.image_sets.get(id))
+            .map(|x| x.info())
+            .collect()
+    }
+}
+
+impl Default for ImageManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Image {
     pub fn new_from_buffer_with_id(id: NonZeroU32, buffer: Vec<u8>) -> Self {
         Self {
             id,
-            buffer,
+            buffer: Mutex::new(Arc::new(buffer)),
             transmitted: Mutex::new(false),
         }
     }
 
-    pub fn new_from_buffer(buffer: Vec<u8>) -> Self {
-        let id: NonZeroU32 = IMAGE_ID
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-            .try_into()
-            .unwrap();
+    /// Size of the png buffer in bytes.
+    pub fn bytes(&self) -> usize {
+        self.buffer.lock().len()
+    }
 
-        Self {
-            id,
-            buffer,
-            transmitted: Mutex::new(false),
-        }
+    pub fn is_transmitted(&self) -> bool {
+        *self.transmitted.lock()
     }
 
     #[instrument(skip(self,))]
@@ -117,7 +270,8 @@ impl Image {
             }
             *transmitted = true;
         }
-        transmit_image(&self.buffer, writer, ID(self.id)).await?;
+        let buffer = self.buffer.lock().clone();
+        transmit_image(&buffer, writer, ID(self.id)).await?;
         writer.flush().await
     }
 
@@ -134,7 +288,8 @@ impl Image {
             }
         }
         if should_transmit {
-            transmit_image(&self.buffer, writer, ID(self.id)).await?;
+            let buffer = self.buffer.lock().clone();
+            transmit_image(&buffer, writer, ID(self.id)).await?;
             writer.flush().await?;
             tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
@@ -166,6 +321,27 @@ impl Image {
         }
         writer.flush().await
     }
+
+    /**
+     * Replace the image with `buffer`, which only differs from the current
+     * one in the png `patch` at `x`, `y` (in pixels).
+     *
+     * A transmitted image is edited in place by sending the patch, its
+     * placements keep displaying. Otherwise the new buffer is transmitted on
+     * the next render.
+     */
+    #[instrument(skip(self, writer, patch, buffer))]
+    pub async fn patch(
+        &self, writer: &mut TermWriter, x: u32, y: u32, patch: &[u8],
+        buffer: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        *self.buffer.lock() = Arc::new(buffer);
+        if !self.is_transmitted() {
+            return Ok(());
+        }
+        edit_frame(patch, writer, ID(self.id), x, y).await?;
+        writer.flush().await
+    }
 }
 
 impl ImageSetDisplayState {
@@ -179,20 +355,6 @@ impl ImageSetDisplayState {
 }
 
 impl ImageSet {
-    pub fn new(images: Vec<Arc<Image>>) -> anyhow::Result<Self> {
-        if images.is_empty() {
-            bail!("ImageSet must have at least one image");
-        }
-        Ok(Self {
-            id: NonZeroU32::try_from(
-                IMAGE_SET_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
-            )
-            .unwrap(),
-            images,
-            state: Mutex::new(ImageSetDisplayState::new()),
-        })
-    }
-
     pub fn new_with_id(
         id: NonZeroU32, images: Vec<Arc<Image>>,
     ) -> anyhow::Result<Self> {
@@ -206,6 +368,28 @@ impl ImageSet {
         })
     }
 
+    pub fn id(&self) -> NonZeroU32 {
+        self.id
+    }
+
+    /// Total size of png buffers in bytes.
+    pub fn bytes(&self) -> usize {
+        self.images.iter().map(|x| x.bytes()).sum()
+    }
+
+    pub fn info(&self) -> ImageSetInfo {
+        ImageSetInfo {
+            id: self.id.get(),
+            images: self.images.len(),
+            bytes: self.bytes(),
+            transmitted: self
+                .images
+                .iter()
+                .filter(|x| x.is_transmitted())
+                .count(),
+        }
+    }
+
     pub async fn delete_image(
         &self, writer: &mut TermWriter, hard: bool,
     ) -> anyhow::Result<()> {
@@ -227,6 +411,35 @@ impl ImageSet {
         image.render_at(writer, x, y, z).await
     }
 
+    /// Patch the image of page `index`, see [`Image::patch`].
+    pub async fn patch(
+        &self, writer: &mut TermWriter, index: usize, x: u32, y: u32,
+        patch: &[u8], buffer: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        let image = self.images.get(index).with_context(|| {
+            format!("No page {} in image set {}", index, self.id)
+        })?;
+        image.patch(writer, x, y, patch, buffer).await
+    }
+
+    /// Where the image set was rendered last, None if it's hidden.
+    pub fn last_rendered_pos(&self) -> Option<(u32, u32)> {
+        self.state.lock().last_rendered_pos
+    }
+
+    /// Delete the placement of the current image, the image data is kept so
+    /// it can be rendered again with [`ImageSet::render_at`].
+    pub async fn hide(&self, writer: &mut TermWriter) -> anyhow::Result<()> {
+        let image = {
+            let mut state = self.state.lock();
+            if state.last_rendered_pos.take().is_none() {
+                return Ok(());
+            }
+            &self.images[state.index]
+        };
+        image.delete_image(writer, false).await
+    }
+
     pub async fn next_image(
         &self, writer: &mut TermWriter,
     ) -> anyhow::Result<()> {
@@ -289,3 +502,436 @@ impl ImageSet {
         Ok(())
     }
 }
+
+impl AnimationFrame {
+    pub fn new(buffer: Vec<u8>, gap: i32) -> Self {
+        Self { buffer, gap }
+    }
+}
+
+impl AnimatedImage {
+    /// Create an animated image with the kitty image id, see
+    /// [`ImageManager::alloc_image_id`].
+    pub fn new_from_frames(
+        id: NonZeroU32, frames: Vec<AnimationFrame>, loop_mode: LoopMode,
+    ) -> anyhow::Result<Self> {
+        if frames.is_empty() {
+            bail!("AnimatedImage must have at least one frame");
+        }
+        Ok(Self {
+            id,
+            frames,
+            loop_mode,
+            state: Mutex::new(AnimatedImageState {
+                transmitted: false,
+                playing: false,
+            }),
+        })
+    }
+
+    /// Decode an animated image (GIF, APNG, WebP), each frame is re-encoded
+    /// to png.
+    pub fn new_from_encoded(
+        id: NonZeroU32, data: &[u8],
+    ) -> anyhow::Result<Self> {
+        let mut codec = Codec::from_data(Data::new_copy(data))
+            .context("Unsupported image format")?;
+        let loop_mode = match codec.get_repetition_count() {
+            None => LoopMode::Infinite,
+            Some(count) => LoopMode::Finite(
+                NonZeroU32::new(count as u32 + 1).unwrap(),
+            ),
+        };
+        let count = codec.get_frame_count().max(1);
+        let mut frames = Vec::with_capacity(count);
+        for index in 0..count {
+            let gap = codec
+                .get_frame_info(index)
+                .map(|info| info.duration)
+                .unwrap_or_default();
+            let options = CodecOptions {
+                zero_initialized: ZeroInitialized::No,
+                subset: None,
+                frame_index: index,
+                prior_frame: None,
+            };
+            let image = codec.get_image(None, &options).map_err(|e| {
+                anyhow::anyhow!("Failed to decode frame {}: {:?}", index, e)
+            })?;
+            let data = image
+                .encode(None, EncodedImageFormat::PNG, None)
+                .context("Failed to encode png")?;
+            frames.push(AnimationFrame::new(data.as_bytes().to_vec(), gap));
+        }
+        Self::new_from_frames(id, frames, loop_mode)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Transmit all frames. The first frame is transmitted as the root frame,
+    /// the others are appended as animation frames.
+    #[instrument(skip(self, writer))]
+    pub async fn transmit(
+        &self, writer: &mut TermWriter,
+    ) -> anyhow::Result<()> {
+        if self.state.lock().transmitted {
+            return Ok(());
+        }
+        let root = &self.frames[0];
+        transmit_image(&root.buffer, writer, ID(self.id)).await?;
+        for frame in &self.frames[1..] {
+            transmit_frame(&frame.buffer, writer, ID(self.id), frame.gap)
+                .await?;
+        }
+        // the root frame defaults to zero gap
+        if root.gap != 0 {
+            self.set_frame_gap_impl(writer, 0, root.gap).await?;
+        }
+        writer.flush().await?;
+        // only after all frames are sent, so a failed transmission is retried
+        self.state.lock().transmitted = true;
+        Ok(())
+    }
+
+    #[instrument(skip(self, writer))]
+    pub async fn render_at(
+        &self, writer: &mut TermWriter, x: u32, y: u32, z: u32,
+    ) -> anyhow::Result<()> {
+        self.transmit(writer).await?;
+        let action = ActionPut {
+            x_offset: x,
+            y_offset: y,
+            move_cursor: false,
+            placement: Placement(Some(self.id)),
+            z_index: z,
+            ..Default::default()
+        };
+        let cmd = Command {
+            action: Action::Put(action),
+            quietness: Quietness::SuppressAll,
+            id: Some(ID(self.id)),
+        };
+        cmd.send(None, writer).await?;
+        writer.flush().await
+    }
+
+    /// Start the animation in the terminal.
+    #[instrument(skip(self, writer))]
+    pub async fn play(&self, writer: &mut TermWriter) -> anyhow::Result<()> {
+        control_animation(
+            writer,
+            ID(self.id),
+            ActionAnimationFrameControl {
+                mode: Some(AnimationMode::Run),
+                loop_mode: Some(self.loop_mode),
+                ..Default::default()
+            },
+        )
+        .await?;
+        self.state.lock().playing = true;
+        writer.flush().await
+    }
+
+    /// Stop the animation, the current frame keeps displaying.
+    #[instrument(skip(self, writer))]
+    pub async fn stop(&self, writer: &mut TermWriter) -> anyhow::Result<()> {
+        control_animation(
+            writer,
+            ID(self.id),
+            ActionAnimationFrameControl {
+                mode: Some(AnimationMode::Stop),
+                ..Default::default()
+            },
+        )
+        .await?;
+        self.state.lock().playing = false;
+        writer.flush().await
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.lock().playing
+    }
+
+    /// Update the gap of the frame at `index` (0-based).
+    #[instrument(skip(self, writer))]
+    pub async fn set_frame_gap(
+        &self, writer: &mut TermWriter, index: usize, gap: i32,
+    ) -> anyhow::Result<()> {
+        if index >= self.frames.len() {
+            bail!(
+                "Frame index {} out of range, only {} frames",
+                index,
+                self.frames.len()
+            );
+        }
+        self.set_frame_gap_impl(writer, index, gap).await?;
+        writer.flush().await
+    }
+
+    async fn set_frame_gap_impl(
+        &self, writer: &mut TermWriter, index: usize, gap: i32,
+    ) -> anyhow::Result<()> {
+        let frame = Frame(NonZeroU32::new(index as u32 + 1).unwrap());
+        control_animation(
+            writer,
+            ID(self.id),
+            ActionAnimationFrameControl {
+                frame_number: Some(frame),
+                gap,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    #[instrument(skip(self, writer))]
+    pub async fn delete_image(
+        &self, writer: &mut TermWriter, hard: bool,
+    ) -> anyhow::Result<()> {
+        delete_image(writer, ID(self.id), hard).await?;
+        {
+            let mut state = self.state.lock();
+            state.transmitted = false;
+            state.playing = false;
+        }
+        writer.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        num::NonZeroU32,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    };
+
+    use tokio::io::AsyncWrite;
+
+    use crate::term::{
+        proto::{
+            decode_commands, Action, ActionAnimationFrameControl,
+            AnimationMode, Frame, LoopMode,
+        },
+        writer::TermWriter,
+    };
+
+    use super::{AnimatedImage, AnimationFrame, Image, ImageManager, ImageSet};
+
+    /// A terminal that went away, every write fails.
+    struct BrokenTerm;
+
+    impl AsyncWrite for BrokenTerm {
+        fn poll_write(
+            self: Pin<&mut Self>, _cx: &mut Context<'_>, _buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>, _cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>, _cx: &mut Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn new_animated_image(
+        loop_mode: LoopMode,
+    ) -> anyhow::Result<AnimatedImage> {
+        let frames = vec![
+            AnimationFrame::new(b"first".to_vec(), 40),
+            AnimationFrame::new(b"second".to_vec(), 50),
+            AnimationFrame::new(b"third".to_vec(), 60),
+        ];
+        AnimatedImage::new_from_frames(
+            NonZeroU32::new(5).unwrap(),
+            frames,
+            loop_mode,
+        )
+    }
+
+    fn new_images(
+        manager: &mut ImageManager, bytes: usize,
+    ) -> Vec<Arc<Image>> {
+        vec![manager.new_image(vec![0; bytes])]
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() -> anyhow::Result<()> {
+        let mut manager = ImageManager::new();
+        manager.set_max_bytes(25);
+        let images = new_images(&mut manager, 10);
+        let a = manager.new_image_set(images)?;
+        let images = new_images(&mut manager, 10);
+        let b = manager.new_image_set(images)?;
+        assert!(manager.evict().is_empty());
+
+        // touch `a`, so `b` becomes the least recently used one
+        manager.find_image_set(a.id());
+        let images = new_images(&mut manager, 10);
+        manager.new_image_set(images)?;
+        let evicted = manager.evict();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id(), b.id());
+        assert!(manager.find_image_set(b.id()).is_none());
+        assert_eq!(manager.total_bytes(), 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_most_recently_used() -> anyhow::Result<()> {
+        let mut manager = ImageManager::new();
+        manager.set_max_bytes(5);
+        let images = new_images(&mut manager, 10);
+        let a = manager.new_image_set(images)?;
+        assert!(manager.evict().is_empty());
+        assert!(manager.remove_image_set(a.id()).is_some());
+        assert!(manager.list_image_sets().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_id_partitioned_by_slot() {
+        let mut a = ImageManager::new_with_slot(1);
+        let mut b = ImageManager::new_with_slot(2);
+        let id_a = a.alloc_image_id().get();
+        let id_b = b.alloc_image_id().get();
+        assert_ne!(id_a, id_b);
+        assert_eq!(id_a >> super::CLIENT_IMAGE_ID_BITS, 1);
+        assert_eq!(id_b >> super::CLIENT_IMAGE_ID_BITS, 2);
+    }
+
+    #[tokio::test]
+    async fn test_patch_image() -> anyhow::Result<()> {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        let id = NonZeroU32::new(7).unwrap();
+        let image = Arc::new(Image::new_from_buffer_with_id(id, vec![0; 10]));
+        let set = ImageSet::new_with_id(id, vec![image.clone()])?;
+
+        // not transmitted yet, the new buffer is sent on the next render
+        set.patch(&mut w, 0, 12, 34, b"patch", vec![0; 20]).await?;
+        assert!(recorder.take().is_empty());
+        assert_eq!(set.bytes(), 20);
+
+        image.transmit(&mut w).await?;
+        recorder.take();
+        set.patch(&mut w, 0, 12, 34, b"patch", vec![0; 20]).await?;
+        assert_eq!(
+            String::from_utf8(recorder.take())?,
+            "\x1b_Gq=2,a=f,f=100,t=d,x=12,y=34,r=1,X=1,i=7,m=0;cGF0Y2g=\x1b\\",
+        );
+
+        assert!(set.patch(&mut w, 1, 0, 0, b"patch", vec![]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_animated_image_frames() -> anyhow::Result<()> {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        let image = new_animated_image(LoopMode::Infinite)?;
+        assert!(AnimatedImage::new_from_frames(
+            NonZeroU32::new(6).unwrap(),
+            vec![],
+            LoopMode::Infinite
+        )
+        .is_err());
+        assert_eq!(image.frame_count(), 3);
+
+        image.transmit(&mut w).await?;
+        let decoded = decode_commands(&recorder.take())?;
+        let payloads: Vec<_> =
+            decoded.iter().map(|d| d.payload.as_slice()).collect();
+        let expected: [&[u8]; 4] = [b"first", b"second", b"third", b""];
+        assert_eq!(payloads, expected);
+        assert!(matches!(decoded[0].command.action, Action::Transmit(_)));
+        let gaps: Vec<_> = decoded[1..3]
+            .iter()
+            .map(|d| match d.command.action {
+                Action::AnimationFrameLoading(loading) => loading.gap,
+                _ => panic!("not a frame: {:?}", d.command),
+            })
+            .collect();
+        assert_eq!(gaps, [50, 60]);
+        // the gap of the root frame is set afterwards
+        assert_eq!(
+            decoded[3].command.action,
+            Action::AnimationFrameControl(ActionAnimationFrameControl {
+                frame_number: Some(Frame(NonZeroU32::new(1).unwrap())),
+                gap: 40,
+                ..Default::default()
+            })
+        );
+
+        // frames are only sent once
+        image.render_at(&mut w, 0, 0, 1).await?;
+        let decoded = decode_commands(&recorder.take())?;
+        assert_eq!(decoded.len(), 1);
+        assert!(matches!(decoded[0].command.action, Action::Put(_)));
+
+        assert!(image.set_frame_gap(&mut w, 3, 10).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_animated_image_loop() -> anyhow::Result<()> {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        let loop_mode = LoopMode::Finite(NonZeroU32::new(3).unwrap());
+        let image = new_animated_image(loop_mode)?;
+        image.transmit(&mut w).await?;
+        recorder.take();
+
+        image.play(&mut w).await?;
+        assert!(image.is_playing());
+        image.stop(&mut w).await?;
+        assert!(!image.is_playing());
+        let actions: Vec<_> = decode_commands(&recorder.take())?
+            .into_iter()
+            .map(|d| d.command.action)
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                Action::AnimationFrameControl(ActionAnimationFrameControl {
+                    mode: Some(AnimationMode::Run),
+                    loop_mode: Some(loop_mode),
+                    ..Default::default()
+                }),
+                Action::AnimationFrameControl(ActionAnimationFrameControl {
+                    mode: Some(AnimationMode::Stop),
+                    ..Default::default()
+                }),
+            ]
+        );
+
+        // deleting forgets the frames, they are sent again on the next render
+        image.play(&mut w).await?;
+        image.delete_image(&mut w, true).await?;
+        assert!(!image.is_playing());
+        recorder.take();
+        image.transmit(&mut w).await?;
+        assert_eq!(decode_commands(&recorder.take())?.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_animated_image_failed_transmit() -> anyhow::Result<()> {
+        let image = new_animated_image(LoopMode::Infinite)?;
+        let mut broken = TermWriter::new_with_writer(BrokenTerm, false, "");
+        assert!(image.transmit(&mut broken).await.is_err());
+
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        image.transmit(&mut w).await?;
+        assert_eq!(decode_commands(&recorder.take())?.len(), 4);
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
 use std::fmt::{self, Display, Formatter};
 
-use super::super::{AnimationMode, CompositionMode, Frame, LoopMode};
+use super::{
+    super::{AnimationMode, CompositionMode, Frame, LoopMode},
+    ActionTransmission,
+};
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Default, Debug)]
 pub struct ActionAnimationFrameLoading {
+    /// How the frame data is transmitted, frames are sent with the same keys
+    /// as a normal transmission.
+    pub transmission: Option<ActionTransmission>,
     /// The left edge (in pixels) of the image area to display
     pub x: u32,
     /// The top edge (in pixels) of the image area to display
@@ -30,6 +36,9 @@ pub struct ActionAnimationFrameLoading {
 
 impl Display for ActionAnimationFrameLoading {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(transmission) = self.transmission {
+            write!(f, "{transmission}")?;
+        }
         if self.x != 0 {
             write!(f, "x={},", self.x)?;
         }
@@ -40,13 +49,13 @@ impl Display for ActionAnimationFrameLoading {
             write!(f, "c={frame_number},")?;
         }
         if let Some(frame_edited) = self.frame_edited {
-            write!(f, "r={frame_edited },")?;
+            write!(f, "r={frame_edited},")?;
         }
         if self.gap != 0 {
             write!(f, "z={},", self.gap)?;
         }
         if self.composition_mode != CompositionMode::default() {
-            write!(f, "X={},", self.gap)?;
+            write!(f, "X={},", self.composition_mode)?;
         }
         if self.color != 0 {
             write!(f, "Y={},", self.color)?;
@@ -89,7 +98,7 @@ impl Display for ActionAnimationFrameComposition {
             write!(f, "c={frame_number},")?;
         }
         if let Some(frame_edited) = self.frame_edited {
-            write!(f, "c={frame_edited},")?;
+            write!(f, "r={frame_edited},")?;
         }
 
         if self.x != 0 {
@@ -121,8 +130,8 @@ impl Display for ActionAnimationFrameComposition {
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Default, Debug)]
 pub struct ActionAnimationFrameControl {
-    /// The mode of this command
-    pub mode: AnimationMode,
+    /// The mode of this command, the current mode is kept if not set
+    pub mode: Option<AnimationMode>,
     /// The 1-based frame number of the frame that is being affected
     pub frame_number: Option<Frame>,
     /// The gap (in milliseconds) of this frame from the next one.
@@ -137,7 +146,9 @@ pub struct ActionAnimationFrameControl {
 
 impl Display for ActionAnimationFrameControl {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "s={},", self.mode)?;
+        if let Some(mode) = self.mode {
+            write!(f, "s={},", mode)?;
+        }
 
         if let Some(frame_number) = self.frame_number {
             write!(f, "r={frame_number},")?;
@@ -61,9 +61,9 @@ impl Display for ActionDelete {
             (true, DeleteTarget::CellWithZIndex { x, y, z }) => {
                 write!(f, "d=P,x={x},y={y},z={z},")
             }
-            (true, DeleteTarget::Column(x)) => write!(f, "d=X,x={x}"),
-            (true, DeleteTarget::Row(y)) => write!(f, "d=Y,y={y}"),
-            (true, DeleteTarget::ZIndex(z)) => write!(f, "d=Z,z={z}"),
+            (true, DeleteTarget::Column(x)) => write!(f, "d=X,x={x},"),
+            (true, DeleteTarget::Row(y)) => write!(f, "d=Y,y={y},"),
+            (true, DeleteTarget::ZIndex(z)) => write!(f, "d=Z,z={z},"),
             (false, DeleteTarget::Placements) => write!(f, "d=a,"),
             (false, DeleteTarget::ID { placement }) => {
                 write!(f, "d=i,")?;
@@ -87,9 +87,9 @@ impl Display for ActionDelete {
             (false, DeleteTarget::CellWithZIndex { x, y, z }) => {
                 write!(f, "d=p,x={x},y={y},z={z},")
             }
-            (false, DeleteTarget::Column(x)) => write!(f, "d=x,x={x}"),
-            (false, DeleteTarget::Row(y)) => write!(f, "d=y,y={y}"),
-            (false, DeleteTarget::ZIndex(z)) => write!(f, "d=z,z={z}"),
+            (false, DeleteTarget::Column(x)) => write!(f, "d=x,x={x},"),
+            (false, DeleteTarget::Row(y)) => write!(f, "d=y,y={y},"),
+            (false, DeleteTarget::ZIndex(z)) => write!(f, "d=z,z={z},"),
         }
     }
 }
@@ -114,7 +114,8 @@ impl Display for LoopMode {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::Infinite => write!(f, "1"),
-            Self::Finite(x) => write!(f, "{}", x.get() - 1),
+            // `v=n` loops the animation `n - 1` times
+            Self::Finite(x) => write!(f, "{}", x.get().saturating_add(1)),
         }
     }
 }
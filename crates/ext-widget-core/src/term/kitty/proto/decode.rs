@@ -0,0 +1,552 @@
+use std::{num::NonZeroU32, str::FromStr};
+
+use anyhow::{bail, Context};
+use base64::Engine;
+
+use super::{
+    Action, ActionAnimationFrameComposition, ActionAnimationFrameControl,
+    ActionAnimationFrameLoading, ActionDelete, ActionPut, ActionTransmission,
+    AnimationMode, Command, CompositionMode, DeleteTarget, Format, Frame,
+    LoopMode, Medium, Placement, Quietness, ID,
+};
+
+const TMUX_START: &[u8] = b"\x1bPtmux;";
+const APC_START: &[u8] = b"\x1b_G";
+const ST: &[u8] = b"\x1b\\";
+
+/// A command decoded from the bytes sent to the terminal.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DecodedCommand {
+    pub command: Command,
+    /// Payload of all chunks, base64 decoded.
+    pub payload: Vec<u8>,
+    /// Number of escape sequences the command was sent in.
+    pub chunks: usize,
+}
+
+/// Decode all graphics commands in `bytes`, the reverse of `Command::send`.
+/// Sequences wrapped for tmux passthrough are unwrapped first.
+///
+/// Chunked transmissions (`m=1`) are merged into one command, the control
+/// data of the first chunk is used.
+pub fn decode_commands(bytes: &[u8]) -> anyhow::Result<Vec<DecodedCommand>> {
+    let bytes = unwrap_tmux(bytes)?;
+    let mut ret = vec![];
+    let mut pending: Option<(DecodedCommand, Vec<u8>)> = None;
+    let mut rest = bytes.as_slice();
+    while !rest.is_empty() {
+        let start = find(rest, APC_START)
+            .with_context(|| format!("Unexpected bytes: {:?}", rest))?;
+        if start != 0 {
+            bail!("Unexpected bytes before command: {:?}", &rest[..start]);
+        }
+        let body = &rest[APC_START.len()..];
+        let end = find(body, ST).context("Unterminated command")?;
+        let body = &body[..end];
+        let (control, payload) = match body.iter().position(|&b| b == b';') {
+            Some(i) => (&body[..i], &body[i + 1..]),
+            None => (body, &body[..0]),
+        };
+        rest = &rest[APC_START.len() + end + ST.len()..];
+
+        let control = std::str::from_utf8(control)?;
+        let (command, more) = parse_control(control)?;
+        let (mut decoded, mut encoded) = match pending.take() {
+            Some(pending) => pending,
+            None => (
+                DecodedCommand {
+                    command,
+                    payload: vec![],
+                    chunks: 0,
+                },
+                vec![],
+            ),
+        };
+        decoded.chunks += 1;
+        encoded.extend_from_slice(payload);
+        if more {
+            pending = Some((decoded, encoded));
+        } else {
+            decoded.payload =
+                base64::engine::general_purpose::STANDARD.decode(encoded)?;
+            ret.push(decoded);
+        }
+    }
+    if pending.is_some() {
+        bail!("Last chunk (m=0) is missing");
+    }
+    Ok(ret)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Remove tmux passthrough wrappers, `ESC` is doubled inside them.
+fn unwrap_tmux(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    while let Some(start) = find(rest, TMUX_START) {
+        ret.extend_from_slice(&rest[..start]);
+        rest = &rest[start + TMUX_START.len()..];
+        let mut i = 0;
+        loop {
+            match rest.get(i..i + 2) {
+                Some(b"\x1b\x1b") => {
+                    ret.push(b'\x1b');
+                    i += 2;
+                }
+                Some(b"\x1b\\") => {
+                    rest = &rest[i + 2..];
+                    break;
+                }
+                Some(_) => {
+                    ret.push(rest[i]);
+                    i += 1;
+                }
+                None => bail!("Unterminated tmux passthrough"),
+            }
+        }
+    }
+    ret.extend_from_slice(rest);
+    Ok(ret)
+}
+
+fn parse_num<T: FromStr>(key: char, value: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .ok()
+        .with_context(|| format!("Invalid value of '{}': {}", key, value))
+}
+
+fn parse_non_zero(key: char, value: &str) -> anyhow::Result<NonZeroU32> {
+    parse_num(key, value)
+}
+
+/// Parse the control data of a command, returns the command and whether
+/// more chunks follow.
+fn parse_control(control: &str) -> anyhow::Result<(Command, bool)> {
+    let mut pairs = vec![];
+    for kv in control.split(',').filter(|kv| !kv.is_empty()) {
+        let (key, value) = kv
+            .split_once('=')
+            .with_context(|| format!("Invalid key-value: {}", kv))?;
+        let mut chars = key.chars();
+        let (Some(key), None) = (chars.next(), chars.next()) else {
+            bail!("Invalid key: {}", key);
+        };
+        pairs.push((key, value));
+    }
+
+    let mut quietness = Quietness::None;
+    let mut id = None;
+    let mut more = false;
+    let mut action = 't';
+    let mut rest = vec![];
+    for (key, value) in pairs {
+        match key {
+            'a' => {
+                action = value.chars().next().context("Empty action")?;
+            }
+            'q' => {
+                quietness = match value {
+                    "1" => Quietness::SupressOk,
+                    "2" => Quietness::SuppressAll,
+                    _ => bail!("Invalid quietness: {}", value),
+                }
+            }
+            'i' => id = Some(ID(parse_non_zero(key, value)?)),
+            'm' => more = value == "1",
+            _ => rest.push((key, value)),
+        }
+    }
+
+    let action = match action {
+        't' => Action::Transmit(parse_transmission(&rest)?.unwrap_or_default()),
+        'T' => Action::TransmitAndDisplay(
+            parse_transmission(&rest)?.unwrap_or_default(),
+            parse_put(&rest)?,
+        ),
+        'q' => Action::Query,
+        'p' => Action::Put(parse_put(&rest)?),
+        'f' => Action::AnimationFrameLoading(parse_frame_loading(&rest)?),
+        'c' => Action::AnimationFrameComposition(parse_composition(&rest)?),
+        'a' => Action::AnimationFrameControl(parse_control_animation(&rest)?),
+        'd' => Action::Delete(parse_delete(&rest)?),
+        _ => bail!("Unknown action: {}", action),
+    };
+    Ok((
+        Command {
+            action,
+            quietness,
+            id,
+        },
+        more,
+    ))
+}
+
+/// Transmission keys, `None` if there is none of them.
+fn parse_transmission(
+    pairs: &[(char, &str)],
+) -> anyhow::Result<Option<ActionTransmission>> {
+    let mut ret = ActionTransmission::default();
+    let mut found = false;
+    for &(key, value) in pairs {
+        match key {
+            'f' => {
+                ret.format = match value {
+                    "24" => Format::Rgb24,
+                    "32" => Format::Rgba32,
+                    "100" => Format::Png,
+                    _ => bail!("Invalid format: {}", value),
+                }
+            }
+            't' => {
+                ret.medium = match value {
+                    "d" => Medium::Direct,
+                    "f" => Medium::File,
+                    "t" => Medium::TemporaryFile,
+                    "s" => Medium::SharedMemoryObject,
+                    _ => bail!("Invalid medium: {}", value),
+                }
+            }
+            's' => ret.width = Some(parse_num(key, value)?),
+            'v' => ret.height = Some(parse_num(key, value)?),
+            'S' => ret.size = Some(parse_num(key, value)?),
+            'O' => ret.offset = Some(parse_num(key, value)?),
+            'I' => ret.number = Some(parse_num(key, value)?),
+            'p' => ret.placement = Placement(Some(parse_non_zero(key, value)?)),
+            'o' if value == "z" => ret.compression = true,
+            _ => continue,
+        }
+        found = true;
+    }
+    Ok(found.then_some(ret))
+}
+
+fn parse_put(pairs: &[(char, &str)]) -> anyhow::Result<ActionPut> {
+    let mut ret = ActionPut {
+        move_cursor: true,
+        ..Default::default()
+    };
+    for &(key, value) in pairs {
+        match key {
+            'x' => ret.x = parse_num(key, value)?,
+            'y' => ret.y = parse_num(key, value)?,
+            'w' => ret.w = parse_num(key, value)?,
+            'h' => ret.h = parse_num(key, value)?,
+            'X' => ret.x_offset = parse_num(key, value)?,
+            'Y' => ret.y_offset = parse_num(key, value)?,
+            'c' => ret.columns = parse_num(key, value)?,
+            'r' => ret.rows = parse_num(key, value)?,
+            'C' => ret.move_cursor = value != "1",
+            'U' => ret.unicode_placeholder = value == "1",
+            'z' => ret.z_index = parse_num(key, value)?,
+            'P' => ret.parent_image = Some(ID(parse_non_zero(key, value)?)),
+            'Q' => {
+                ret.parent_placement =
+                    Placement(Some(parse_non_zero(key, value)?))
+            }
+            'H' => ret.cell_relative_offset_horizontal = parse_num(key, value)?,
+            'V' => ret.cell_relative_offset_vertical = parse_num(key, value)?,
+            'p' => ret.placement = Placement(Some(parse_non_zero(key, value)?)),
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_composition_mode(value: &str) -> anyhow::Result<CompositionMode> {
+    Ok(match value {
+        "0" => CompositionMode::AlphaBlend,
+        "1" => CompositionMode::Overwrite,
+        _ => bail!("Invalid composition mode: {}", value),
+    })
+}
+
+fn parse_frame(key: char, value: &str) -> anyhow::Result<Frame> {
+    Ok(Frame(parse_non_zero(key, value)?))
+}
+
+fn parse_frame_loading(
+    pairs: &[(char, &str)],
+) -> anyhow::Result<ActionAnimationFrameLoading> {
+    // `f` and `t` are always written with a transmission
+    let transmission = match pairs.iter().any(|(k, _)| *k == 'f' || *k == 't')
+    {
+        true => parse_transmission(pairs)?,
+        false => None,
+    };
+    let mut ret = ActionAnimationFrameLoading {
+        transmission,
+        ..Default::default()
+    };
+    for &(key, value) in pairs {
+        match key {
+            'x' => ret.x = parse_num(key, value)?,
+            'y' => ret.y = parse_num(key, value)?,
+            'c' => ret.frame_number = Some(parse_frame(key, value)?),
+            'r' => ret.frame_edited = Some(parse_frame(key, value)?),
+            'z' => ret.gap = parse_num(key, value)?,
+            'X' => ret.composition_mode = parse_composition_mode(value)?,
+            'Y' => ret.color = parse_num(key, value)?,
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_composition(
+    pairs: &[(char, &str)],
+) -> anyhow::Result<ActionAnimationFrameComposition> {
+    let mut ret = ActionAnimationFrameComposition::default();
+    for &(key, value) in pairs {
+        match key {
+            'c' => ret.frame_number = Some(parse_frame(key, value)?),
+            'r' => ret.frame_edited = Some(parse_frame(key, value)?),
+            'x' => ret.x = parse_num(key, value)?,
+            'y' => ret.y = parse_num(key, value)?,
+            'w' => ret.w = parse_num(key, value)?,
+            'h' => ret.h = parse_num(key, value)?,
+            'X' => ret.source_x = parse_num(key, value)?,
+            'Y' => ret.source_y = parse_num(key, value)?,
+            'C' => ret.composition_mode = parse_composition_mode(value)?,
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_control_animation(
+    pairs: &[(char, &str)],
+) -> anyhow::Result<ActionAnimationFrameControl> {
+    let mut ret = ActionAnimationFrameControl::default();
+    for &(key, value) in pairs {
+        match key {
+            's' => {
+                ret.mode = Some(match value {
+                    "1" => AnimationMode::Stop,
+                    "2" => AnimationMode::RunWithNewFrames,
+                    "3" => AnimationMode::Run,
+                    _ => bail!("Invalid animation mode: {}", value),
+                })
+            }
+            'r' => ret.frame_number = Some(parse_frame(key, value)?),
+            'z' => ret.gap = parse_num(key, value)?,
+            'c' => ret.frame = Some(parse_frame(key, value)?),
+            'v' => {
+                // `v=n` loops the animation `n - 1` times
+                let v: u32 = parse_num(key, value)?;
+                let n = NonZeroU32::new(v.saturating_sub(1));
+                ret.loop_mode = Some(match n {
+                    None => LoopMode::Infinite,
+                    Some(n) => LoopMode::Finite(n),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_delete(pairs: &[(char, &str)]) -> anyhow::Result<ActionDelete> {
+    let get = |key: char| {
+        pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    };
+    let num = |key: char| -> anyhow::Result<u32> {
+        parse_num(key, get(key).with_context(|| format!("Missing {}", key))?)
+    };
+    let placement = match get('p') {
+        Some(p) => Placement(Some(parse_non_zero('p', p)?)),
+        None => Placement(None),
+    };
+
+    let d = get('d').unwrap_or("a");
+    let target = match d.to_ascii_lowercase().as_str() {
+        "a" => DeleteTarget::Placements,
+        "i" => DeleteTarget::ID { placement },
+        "n" => DeleteTarget::Newest {
+            number: num('I')?,
+            placement,
+        },
+        "c" => DeleteTarget::Cursor,
+        "d" => DeleteTarget::Frames,
+        "p" if get('z').is_some() => DeleteTarget::CellWithZIndex {
+            x: num('x')?,
+            y: num('y')?,
+            z: num('z')?,
+        },
+        "p" => DeleteTarget::Cell {
+            x: num('x')?,
+            y: num('y')?,
+        },
+        "x" => DeleteTarget::Column(num('x')?),
+        "y" => DeleteTarget::Row(num('y')?),
+        "z" => DeleteTarget::ZIndex(num('z')?),
+        _ => bail!("Invalid delete target: {}", d),
+    };
+    Ok(ActionDelete {
+        hard: d.chars().all(|c| c.is_ascii_uppercase()),
+        target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::term::TermWriter;
+
+    use super::{super::*, *};
+
+    fn id(id: u32) -> ID {
+        ID(NonZeroU32::new(id).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_transmit_chunks() {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        // 5000 bytes are 6668 base64 chars, sent in 2 chunks
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        transmit_image(&data, &mut w, id(7)).await.unwrap();
+
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&data);
+        let expected = format!(
+            "\x1b_Gq=2,a=t,f=100,t=d,i=7,m=1;{}\x1b\\\
+             \x1b_Gq=2,a=t,f=100,t=d,i=7,m=0;{}\x1b\\",
+            &encoded[..4096],
+            &encoded[4096..]
+        );
+        let bytes = recorder.take();
+        assert_eq!(String::from_utf8(bytes.clone()).unwrap(), expected);
+
+        let decoded = decode_commands(&bytes).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].chunks, 2);
+        assert_eq!(decoded[0].payload, data);
+        assert_eq!(
+            decoded[0].command,
+            Command {
+                action: Action::Transmit(ActionTransmission {
+                    format: Format::Png,
+                    medium: Medium::Direct,
+                    ..Default::default()
+                }),
+                quietness: Quietness::SuppressAll,
+                id: Some(id(7)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tmux_passthrough() {
+        let (mut w, recorder) = TermWriter::new_recorder(true);
+        delete_image(&mut w, id(3), true).await.unwrap();
+
+        let bytes = recorder.take();
+        assert_eq!(
+            bytes,
+            b"\x1bPtmux;\x1b\x1b_Gq=2,a=d,d=I,p=3,i=3,\x1b\x1b\\\x1b\\"
+        );
+        let decoded = decode_commands(&bytes).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(
+            decoded[0].command.action,
+            Action::Delete(ActionDelete {
+                hard: true,
+                target: DeleteTarget::ID {
+                    placement: Placement(Some(id(3).0)),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_delete_targets() {
+        let cases = [
+            (DeleteTarget::Column(3), "d=x,x=3,"),
+            (DeleteTarget::Row(4), "d=y,y=4,"),
+            (DeleteTarget::ZIndex(5), "d=z,z=5,"),
+            (DeleteTarget::Cell { x: 1, y: 2 }, "d=p,x=1,y=2,"),
+            (
+                DeleteTarget::CellWithZIndex { x: 1, y: 2, z: 3 },
+                "d=p,x=1,y=2,z=3,",
+            ),
+        ];
+        for (target, expected) in cases {
+            let cmd = Command {
+                action: Action::Delete(ActionDelete {
+                    hard: false,
+                    target,
+                }),
+                quietness: Quietness::None,
+                id: Some(id(9)),
+            };
+            let control = cmd.to_string();
+            assert_eq!(control, format!("a=d,{}i=9,", expected));
+            assert_eq!(parse_control(&control).unwrap(), (cmd, false));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        display_image(&mut w, id(2)).await.unwrap();
+        transmit_frame(b"frame", &mut w, id(2), 40).await.unwrap();
+        let control = ActionAnimationFrameControl {
+            mode: Some(AnimationMode::Run),
+            loop_mode: Some(LoopMode::Finite(NonZeroU32::new(2).unwrap())),
+            ..Default::default()
+        };
+        control_animation(&mut w, id(2), control).await.unwrap();
+
+        let decoded = decode_commands(&recorder.take()).unwrap();
+        let actions: Vec<_> =
+            decoded.iter().map(|d| d.command.action).collect();
+        assert_eq!(
+            actions,
+            [
+                Action::Put(ActionPut {
+                    move_cursor: false,
+                    placement: Placement(Some(id(2).0)),
+                    ..Default::default()
+                }),
+                Action::AnimationFrameLoading(ActionAnimationFrameLoading {
+                    transmission: Some(ActionTransmission {
+                        format: Format::Png,
+                        medium: Medium::Direct,
+                        ..Default::default()
+                    }),
+                    gap: 40,
+                    ..Default::default()
+                }),
+                Action::AnimationFrameControl(control),
+            ]
+        );
+        assert_eq!(decoded[1].payload, b"frame");
+    }
+
+    #[tokio::test]
+    async fn test_edit_frame() {
+        let (mut w, recorder) = TermWriter::new_recorder(false);
+        edit_frame(b"patch", &mut w, id(3), 12, 34).await.unwrap();
+
+        let decoded = decode_commands(&recorder.take()).unwrap();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].command.id, Some(id(3)));
+        assert_eq!(
+            decoded[0].command.action,
+            Action::AnimationFrameLoading(ActionAnimationFrameLoading {
+                transmission: Some(ActionTransmission {
+                    format: Format::Png,
+                    medium: Medium::Direct,
+                    ..Default::default()
+                }),
+                x: 12,
+                y: 34,
+                frame_edited: Some(Frame(NonZeroU32::new(1).unwrap())),
+                composition_mode: CompositionMode::Overwrite,
+                ..Default::default()
+            })
+        );
+        assert_eq!(decoded[0].payload, b"patch");
+    }
+}
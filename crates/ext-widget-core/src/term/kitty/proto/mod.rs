@@ -1,19 +1,23 @@
 mod action;
 mod actions;
 mod common;
+mod decode;
 
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    num::NonZeroU32,
+};
 
 pub use action::*;
 pub use actions::*;
 use base64::Engine;
 pub use common::*;
+pub use decode::{decode_commands, DecodedCommand};
 
 use crate::term::writer::TermWriter;
 
-// use crate::term::TermWriter;
-
 /// A command to the kitty graphics protocol.
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub struct Command {
     /// What action to do
     pub action: Action,
@@ -128,3 +132,61 @@ pub async fn delete_image(
     };
     cmd.send(None, w).await
 }
+
+/// Append a new png frame to the animation of an transmitted image.
+pub async fn transmit_frame(
+    data: &[u8], w: &mut TermWriter, id: ID, gap: i32,
+) -> anyhow::Result<()> {
+    let action = ActionAnimationFrameLoading {
+        transmission: Some(ActionTransmission {
+            format: Format::Png,
+            medium: Medium::Direct,
+            ..Default::default()
+        }),
+        gap,
+        ..Default::default()
+    };
+    let cmd = Command {
+        action: Action::AnimationFrameLoading(action),
+        quietness: Quietness::SuppressAll,
+        id: Some(id),
+    };
+    cmd.send(Some(data), w).await
+}
+
+/// Replace pixels of the root frame of an transmitted image with a png
+/// `data`, its top left corner is at `x`, `y` (in pixels) of the image.
+pub async fn edit_frame(
+    data: &[u8], w: &mut TermWriter, id: ID, x: u32, y: u32,
+) -> anyhow::Result<()> {
+    let action = ActionAnimationFrameLoading {
+        transmission: Some(ActionTransmission {
+            format: Format::Png,
+            medium: Medium::Direct,
+            ..Default::default()
+        }),
+        x,
+        y,
+        frame_edited: Some(Frame(NonZeroU32::MIN)),
+        // cleared pixels are transparent in the patch
+        composition_mode: CompositionMode::Overwrite,
+        ..Default::default()
+    };
+    let cmd = Command {
+        action: Action::AnimationFrameLoading(action),
+        quietness: Quietness::SuppressAll,
+        id: Some(id),
+    };
+    cmd.send(Some(data), w).await
+}
+
+pub async fn control_animation(
+    w: &mut TermWriter, id: ID, control: ActionAnimationFrameControl,
+) -> anyhow::Result<()> {
+    let cmd = Command {
+        action: Action::AnimationFrameControl(control),
+        quietness: Quietness::SuppressAll,
+        id: Some(id),
+    };
+    cmd.send(None, w).await
+}
@@ -4,4 +4,4 @@ mod writer;
 
 pub use kitty::*;
 pub use size::{get_term_size_info, get_term_size_info_fd, TermSizeInfo};
-pub use writer::TermWriter;
+pub use writer::{TermRecorder, TermWriter};
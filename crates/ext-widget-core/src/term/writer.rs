@@ -1,12 +1,16 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 use futures::AsyncWrite;
 use libc;
 use nvim_rs::Neovim;
-use tokio::{
-    fs::File,
-    io::{AsyncWriteExt, BufWriter},
-};
+use parking_lot::Mutex;
+use tokio::io::{AsyncWrite as TokioAsyncWrite, AsyncWriteExt, BufWriter};
 use tracing::info;
 
 use crate::{
@@ -15,8 +19,10 @@ use crate::{
     tmux::{tmux_escape_write, tmux_pane_tty},
 };
 
+type BoxedWrite = Box<dyn TokioAsyncWrite + Send + Unpin>;
+
 pub struct TermWriter {
-    inner: BufWriter<File>,
+    inner: BufWriter<BoxedWrite>,
     tmux: bool,
     tty: String,
 }
@@ -47,13 +53,8 @@ impl TermWriter {
             .custom_flags(libc::O_NOCTTY | libc::O_ACCMODE)
             .open(&tty)
             .await?;
-        let writer = BufWriter::new(writer);
 
-        Ok(Self {
-            inner: writer,
-            tmux,
-            tty,
-        })
+        Ok(Self::new_with_writer(writer, tmux, &tty))
     }
 
     pub async fn new_tmux_tty(tty: &str, tmux: bool) -> anyhow::Result<Self> {
@@ -65,13 +66,29 @@ impl TermWriter {
             .custom_flags(libc::O_NOCTTY | libc::O_ACCMODE)
             .open(tty)
             .await?;
-        let writer = BufWriter::new(writer);
 
-        Ok(Self {
-            inner: writer,
+        Ok(Self::new_with_writer(writer, tmux, tty))
+    }
+
+    /// Write to any writer instead of a tty, `name` is only for debugging.
+    pub fn new_with_writer<W>(writer: W, tmux: bool, name: &str) -> Self
+    where
+        W: TokioAsyncWrite + Send + Unpin + 'static,
+    {
+        let writer: BoxedWrite = Box::new(writer);
+        Self {
+            inner: BufWriter::new(writer),
             tmux,
-            tty: tty.to_string(),
-        })
+            tty: name.to_string(),
+        }
+    }
+
+    /// Write into memory, returns the writer and the recorder of all written
+    /// bytes.
+    pub fn new_recorder(tmux: bool) -> (Self, TermRecorder) {
+        let recorder = TermRecorder::default();
+        let writer = Self::new_with_writer(recorder.clone(), tmux, "<memory>");
+        (writer, recorder)
     }
 
     pub async fn write_all(
@@ -90,3 +107,42 @@ impl TermWriter {
         Ok(())
     }
 }
+
+/// Records everything written to a `TermWriter` in memory.
+#[derive(Debug, Clone, Default)]
+pub struct TermRecorder {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl TermRecorder {
+    /// All bytes written so far.
+    pub fn bytes(&self) -> Vec<u8> {
+        self.buf.lock().clone()
+    }
+
+    /// Take all bytes written so far, and clear the recorder.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.buf.lock())
+    }
+}
+
+impl TokioAsyncWrite for TermRecorder {
+    fn poll_write(
+        self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buf.lock().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>, _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>, _cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
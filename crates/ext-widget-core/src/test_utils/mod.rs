@@ -1,6 +1,11 @@
 mod nvim;
+mod snapshot;
 
 pub use nvim::{start_embed_nvim, EmbedNvim};
+pub use snapshot::{
+    assert_snapshot, png_pixel, render_widget_png, test_font_collection,
+    test_paragraph, TEST_FONT, TEST_MONO_FONT,
+};
 
 use std::{cell::RefCell, rc::Rc};
 
@@ -0,0 +1,285 @@
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::Context;
+use skia_safe::{
+    image::CachingHint,
+    textlayout::{
+        FontCollection, Paragraph, ParagraphBuilder, ParagraphStyle,
+        TypefaceFontProvider,
+    },
+    AlphaType, ColorType, Data, FontMgr, Image, ImageInfo,
+};
+
+use crate::{
+    painting::Renderer,
+    widgets::{Widget, WidgetTree},
+};
+
+pub const TEST_FONT: &str = "DejaVu Sans";
+pub const TEST_MONO_FONT: &str = "DejaVu Sans Mono";
+
+/// Set to bless snapshots: missing and mismatched snapshots are written.
+const BLESS_ENV: &str = "EXT_WIDGET_BLESS";
+
+/// A channel differs if it's off by more than this.
+const CHANNEL_TOLERANCE: u8 = 24;
+/// Max ratio of differing pixels, covers anti-aliasing noise.
+const PIXEL_TOLERANCE: f64 = 0.001;
+
+macro_rules! test_font {
+    ($name:literal) => {
+        include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fonts/",
+            $name
+        ))
+    };
+}
+
+static SANS_FONTS: &[&[u8]] = &[
+    test_font!("DejaVuSans.ttf"),
+    test_font!("DejaVuSans-Bold.ttf"),
+    test_font!("DejaVuSans-Oblique.ttf"),
+];
+static MONO_FONTS: &[&[u8]] = &[test_font!("DejaVuSansMono.ttf")];
+
+/// A font collection with only the bundled fonts, system fonts are never
+/// used so snapshots are the same on every machine.
+pub fn test_font_collection() -> FontCollection {
+    let font_mgr = FontMgr::new();
+    let mut provider = TypefaceFontProvider::new();
+    for data in SANS_FONTS {
+        let typeface = font_mgr.new_from_data(data, None).unwrap();
+        provider.register_typeface(typeface.clone(), None::<&str>);
+        // skia's default family
+        provider.register_typeface(typeface, Some("sans-serif"));
+    }
+    for data in MONO_FONTS {
+        let typeface = font_mgr.new_from_data(data, None).unwrap();
+        provider.register_typeface(typeface, None::<&str>);
+    }
+    let mut fonts = FontCollection::new();
+    fonts.set_asset_font_manager(Some(provider.into()));
+    fonts.disable_font_fallback();
+    fonts
+}
+
+/// A paragraph of `text` in the test font.
+pub fn test_paragraph(
+    fonts: &FontCollection, text: &str, font_size: f32,
+) -> Paragraph {
+    let mut builder = ParagraphBuilder::new(&ParagraphStyle::default(), fonts);
+    let mut style = builder.peek_style();
+    style.set_font_families(&[TEST_FONT]);
+    style.set_font_size(font_size);
+    builder.push_style(&style);
+    builder.add_text(text);
+    builder.build()
+}
+
+/// Layout `widget` in `width` x `height`, and paint it into a PNG of its
+/// result size.
+pub fn render_widget_png(
+    widget: Rc<dyn Widget>, width: f32, height: f32,
+) -> anyhow::Result<Vec<u8>> {
+    let mut tree = WidgetTree::new();
+    tree.new_root(widget)?;
+    tree.compute_layout(width, height)?;
+    let size = tree.result_size()?;
+
+    let renderer = Rc::new(RefCell::new(Renderer::new(
+        size.width.ceil().max(1.) as u32,
+        size.height.ceil().max(1.) as u32,
+    )?));
+    tree.paint(renderer.clone())?;
+    let png = renderer.borrow_mut().snapshot_png_raw()?;
+    Ok(png)
+}
+
+fn snapshot_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots")
+}
+
+/// The unpremultiplied RGBA of the pixel at `x`, `y` of `png`.
+pub fn png_pixel(png: &[u8], x: i32, y: i32) -> anyhow::Result<[u8; 4]> {
+    let image = decode_rgba(png)?;
+    anyhow::ensure!(
+        (0..image.width).contains(&x) && (0..image.height).contains(&y),
+        "({}, {}) is out of the {}x{} image",
+        x,
+        y,
+        image.width,
+        image.height
+    );
+    let i = (y * image.width + x) as usize * 4;
+    Ok(image.pixels[i..i + 4].try_into()?)
+}
+
+/// Compare `png` with the snapshot `tests/snapshots/<name>.png`.
+///
+/// On mismatch, `<name>.actual.png` and `<name>.diff.png` are written next
+/// to the snapshot. Set `EXT_WIDGET_BLESS=1` to accept the new image.
+/// A missing snapshot fails too, it's only written when blessing.
+pub fn assert_snapshot(name: &str, png: &[u8]) {
+    if let Err(e) = check_snapshot(&snapshot_dir(), name, png) {
+        panic!("snapshot '{}': {:#}", name, e);
+    }
+}
+
+fn check_snapshot(dir: &Path, name: &str, png: &[u8]) -> anyhow::Result<()> {
+    let expected_path = dir.join(format!("{}.png", name));
+    let actual_path = dir.join(format!("{}.actual.png", name));
+    let diff_path = dir.join(format!("{}.diff.png", name));
+    let bless = std::env::var_os(BLESS_ENV).is_some();
+
+    if !expected_path.exists() {
+        if bless {
+            eprintln!("snapshot '{}': new snapshot written", name);
+            std::fs::write(&expected_path, png)?;
+            return Ok(());
+        }
+        std::fs::write(&actual_path, png)?;
+        anyhow::bail!(
+            "missing, wrote {:?}, run with {}=1 to accept it",
+            actual_path,
+            BLESS_ENV
+        );
+    }
+
+    let expected = std::fs::read(&expected_path)?;
+    let expected = decode_rgba(&expected).context("bad snapshot")?;
+    let actual = decode_rgba(png)?;
+    let diff = compare(&expected, &actual);
+    let _ = std::fs::remove_file(&actual_path);
+    let _ = std::fs::remove_file(&diff_path);
+    let Some(diff) = diff else {
+        return Ok(());
+    };
+
+    if bless {
+        eprintln!("snapshot '{}': blessed", name);
+        std::fs::write(&expected_path, png)?;
+        return Ok(());
+    }
+    std::fs::write(&actual_path, png)?;
+    if let Some(image) = &diff.image {
+        std::fs::write(&diff_path, image.encode_png()?)?;
+    }
+    anyhow::bail!(
+        "{}, wrote {:?}, run with {}=1 to accept it",
+        diff.reason,
+        actual_path,
+        BLESS_ENV
+    )
+}
+
+/// Unpremultiplied RGBA pixels.
+struct RgbaImage {
+    width: i32,
+    height: i32,
+    pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    fn info(width: i32, height: i32) -> ImageInfo {
+        ImageInfo::new(
+            (width, height),
+            ColorType::RGBA8888,
+            AlphaType::Unpremul,
+            None,
+        )
+    }
+
+    fn encode_png(&self) -> anyhow::Result<Vec<u8>> {
+        let mut renderer =
+            Renderer::new(self.width as u32, self.height as u32)?;
+        let info = Self::info(self.width, self.height);
+        let row_bytes = self.width as usize * 4;
+        if !renderer
+            .canvas()
+            .write_pixels(&info, &self.pixels, row_bytes, (0, 0))
+        {
+            anyhow::bail!("Failed to write pixels");
+        }
+        renderer.snapshot_png_raw()
+    }
+}
+
+fn decode_rgba(png: &[u8]) -> anyhow::Result<RgbaImage> {
+    let image = Image::from_encoded(Data::new_copy(png))
+        .context("Failed to decode png")?;
+    let (width, height) = (image.width(), image.height());
+    let info = RgbaImage::info(width, height);
+    let row_bytes = width as usize * 4;
+    let mut pixels = vec![0u8; row_bytes * height as usize];
+    if !image.read_pixels(
+        &info,
+        &mut pixels,
+        row_bytes,
+        (0, 0),
+        CachingHint::Disallow,
+    ) {
+        anyhow::bail!("Failed to read pixels");
+    }
+    Ok(RgbaImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+struct SnapshotDiff {
+    reason: String,
+    /// Differing pixels in red over a faded copy of the snapshot.
+    image: Option<RgbaImage>,
+}
+
+fn compare(expected: &RgbaImage, actual: &RgbaImage) -> Option<SnapshotDiff> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return Some(SnapshotDiff {
+            reason: format!(
+                "size changed from {}x{} to {}x{}",
+                expected.width, expected.height, actual.width, actual.height
+            ),
+            image: None,
+        });
+    }
+
+    let mut differing = 0usize;
+    let mut image = Vec::with_capacity(expected.pixels.len());
+    for (e, a) in expected
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+    {
+        let differs = e
+            .iter()
+            .zip(a)
+            .any(|(e, a)| e.abs_diff(*a) > CHANNEL_TOLERANCE);
+        if differs {
+            differing += 1;
+            image.extend_from_slice(&[0xff, 0, 0, 0xff]);
+        } else {
+            let gray = (e[0] as u16 + e[1] as u16 + e[2] as u16) / 3;
+            let faded = (gray / 4 + 0xc0) as u8;
+            image.extend_from_slice(&[faded, faded, faded, 0xff]);
+        }
+    }
+
+    let total = expected.pixels.len() / 4;
+    if differing as f64 <= total as f64 * PIXEL_TOLERANCE {
+        return None;
+    }
+    Some(SnapshotDiff {
+        reason: format!("{} of {} pixels differ", differing, total),
+        image: Some(RgbaImage {
+            width: expected.width,
+            height: expected.height,
+            pixels: image,
+        }),
+    })
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use libloading::Library;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::Regex;
+use tracing::warn;
+use tree_sitter::{Language, Parser, Query};
+
+static INHERITS_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r";+\s*inherits\s*:?\s*([a-z_,()-]+)\s*").unwrap());
+
+/**
+ * Loads tree-sitter parsers and queries from runtime paths, laid out like
+ * Neovim's: `parser/<lang>.so` and `queries/<lang>/<name>.scm`.
+ *
+ * Loaded libraries and queries are cached.
+ */
+#[derive(Debug, Default)]
+pub struct TreeSitterLoader {
+    libs: Mutex<HashMap<PathBuf, Arc<Library>>>,
+    queries: Mutex<HashMap<String, String>>,
+}
+
+impl TreeSitterLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load_parser(
+        &self, rtps: &[PathBuf], lang: &str,
+    ) -> anyhow::Result<Option<Parser>> {
+        let lang = self
+            .load_language(rtps, lang)?
+            .context("No language parser")?;
+        let mut parser = Parser::new();
+        parser.set_language(lang)?;
+        Ok(Some(parser))
+    }
+
+    pub fn load_query(
+        &self, rtps: &[PathBuf], lang: &str, query: &str,
+    ) -> anyhow::Result<Query> {
+        let query = self.read_query(rtps, lang, query);
+        let lang = self
+            .load_language(rtps, lang)?
+            .context("No language parser")?;
+        let query = Query::new(lang, &query)?;
+        Ok(query)
+    }
+
+    fn load_language(
+        &self, rtps: &[PathBuf], lang: &str,
+    ) -> anyhow::Result<Option<Language>> {
+        let path = PathBuf::from(format!("parser/{}.so", lang));
+        let lib = self.load_lib(rtps, path)?;
+        if lib.is_none() {
+            return Ok(None);
+        }
+        let lib = lib.unwrap();
+        let func_name = format!("tree_sitter_{}", lang.replace('-', "_"));
+        unsafe {
+            let entry = lib.get::<unsafe extern "C" fn() -> Language>(
+                func_name.as_bytes(),
+            )?;
+            let parser = entry();
+            Ok(Some(parser))
+        }
+    }
+
+    fn load_lib(
+        &self, rtps: &[PathBuf], path: PathBuf,
+    ) -> anyhow::Result<Option<Arc<Library>>> {
+        {
+            if let Some(lib) = self.libs.lock().get(&path) {
+                return Ok(Some(lib.clone()));
+            }
+        }
+
+        let file = find_file_in_runtime_paths(rtps, &path);
+        if file.is_none() {
+            return Ok(None);
+        }
+        let file = unsafe { file.unwrap_unchecked() };
+        unsafe {
+            let lib = Arc::new(Library::new(file)?);
+            self.libs.lock().insert(path, lib.clone());
+            Ok(Some(lib))
+        }
+    }
+
+    fn read_query(
+        &self, rtps: &[PathBuf], lang: &str, filename: &str,
+    ) -> String {
+        let key = format!("{}/{}", lang, filename);
+        if let Some(query) = self.queries.lock().get(&key) {
+            return query.clone();
+        }
+
+        let ret =
+            read_query_inherited(rtps, lang, filename, &mut HashSet::new());
+
+        {
+            self.queries.lock().insert(key, ret.clone());
+        }
+
+        ret
+    }
+}
+
+/// Read the query of `lang`, with the queries it inherits. Languages in
+/// `visited` are skipped, so inheritance cycles terminate.
+fn read_query_inherited(
+    rtps: &[PathBuf], lang: &str, filename: &str, visited: &mut HashSet<String>,
+) -> String {
+    if !visited.insert(lang.to_string()) {
+        warn!("Query {}/{} is inherited more than once", lang, filename);
+        return String::new();
+    }
+    let query = read_query_raw(rtps, lang, filename).unwrap_or_default();
+
+    // replaces all "; inherits <language>(,<language>)*" with the queries of
+    // the given language(s)
+    let replaced = match INHERITS_REGEX.captures(&query) {
+        Some(captures) => {
+            let mut ret = String::new();
+            let parts = captures.get(1).unwrap().as_str();
+            for part in parts.split(',') {
+                let _ = write!(
+                    &mut ret,
+                    "\n{}\n",
+                    read_query_inherited(rtps, part, filename, visited)
+                );
+            }
+            ret
+        }
+        None => "".to_string(),
+    };
+    INHERITS_REGEX
+        .replace_all(&query, replaced.as_str())
+        .to_string()
+}
+
+fn read_query_raw(
+    rtps: &[PathBuf], lang: &str, filename: &str,
+) -> anyhow::Result<String> {
+    let path = PathBuf::from(format!("queries/{}/{}.scm", lang, filename));
+    let query_file = find_file_in_runtime_paths(rtps, path);
+    if query_file.is_none() {
+        return Ok(String::new());
+    }
+    let query_file = query_file.unwrap();
+    Ok(std::fs::read_to_string(query_file)?)
+}
+
+/**
+ * Find a file in the given runtime paths, the first match wins.
+ *
+ * NO CACHING
+ */
+pub fn find_file_in_runtime_paths<P>(
+    rtps: &[PathBuf], path: P,
+) -> Option<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    for rtp in rtps {
+        let p = rtp.join(path.as_ref());
+        if p.exists() && p.is_file() {
+            return Some(p);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::TreeSitterLoader;
+
+    fn write_query(rtp: &Path, lang: &str, text: &str) -> anyhow::Result<()> {
+        let dir = rtp.join("queries").join(lang);
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("highlights.scm"), text)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_inherits_cycle() -> anyhow::Result<()> {
+        let rtp = std::env::temp_dir()
+            .join(format!("ext-widget-queries-{}", std::process::id()));
+        write_query(&rtp, "a", "; inherits: b\n(a)\n")?;
+        write_query(&rtp, "b", "; inherits: a,c\n(b)\n")?;
+        write_query(&rtp, "c", "(c)\n")?;
+
+        let loader = TreeSitterLoader::new();
+        let query = loader.read_query(&[rtp.clone()], "a", "highlights");
+        std::fs::remove_dir_all(&rtp)?;
+        for pattern in ["(a)", "(b)", "(c)"] {
+            assert_eq!(query.matches(pattern).count(), 1, "{}", query);
+        }
+        Ok(())
+    }
+}
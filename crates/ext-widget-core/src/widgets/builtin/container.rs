@@ -1,6 +1,6 @@
 use std::rc::Rc;
 
-use skia_safe::Paint;
+use skia_safe::{BlurStyle, ClipOp, MaskFilter, Paint, RRect};
 
 use crate::{
     painting::{BoxDecoration, Location, Margin, RectSize, SpacePolicy},
@@ -103,13 +103,39 @@ impl Widget for Container {
                 context.size.width * p
             }
         };
+        if let Some(shadow) = &self.decoration.shadow {
+            let mut shadow_paint = Paint::default();
+            shadow_paint.set_color(shadow.color);
+            if shadow.blur_radius > 0.0 {
+                shadow_paint.set_mask_filter(MaskFilter::blur(
+                    BlurStyle::Normal,
+                    shadow.blur_radius,
+                    None,
+                ));
+            }
+            // the box is cut out, or the shadow shows through a translucent
+            // background
+            canvas.save();
+            canvas.clip_rrect(
+                RRect::new_rect_xy(rect, radius, radius),
+                ClipOp::Difference,
+                true,
+            );
+            canvas.draw_round_rect(
+                rect.with_offset((shadow.offset.x, shadow.offset.y)),
+                radius,
+                radius,
+                &shadow_paint,
+            );
+            canvas.restore();
+        }
+        // a transparent background draws nothing
         if self.decoration.color.alpha > 0 {
             let color: skia_safe::Color = self.decoration.color.into();
             fill_paint.set_color(color);
+            canvas.draw_round_rect(rect, radius, radius, &fill_paint);
         }
 
-        canvas.draw_round_rect(rect, radius, radius, &fill_paint);
-
         let border_top_left = context.top_left_location
             + Location {
                 x: self.decoration.border.width / 2.0,
@@ -144,5 +170,10 @@ impl Container {
         self.decoration.color.alpha > 0
             || (self.decoration.border.color.alpha > 0
                 && self.decoration.border.width > 0.0)
+            || self
+                .decoration
+                .shadow
+                .as_ref()
+                .is_some_and(|shadow| shadow.color.alpha > 0)
     }
 }
@@ -0,0 +1,80 @@
+use std::{fmt::Debug, rc::Rc};
+
+use crate::{
+    painting::{GridCell, LayoutMode, RenderCtx},
+    widgets::{
+        widget::{LayoutElement, Widget, WidgetKey},
+        BoxOptions,
+    },
+};
+
+use super::placed::Placed;
+
+/// Lays out children in the tracks of `options.template`, children are
+/// placed in the next free cells unless added with a [`GridCell`].
+#[derive(Clone)]
+pub struct Grid {
+    key: WidgetKey,
+    options: BoxOptions,
+    children: Vec<Rc<dyn Widget>>,
+}
+
+impl Grid {
+    pub fn new(options: BoxOptions) -> Self {
+        Self::new_with_children(options, vec![])
+    }
+
+    pub fn new_with_children(
+        options: BoxOptions, children: Vec<Rc<dyn Widget>>,
+    ) -> Self {
+        let key = WidgetKey::next();
+        Self {
+            key,
+            options,
+            children,
+        }
+    }
+
+    pub fn add_child(&mut self, child: Rc<dyn Widget>) {
+        self.children.push(child);
+    }
+
+    /// Add `child` taking `cell`, see [`GridCell`].
+    pub fn add_child_at(&mut self, child: Rc<dyn Widget>, cell: GridCell) {
+        let placed = Placed::new(child, move |options| options.cell = cell);
+        self.children.push(Rc::new(placed));
+    }
+}
+
+impl Debug for Grid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Grid")
+            .field("template", &self.options.template)
+            .field("children", &self.children.len())
+            .finish()
+    }
+}
+
+impl LayoutElement for Grid {
+    fn style(&self) -> BoxOptions {
+        BoxOptions {
+            display: LayoutMode::Grid,
+            ..self.options.clone()
+        }
+    }
+}
+
+impl Widget for Grid {
+    fn key(&self) -> WidgetKey {
+        self.key
+    }
+
+    fn children(&self) -> Vec<Rc<dyn Widget>> {
+        self.children.clone()
+    }
+
+    fn paint(&self, _render: &mut RenderCtx<'_>) -> anyhow::Result<()> {
+        // skip paint
+        Ok(())
+    }
+}
@@ -1,11 +1,670 @@
 mod column;
 mod container;
+mod grid;
+mod placed;
 mod rich_text;
 mod row;
+mod stack;
+mod stateful_widget;
 mod stateless_widget;
 
 pub use column::Column;
 pub use container::Container;
+pub use grid::Grid;
 pub use rich_text::RichText;
 pub use row::Row;
+pub use stack::{Overlay, Stack, StackAnchor};
+pub use stateful_widget::{StatefulWidget, StatefulWidgetPod};
 pub use stateless_widget::{StatelessWidget, StatelessWidgetPod};
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{
+        painting::{
+            BoxBorder, BoxConstraints, BoxDecoration, BoxShadow, BoxSize,
+            Color, FlexibleLength, FlexibleLengthAuto, GridCell, GridTemplate,
+            Inset, Justify, Location, Margin, Padding, Position, RectSize,
+            Region, RenderCtx, Renderer, TrackSize, Wrap,
+        },
+        test_utils::{
+            assert_snapshot, png_pixel, render_widget_png,
+            test_font_collection, test_paragraph,
+        },
+        widgets::{
+            widget::{
+                BuildContext, LayoutElement, Widget, WidgetKey, WidgetTree,
+            },
+            BoxOptions, Column, Container, Grid, Overlay, RichText, Row, Stack,
+            StackAnchor, StatefulWidget, StatefulWidgetPod, StatelessWidget,
+            StatelessWidgetPod,
+        },
+    };
+
+    fn square(color: u32, size: f32) -> Rc<dyn Widget> {
+        Rc::new(Container::new(
+            BoxDecoration {
+                color: Color::new(color),
+                border: BoxBorder::NONE,
+                shadow: None,
+            },
+            BoxOptions {
+                constraints: BoxConstraints {
+                    min_width: FlexibleLengthAuto::Fixed(size),
+                    max_width: FlexibleLengthAuto::Fixed(size),
+                    min_height: FlexibleLengthAuto::Fixed(size),
+                    max_height: FlexibleLengthAuto::Fixed(size),
+                },
+                ..Default::default()
+            },
+        ))
+    }
+
+    #[test]
+    fn test_container_snapshot() -> anyhow::Result<()> {
+        let container = Container::new_with_child(
+            BoxDecoration {
+                color: Color::new(0x1a1b26),
+                border: BoxBorder {
+                    width: 2.0,
+                    color: Color::new(0x7aa2f7),
+                    radius: 6.0.into(),
+                },
+                shadow: None,
+            },
+            BoxOptions {
+                padding: Padding::all(8.0.into()),
+                margin: Margin::all(4.0.into()),
+                ..Default::default()
+            },
+            square(0xf7768e, 24.0),
+        );
+        let png = render_widget_png(Rc::new(container), 200.0, 200.0)?;
+        assert_snapshot("builtin_container", &png);
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_shadow_snapshot() -> anyhow::Result<()> {
+        let container = Container::new_with_child(
+            BoxDecoration {
+                color: Color::new(0xffffff),
+                border: BoxBorder {
+                    width: 1.0,
+                    color: Color::new(0x565f89),
+                    radius: 4.0.into(),
+                },
+                shadow: Some(BoxShadow {
+                    color: Color::new(0x00000080),
+                    blur_radius: 3.0,
+                    offset: Location { x: 2.0, y: 2.0 },
+                }),
+            },
+            BoxOptions {
+                padding: Padding::all(8.0.into()),
+                margin: Margin::all(12.0.into()),
+                ..Default::default()
+            },
+            square(0x9ece6a, 24.0),
+        );
+        let png = render_widget_png(Rc::new(container), 200.0, 200.0)?;
+        assert_snapshot("builtin_container_shadow", &png);
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_shadow_behind_translucent_box() -> anyhow::Result<()> {
+        let container = Container::new(
+            BoxDecoration {
+                color: Color::new_from_rgba(0, 0, 255, 128),
+                border: BoxBorder::NONE,
+                shadow: Some(BoxShadow {
+                    color: Color::new_from_rgba(0, 0, 0, 255),
+                    blur_radius: 0.0,
+                    offset: Location { x: 4.0, y: 4.0 },
+                }),
+            },
+            BoxOptions {
+                constraints: BoxConstraints {
+                    min_width: FlexibleLengthAuto::Fixed(40.0),
+                    max_width: FlexibleLengthAuto::Fixed(40.0),
+                    min_height: FlexibleLengthAuto::Fixed(40.0),
+                    max_height: FlexibleLengthAuto::Fixed(40.0),
+                },
+                ..Default::default()
+            },
+        );
+        let png = render_widget_png(Rc::new(container), 100.0, 100.0)?;
+        // only the background is under the box, the shadow is cut out
+        let [red, green, blue, alpha] = png_pixel(&png, 20, 20)?;
+        assert_eq!((red, green, alpha), (0, 0, 128));
+        assert!(blue >= 254, "blue {}", blue);
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_snapshot() -> anyhow::Result<()> {
+        let column = Column::new_with_gap_children(
+            4.0.into(),
+            vec![
+                square(0xf7768e, 16.0),
+                square(0x9ece6a, 24.0),
+                square(0x7aa2f7, 32.0),
+            ],
+        );
+        let png = render_widget_png(Rc::new(column), 200.0, 200.0)?;
+        assert_snapshot("builtin_column", &png);
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_snapshot() -> anyhow::Result<()> {
+        let row = Row::new_with_children(vec![
+            square(0xf7768e, 16.0),
+            square(0x9ece6a, 24.0),
+            square(0x7aa2f7, 32.0),
+        ]);
+        let png = render_widget_png(Rc::new(row), 200.0, 200.0)?;
+        assert_snapshot("builtin_row", &png);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rich_text_snapshot() -> anyhow::Result<()> {
+        let fonts = test_font_collection();
+        let text = "The quick brown fox jumps over the lazy dog, \
+                    and wraps at the width of the tree.";
+        let rich_text = RichText::new_with_paragraph(
+            test_paragraph(&fonts, text, 16.0),
+            Some(text.to_string()),
+        );
+        let png = render_widget_png(Rc::new(rich_text), 240.0, 400.0)?;
+        assert_snapshot("builtin_rich_text", &png);
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Swatches;
+
+    impl StatelessWidget for Swatches {
+        fn build(&self, _context: &BuildContext) -> Rc<dyn Widget> {
+            Rc::new(Row::new_with_children(vec![
+                square(0xe0af68, 20.0),
+                square(0xbb9af7, 20.0),
+            ]))
+        }
+    }
+
+    #[test]
+    fn test_stateless_widget_snapshot() -> anyhow::Result<()> {
+        let widget = StatelessWidgetPod::new(Swatches);
+        widget.build(&BuildContext::default())?;
+        let png = render_widget_png(Rc::new(widget), 200.0, 200.0)?;
+        assert_snapshot("builtin_stateless_widget", &png);
+        Ok(())
+    }
+
+    /// Shows the first `count` squares, kept across builds.
+    #[derive(Debug)]
+    struct Counter {
+        squares: Vec<Rc<dyn Widget>>,
+    }
+
+    impl StatefulWidget for Counter {
+        type State = usize;
+
+        fn build(
+            &self, count: &usize, _context: &BuildContext,
+        ) -> Rc<dyn Widget> {
+            Rc::new(Row::new_with_children(self.squares[..*count].to_vec()))
+        }
+    }
+
+    fn paint_tree(
+        tree: &mut WidgetTree, renderer: &Rc<RefCell<Renderer>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        tree.compute_layout(100.0, 20.0)?;
+        match tree.damage()? {
+            Some(region) => tree.paint_region(renderer.clone(), region)?,
+            None => tree.paint(renderer.clone())?,
+        }
+        let png = renderer.borrow_mut().snapshot_png_raw()?;
+        Ok(png)
+    }
+
+    #[test]
+    fn test_stateful_widget_rebuild() -> anyhow::Result<()> {
+        let counter = Counter {
+            squares: [0xf7768e, 0xe0af68, 0x9ece6a, 0x7aa2f7]
+                .into_iter()
+                .map(|color| square(color, 20.0))
+                .collect(),
+        };
+        let widget = Rc::new(StatefulWidgetPod::new(counter, 2));
+        let mut tree = WidgetTree::new();
+        tree.new_root(widget.clone())?;
+        let renderer = Rc::new(RefCell::new(Renderer::new(100, 20)?));
+        tree.compute_layout(100.0, 20.0)?;
+        tree.paint(renderer.clone())?;
+        assert!(!tree.rebuild()?);
+        assert_eq!(tree.damage()?, None);
+
+        widget.set_state(|count| *count = 3);
+        assert!(tree.needs_rebuild());
+        assert!(tree.rebuild()?);
+        tree.compute_layout(100.0, 20.0)?;
+        let damage = tree.damage()?.expect("the new square is damaged");
+        let added = Region {
+            x: 40.0,
+            y: 0.0,
+            width: 20.0,
+            height: 20.0,
+        };
+        assert_eq!(damage.union(&added), damage);
+        let incremental = paint_tree(&mut tree, &renderer)?;
+        assert_eq!(tree.damage()?, None);
+
+        // a removed square is cleared
+        widget.set_state(|count| *count = 1);
+        assert!(tree.rebuild()?);
+        let shrunk = paint_tree(&mut tree, &renderer)?;
+
+        let mut fresh = WidgetTree::new();
+        fresh.new_root(Rc::new(StatefulWidgetPod::new(
+            Counter {
+                squares: widget.widget().squares.clone(),
+            },
+            3,
+        )))?;
+        let renderer = Rc::new(RefCell::new(Renderer::new(100, 20)?));
+        assert_eq!(incremental, paint_tree(&mut fresh, &renderer)?);
+
+        let mut fresh = WidgetTree::new();
+        fresh.new_root(Rc::new(StatefulWidgetPod::new(
+            Counter {
+                squares: widget.widget().squares.clone(),
+            },
+            1,
+        )))?;
+        let renderer = Rc::new(RefCell::new(Renderer::new(100, 20)?));
+        assert_eq!(shrunk, paint_tree(&mut fresh, &renderer)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stateful_widget_before_build() {
+        let counter = Counter {
+            squares: vec![square(0xf7768e, 20.0), square(0xe0af68, 20.0)],
+        };
+        let widget = Rc::new(StatefulWidgetPod::new(counter, 1));
+        // built with its current state on first use
+        assert_eq!(widget.type_name(), "Row");
+        assert_eq!(widget.children().len(), 1);
+        widget.set_state(|count| *count = 2);
+        assert_eq!(widget.children().len(), 1);
+
+        // the tree doesn't reuse the child built outside of it
+        let mut tree = WidgetTree::new();
+        tree.new_root(widget.clone()).unwrap();
+        assert_eq!(widget.children().len(), 2);
+        assert!(!tree.needs_rebuild());
+    }
+
+    /// A box as high as `height`, as wide as its cell.
+    fn bar(color: u32, height: f32) -> Rc<dyn Widget> {
+        Rc::new(Container::new(
+            BoxDecoration {
+                color: Color::new(color),
+                border: BoxBorder::NONE,
+                shadow: None,
+            },
+            BoxOptions {
+                constraints: BoxConstraints {
+                    min_height: FlexibleLengthAuto::Fixed(height),
+                    max_height: FlexibleLengthAuto::Fixed(height),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+    }
+
+    #[test]
+    fn test_grid_layout() -> anyhow::Result<()> {
+        let columns: Vec<TrackSize> =
+            serde_json::from_str(r#"[40, "1fr", "2fr"]"#)?;
+        let mut grid = Grid::new(BoxOptions {
+            size: BoxSize {
+                width: FlexibleLengthAuto::Fixed(200.0),
+                height: FlexibleLengthAuto::Auto,
+            },
+            gap: RectSize {
+                width: FlexibleLength::Fixed(5.0),
+                height: FlexibleLength::Fixed(5.0),
+            },
+            template: GridTemplate {
+                columns,
+                rows: vec![],
+            },
+            ..Default::default()
+        });
+        let first = bar(0xf7768e, 20.0);
+        let spanned = bar(0xe0af68, 20.0);
+        let wrapped = bar(0x9ece6a, 10.0);
+        let placed = bar(0x7aa2f7, 10.0);
+        grid.add_child(first.clone());
+        grid.add_child_at(spanned.clone(), GridCell::default().with_span(2, 1));
+        grid.add_child(wrapped.clone());
+        grid.add_child_at(placed.clone(), GridCell::at(3, 2));
+        // ends at the last line instead of adding columns after it
+        let last = bar(0xbb9af7, 10.0);
+        grid.add_child_at(last.clone(), GridCell::at(-1, 3).with_span(2, 1));
+
+        let mut tree = WidgetTree::new();
+        tree.new_root(Rc::new(grid))?;
+        tree.compute_layout(400.0, 400.0)?;
+        let region = |widget: &Rc<dyn Widget>| tree.region(widget.key());
+
+        // 40px, then 1fr and 2fr of the 150px left after gaps
+        let expected = |x, y, width, height| Region {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(region(&first)?, expected(0.0, 0.0, 40.0, 20.0));
+        assert_eq!(region(&spanned)?, expected(45.0, 0.0, 155.0, 20.0));
+        assert_eq!(region(&wrapped)?, expected(0.0, 25.0, 40.0, 10.0));
+        assert_eq!(region(&placed)?, expected(100.0, 25.0, 100.0, 10.0));
+        assert_eq!(region(&last)?, expected(45.0, 40.0, 155.0, 10.0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_stack_overlays() -> anyhow::Result<()> {
+        let base = square(0x1a1b26, 100.0);
+        let badge = square(0xf7768e, 10.0);
+        let hint = square(0x7aa2f7, 20.0);
+        let corner = square(0x9ece6a, 10.0);
+        let mut stack = Stack::new(BoxOptions::default());
+        stack.add_child(base.clone());
+        stack.add_overlay(
+            badge.clone(),
+            Overlay::new(StackAnchor::TopRight).with_offset(-2.0, 2.0),
+        );
+        stack.add_overlay(hint.clone(), Overlay::new(StackAnchor::Center));
+        stack.add_overlay(
+            corner.clone(),
+            Overlay::new(StackAnchor::BottomLeft),
+        );
+
+        let mut tree = WidgetTree::new();
+        tree.new_root(Rc::new(stack))?;
+        tree.compute_layout(400.0, 400.0)?;
+        let region = |widget: &Rc<dyn Widget>| tree.region(widget.key());
+        let expected = |x, y, size| Region {
+            x,
+            y,
+            width: size,
+            height: size,
+        };
+        assert_eq!(region(&base)?, expected(0.0, 0.0, 100.0));
+        assert_eq!(region(&badge)?, expected(88.0, 2.0, 10.0));
+        assert_eq!(region(&hint)?, expected(40.0, 40.0, 20.0));
+        assert_eq!(region(&corner)?, expected(0.0, 90.0, 10.0));
+        Ok(())
+    }
+
+    /// Records the order widgets are painted in.
+    #[derive(Debug)]
+    struct PaintProbe {
+        key: WidgetKey,
+        name: &'static str,
+        painted: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl LayoutElement for PaintProbe {
+        fn style(&self) -> BoxOptions {
+            BoxOptions::default()
+        }
+    }
+
+    impl Widget for PaintProbe {
+        fn key(&self) -> WidgetKey {
+            self.key
+        }
+
+        fn paint(&self, _render: &mut RenderCtx<'_>) -> anyhow::Result<()> {
+            self.painted.borrow_mut().push(self.name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stack_paint_order() -> anyhow::Result<()> {
+        let painted = Rc::new(RefCell::new(vec![]));
+        let probe = |name| -> Rc<dyn Widget> {
+            Rc::new(PaintProbe {
+                key: WidgetKey::next(),
+                name,
+                painted: painted.clone(),
+            })
+        };
+        let mut stack = Stack::new(BoxOptions::default());
+        stack.add_child(probe("base"));
+        stack.add_overlay(probe("top"), Overlay::default().with_z_index(1));
+        stack.add_overlay(probe("bottom"), Overlay::default().with_z_index(-1));
+        stack.add_overlay(probe("middle"), Overlay::default());
+        render_widget_png(Rc::new(stack), 100.0, 100.0)?;
+        assert_eq!(*painted.borrow(), ["bottom", "base", "middle", "top"]);
+        Ok(())
+    }
+
+    /// A flex box with `options`, which no builtin widget exposes.
+    #[derive(Debug)]
+    struct FlexBox {
+        key: WidgetKey,
+        options: BoxOptions,
+        children: Vec<Rc<dyn Widget>>,
+    }
+
+    impl LayoutElement for FlexBox {
+        fn style(&self) -> BoxOptions {
+            self.options.clone()
+        }
+    }
+
+    impl Widget for FlexBox {
+        fn key(&self) -> WidgetKey {
+            self.key
+        }
+
+        fn children(&self) -> Vec<Rc<dyn Widget>> {
+            self.children.clone()
+        }
+
+        fn paint(&self, _render: &mut RenderCtx<'_>) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sized(width: f32, height: f32) -> BoxSize {
+        BoxSize {
+            width: FlexibleLengthAuto::Fixed(width),
+            height: FlexibleLengthAuto::Fixed(height),
+        }
+    }
+
+    fn leaf(options: BoxOptions) -> Rc<dyn Widget> {
+        Rc::new(Container::new(
+            BoxDecoration {
+                color: Color::new(0x7aa2f7),
+                border: BoxBorder::NONE,
+                shadow: None,
+            },
+            options,
+        ))
+    }
+
+    /// Lay out `children` in a flex box with `options`, returns their
+    /// regions as (x, y, width, height).
+    fn flex_regions(
+        options: BoxOptions, children: &[Rc<dyn Widget>],
+    ) -> anyhow::Result<Vec<(f32, f32, f32, f32)>> {
+        let mut tree = WidgetTree::new();
+        tree.new_root(Rc::new(FlexBox {
+            key: WidgetKey::next(),
+            options,
+            children: children.to_vec(),
+        }))?;
+        tree.compute_layout(400.0, 400.0)?;
+        children
+            .iter()
+            .map(|child| {
+                let r = tree.region(child.key())?;
+                Ok((r.x, r.y, r.width, r.height))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flex_grow_layout() -> anyhow::Result<()> {
+        let grow = |grow| {
+            leaf(BoxOptions {
+                grow,
+                ..Default::default()
+            })
+        };
+        let fixed = leaf(BoxOptions {
+            size: sized(40.0, 10.0),
+            ..Default::default()
+        });
+        let options = BoxOptions {
+            size: sized(200.0, 20.0),
+            ..Default::default()
+        };
+        // the 160px left are shared 1:3, boxes without a height stretch
+        let regions = flex_regions(options, &[fixed, grow(1.0), grow(3.0)])?;
+        assert_eq!(
+            regions,
+            [
+                (0.0, 0.0, 40.0, 10.0),
+                (40.0, 0.0, 40.0, 20.0),
+                (80.0, 0.0, 120.0, 20.0),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_flex_justify_layout() -> anyhow::Result<()> {
+        let xs = |justify_content| -> anyhow::Result<Vec<f32>> {
+            let children: Vec<_> = (0..3)
+                .map(|_| {
+                    leaf(BoxOptions {
+                        size: sized(20.0, 20.0),
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            let options = BoxOptions {
+                size: sized(200.0, 20.0),
+                justify_content,
+                ..Default::default()
+            };
+            let regions = flex_regions(options, &children)?;
+            Ok(regions.into_iter().map(|(x, ..)| x).collect())
+        };
+        assert_eq!(xs(Justify::Start)?, [0.0, 20.0, 40.0]);
+        assert_eq!(xs(Justify::End)?, [140.0, 160.0, 180.0]);
+        assert_eq!(xs(Justify::Center)?, [70.0, 90.0, 110.0]);
+        assert_eq!(xs(Justify::SpaceBetween)?, [0.0, 90.0, 180.0]);
+        assert_eq!(xs(Justify::SpaceEvenly)?, [35.0, 90.0, 145.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_flex_wrap_layout() -> anyhow::Result<()> {
+        let children: Vec<_> = (0..3)
+            .map(|_| {
+                leaf(BoxOptions {
+                    size: sized(40.0, 10.0),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let options = |wrap| BoxOptions {
+            size: BoxSize {
+                width: FlexibleLengthAuto::Fixed(100.0),
+                height: FlexibleLengthAuto::Auto,
+            },
+            wrap,
+            ..Default::default()
+        };
+        // the third one moves to the next line
+        let regions = flex_regions(options(Wrap::Wrap), &children)?;
+        assert_eq!(
+            regions,
+            [
+                (0.0, 0.0, 40.0, 10.0),
+                (40.0, 0.0, 40.0, 10.0),
+                (0.0, 10.0, 40.0, 10.0),
+            ]
+        );
+        // or stays in the line
+        let regions = flex_regions(options(Wrap::NoWrap), &children)?;
+        assert!(regions.iter().all(|r| r.1 == 0.0));
+        assert!(regions[2].0 > regions[1].0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_absolute_inset_layout() -> anyhow::Result<()> {
+        let sibling = leaf(BoxOptions {
+            size: sized(50.0, 50.0),
+            ..Default::default()
+        });
+        let corner = leaf(BoxOptions {
+            size: sized(20.0, 10.0),
+            position: Position::Absolute,
+            inset: Inset {
+                right: FlexibleLengthAuto::Fixed(10.0),
+                bottom: FlexibleLengthAuto::Fixed(5.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let centered = leaf(BoxOptions {
+            size: sized(20.0, 10.0),
+            position: Position::Absolute,
+            inset: Inset {
+                left: FlexibleLengthAuto::Percent(0.5),
+                top: FlexibleLengthAuto::Fixed(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let after = leaf(BoxOptions {
+            size: sized(30.0, 30.0),
+            ..Default::default()
+        });
+        let options = BoxOptions {
+            size: sized(200.0, 100.0),
+            ..Default::default()
+        };
+        let regions =
+            flex_regions(options, &[sibling, corner, centered, after])?;
+        // placed from the edges of the parent, siblings ignore them
+        assert_eq!(
+            regions,
+            [
+                (0.0, 0.0, 50.0, 50.0),
+                (170.0, 85.0, 20.0, 10.0),
+                (100.0, 0.0, 20.0, 10.0),
+                (50.0, 0.0, 30.0, 30.0),
+            ]
+        );
+        Ok(())
+    }
+}
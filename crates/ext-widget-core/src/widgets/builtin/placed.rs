@@ -0,0 +1,79 @@
+use std::{fmt::Debug, rc::Rc};
+
+use crate::{
+    painting::{RectSize, RenderCtx, SpacePolicy},
+    widgets::{
+        widget::{BuildContext, LayoutElement, Widget, WidgetKey},
+        BoxOptions,
+    },
+};
+
+/// A child placed by its parent, it's the child itself except the options
+/// `place` sets, such as its grid cell.
+pub(super) struct Placed {
+    child: Rc<dyn Widget>,
+    place: Box<dyn Fn(&mut BoxOptions)>,
+}
+
+impl Placed {
+    pub(super) fn new<F>(child: Rc<dyn Widget>, place: F) -> Self
+    where
+        F: Fn(&mut BoxOptions) + 'static,
+    {
+        Self {
+            child,
+            place: Box::new(place),
+        }
+    }
+}
+
+impl Debug for Placed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Placed")
+            .field("child", &self.child)
+            .field("style", &self.style())
+            .finish()
+    }
+}
+
+impl LayoutElement for Placed {
+    fn style(&self) -> BoxOptions {
+        let mut options = self.child.style();
+        (self.place)(&mut options);
+        options
+    }
+
+    fn compute_layout(
+        &self, known_dimensions: RectSize<Option<f32>>,
+        available_space: RectSize<SpacePolicy>,
+    ) -> RectSize<f32> {
+        self.child.compute_layout(known_dimensions, available_space)
+    }
+}
+
+impl Widget for Placed {
+    /// The key of the child, so it's marked dirty and reconciled as itself.
+    fn key(&self) -> WidgetKey {
+        self.child.key()
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.child.type_name()
+    }
+
+    fn children(&self) -> Vec<Rc<dyn Widget>> {
+        self.child.children()
+    }
+
+    fn need_build(&self) -> bool {
+        self.child.need_build()
+    }
+
+    fn build(&self, context: &BuildContext) -> anyhow::Result<()> {
+        self.child.build(context)
+    }
+
+    fn paint(&self, render: &mut RenderCtx<'_>) -> anyhow::Result<()> {
+        self.child.paint(render)
+    }
+}
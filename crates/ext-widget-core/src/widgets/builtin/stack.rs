@@ -0,0 +1,183 @@
+use std::{fmt::Debug, rc::Rc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    painting::{Align, GridCell, LayoutMode, Location, Position, RenderCtx},
+    widgets::{
+        widget::{LayoutElement, Widget, WidgetKey},
+        BoxOptions,
+    },
+};
+
+use super::placed::Placed;
+
+/// The only cell of a stack, all layers take it.
+const LAYER_CELL: GridCell = GridCell {
+    column: Some(1),
+    row: Some(1),
+    column_span: 1,
+    row_span: 1,
+};
+
+/// Where an overlay is attached to its [`Stack`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StackAnchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+/// How an overlay is placed in its [`Stack`].
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Overlay {
+    #[serde(default)]
+    pub anchor: StackAnchor,
+    /// Moved from the anchor by, in pixels.
+    #[serde(default)]
+    pub offset: Location,
+    /// See [`BoxOptions::z_index`].
+    #[serde(default)]
+    pub z_index: i32,
+}
+
+/**
+ * Layers children over each other.
+ *
+ * Children are laid out as layers in the same area, which is as large as
+ * the largest of them. Overlays don't take room, they are attached to an
+ * anchor of that area. Later children are painted over earlier ones, unless
+ * their z index is lower.
+ */
+#[derive(Clone)]
+pub struct Stack {
+    key: WidgetKey,
+    options: BoxOptions,
+    children: Vec<Rc<dyn Widget>>,
+}
+
+impl StackAnchor {
+    /// Alignment on the horizontal and vertical axis.
+    fn alignment(self) -> (Align, Align) {
+        let horizontal = match self {
+            Self::TopLeft | Self::Left | Self::BottomLeft => Align::Start,
+            Self::Top | Self::Center | Self::Bottom => Align::Center,
+            Self::TopRight | Self::Right | Self::BottomRight => Align::End,
+        };
+        let vertical = match self {
+            Self::TopLeft | Self::Top | Self::TopRight => Align::Start,
+            Self::Left | Self::Center | Self::Right => Align::Center,
+            Self::BottomLeft | Self::Bottom | Self::BottomRight => Align::End,
+        };
+        (horizontal, vertical)
+    }
+}
+
+impl Overlay {
+    pub fn new(anchor: StackAnchor) -> Self {
+        Self {
+            anchor,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_offset(self, x: f32, y: f32) -> Self {
+        Self {
+            offset: Location { x, y },
+            ..self
+        }
+    }
+
+    pub fn with_z_index(self, z_index: i32) -> Self {
+        Self { z_index, ..self }
+    }
+
+    fn place(&self, options: &mut BoxOptions) {
+        let (horizontal, vertical) = self.anchor.alignment();
+        // attached to the area of the layers, rather than the whole stack
+        options.cell = LAYER_CELL;
+        options.position = Position::Absolute;
+        options.inset = Default::default();
+        options.justify_self = Some(horizontal);
+        options.align_self = Some(vertical);
+        // opposite margins move the box, whatever it's aligned to
+        options.margin.left = self.offset.x.into();
+        options.margin.right = (-self.offset.x).into();
+        options.margin.top = self.offset.y.into();
+        options.margin.bottom = (-self.offset.y).into();
+        options.z_index = self.z_index;
+    }
+}
+
+impl Stack {
+    pub fn new(options: BoxOptions) -> Self {
+        Self {
+            key: WidgetKey::next(),
+            options,
+            children: vec![],
+        }
+    }
+
+    pub fn new_with_children(
+        options: BoxOptions, children: Vec<Rc<dyn Widget>>,
+    ) -> Self {
+        let mut stack = Self::new(options);
+        for child in children {
+            stack.add_child(child);
+        }
+        stack
+    }
+
+    /// Add a layer, the stack grows to fit it.
+    pub fn add_child(&mut self, child: Rc<dyn Widget>) {
+        let placed = Placed::new(child, |options| options.cell = LAYER_CELL);
+        self.children.push(Rc::new(placed));
+    }
+
+    /// Add `child` over the layers, placed by `overlay`.
+    pub fn add_overlay(&mut self, child: Rc<dyn Widget>, overlay: Overlay) {
+        let placed = Placed::new(child, move |options| overlay.place(options));
+        self.children.push(Rc::new(placed));
+    }
+}
+
+impl Debug for Stack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stack")
+            .field("children", &self.children.len())
+            .finish()
+    }
+}
+
+impl LayoutElement for Stack {
+    fn style(&self) -> BoxOptions {
+        BoxOptions {
+            display: LayoutMode::Grid,
+            template: Default::default(),
+            ..self.options.clone()
+        }
+    }
+}
+
+impl Widget for Stack {
+    fn key(&self) -> WidgetKey {
+        self.key
+    }
+
+    fn children(&self) -> Vec<Rc<dyn Widget>> {
+        self.children.clone()
+    }
+
+    fn paint(&self, _render: &mut RenderCtx<'_>) -> anyhow::Result<()> {
+        // skip paint
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    cell::{Ref, RefCell},
+    fmt::Debug,
+    rc::Rc,
+};
+
+use crate::{
+    painting::SpacePolicy,
+    widgets::widget::{
+        BuildContext, DirtyKeys, LayoutElement, Widget, WidgetKey,
+    },
+};
+
+/// A widget built from a mutable state, see [`StatefulWidgetPod::set_state`].
+pub trait StatefulWidget: Debug {
+    type State: Debug;
+
+    fn build(
+        &self, state: &Self::State, context: &BuildContext,
+    ) -> Rc<dyn Widget>;
+}
+
+pub struct StatefulWidgetPod<T: StatefulWidget> {
+    key: WidgetKey,
+    widget: T,
+    child: RefCell<Option<Rc<dyn Widget>>>,
+    state: RefCell<T::State>,
+    /// Set once built in a tree, where `set_state` reports changes.
+    dirty: RefCell<Option<DirtyKeys>>,
+}
+
+impl<T: StatefulWidget> Debug for StatefulWidgetPod<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatefulWidget")
+            .field("widget", &self.widget)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl<T: StatefulWidget> StatefulWidgetPod<T> {
+    pub fn new(widget: T, state: T::State) -> Self {
+        Self {
+            key: WidgetKey::next(),
+            widget,
+            child: RefCell::new(None),
+            state: RefCell::new(state),
+            dirty: RefCell::new(None),
+        }
+    }
+
+    pub fn widget(&self) -> &T {
+        &self.widget
+    }
+
+    pub fn state(&self) -> Ref<'_, T::State> {
+        self.state.borrow()
+    }
+
+    /// Update the state with `f`, and mark this widget dirty. The tree
+    /// builds it again on the next [`crate::widgets::WidgetTree::rebuild`].
+    pub fn set_state<F>(&self, f: F)
+    where
+        F: FnOnce(&mut T::State),
+    {
+        f(&mut self.state.borrow_mut());
+        if let Some(dirty) = self.dirty.borrow().as_ref() {
+            dirty.mark(self.key);
+        }
+    }
+
+    pub fn build_inner(&self, context: &BuildContext) -> Rc<dyn Widget> {
+        // a child built before it's in a tree may miss `set_state`
+        let detached = self
+            .dirty
+            .replace(Some(context.dirty_keys().clone()))
+            .is_none();
+        let mut child = self.child.borrow_mut();
+        if child.is_none() || detached || context.is_dirty(self.key) {
+            let c = self.widget.build(&self.state.borrow(), context);
+            child.replace(c.clone());
+            c
+        } else {
+            child.as_ref().unwrap().clone()
+        }
+    }
+
+    /// The last built child, built now if this widget is used before a tree
+    /// builds it. The tree builds it again when it's mounted.
+    fn child(&self) -> Rc<dyn Widget> {
+        if let Some(child) = self.child.borrow().as_ref() {
+            return child.clone();
+        }
+        let child = self
+            .widget
+            .build(&self.state.borrow(), &BuildContext::default());
+        self.child.replace(Some(child.clone()));
+        child
+    }
+}
+
+impl<T: StatefulWidget> LayoutElement for StatefulWidgetPod<T> {
+    fn style(&self) -> crate::widgets::BoxOptions {
+        self.child().style()
+    }
+
+    fn compute_layout(
+        &self, known_dimensions: crate::painting::RectSize<Option<f32>>,
+        available_space: crate::painting::RectSize<SpacePolicy>,
+    ) -> crate::painting::RectSize<f32> {
+        self.child()
+            .compute_layout(known_dimensions, available_space)
+    }
+}
+
+impl<T: StatefulWidget> Widget for StatefulWidgetPod<T> {
+    fn key(&self) -> WidgetKey {
+        self.key
+    }
+
+    fn children(&self) -> Vec<Rc<dyn Widget>> {
+        self.child().children()
+    }
+
+    fn paint(
+        &self, render: &mut crate::painting::RenderCtx<'_>,
+    ) -> anyhow::Result<()> {
+        self.child().paint(render)
+    }
+
+    fn type_name(&self) -> &'static str {
+        self.child().type_name()
+    }
+
+    fn need_build(&self) -> bool {
+        true
+    }
+
+    fn build(&self, context: &BuildContext) -> anyhow::Result<()> {
+        self.build_inner(context);
+        Ok(())
+    }
+}
@@ -6,4 +6,4 @@ mod widget;
 pub use builtin::*;
 pub use others::*;
 pub use widget::BoxOptions;
-pub use widget::{Widget, WidgetTree};
+pub use widget::{BuildContext, Widget, WidgetTree};
@@ -1,12 +1,10 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use anyhow::{bail, Context};
 use comrak::nodes::{
     AstNode, NodeCode, NodeCodeBlock, NodeHeading, NodeLink, NodeList,
     NodeValue,
 };
-use futures::AsyncWrite;
-use nvim_rs::Neovim;
 use skia_safe::{
     font_style::{Slant as SkSlant, Weight as SkWeight},
     textlayout::{
@@ -19,7 +17,7 @@ use tracing::{instrument, trace};
 use tree_sitter::{Parser, Query};
 
 use crate::{
-    nvim::{HighlightInfos, NeovimSession},
+    nvim::HighlightInfos,
     painting::{
         BoxBorder, BoxConstraints, BoxDecoration, FlexibleLengthAuto, Margin,
     },
@@ -35,14 +33,9 @@ pub struct ConverterOptions {
     pub normal_font_size: f32,
 }
 
-pub(crate) struct Converter<'o, 'f, W>
-where
-    W: AsyncWrite + Send + Unpin + 'static,
-{
+pub(crate) struct Converter<'o, 'f> {
     pub(super) opts: &'o ConverterOptions,
     pub(super) font_collection: &'f FontCollection,
-    pub(super) nvim: Neovim<W>,
-    pub(super) session: Arc<NeovimSession>,
 
     pub(super) parsers: HashMap<String, RefCell<Parser>>,
     pub(super) highlight_queries: HashMap<String, Query>,
@@ -115,10 +108,7 @@ impl<'a> BlockContext<'a> {
     }
 }
 
-impl<'o, 'f, W> Converter<'o, 'f, W>
-where
-    W: AsyncWrite + Send + Unpin + 'static,
-{
+impl<'o, 'f> Converter<'o, 'f> {
     fn update_text_tyle(&self, group: &str, style: &mut TextStyle) {
         if let Some(hl) = self.highlight_infos.get(group) {
             hl.update_text_tyle(style)
@@ -136,10 +126,7 @@ where
 }
 
 /// **Inline**
-impl<'o, 'f, W> Converter<'o, 'f, W>
-where
-    W: AsyncWrite + Send + Unpin + 'static,
-{
+impl<'o, 'f> Converter<'o, 'f> {
     #[instrument(level = "trace", skip_all)]
     fn visit_inline_node<'a, 'b>(
         &mut self, node: &'a AstNode<'a>, block: &mut BlockContext<'f>,
@@ -272,10 +259,7 @@ enum VirtialBlockWrapper {
 static HEADING_FONT_SIZES: [f32; 3] = [2.0, 1.5, 1.2];
 
 /// **Block**
-impl<'c, 'f, W> Converter<'c, 'f, W>
-where
-    W: AsyncWrite + Send + Unpin + 'static,
-{
+impl<'c, 'f> Converter<'c, 'f> {
     #[instrument(level = "trace", skip_all)]
     pub(super) fn visit_block_node<'a>(
         &mut self, node: &'a AstNode<'a>,
@@ -310,6 +294,7 @@ where
                     BoxDecoration {
                         color: hl.and_then(|x| x.fg).unwrap_or_default(),
                         border: BoxBorder::NONE,
+                        shadow: None,
                     },
                     BoxOptions {
                         constraints: BoxConstraints {
@@ -538,12 +523,9 @@ where
     }
 }
 
-fn get_all_captures<W>(
-    code: &str, lang: &str, converter: &Converter<'_, '_, W>,
-) -> anyhow::Result<Vec<HighlightMarker>>
-where
-    W: AsyncWrite + Send + Unpin + 'static,
-{
+fn get_all_captures(
+    code: &str, lang: &str, converter: &Converter<'_, '_>,
+) -> anyhow::Result<Vec<HighlightMarker>> {
     let mut parser = converter
         .parsers
         .get(lang)
@@ -1,20 +1,18 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use comrak::nodes::AstNode;
-use futures::AsyncWrite;
-use nvim_rs::Neovim;
 use skia_safe::{textlayout::FontCollection, FontMgr};
+use tree_sitter::{Parser, Query};
 
-use crate::{nvim::NeovimSession, widgets::Widget};
+use crate::{nvim::HighlightInfos, widgets::Widget};
 
-use super::{converter::Converter, ConverterOptions};
+use super::{converter::Converter, ConverterOptions, DocumentProvider};
 
-pub struct MarkdownDocumentBuilder<W>
+pub struct MarkdownDocumentBuilder<P>
 where
-    W: AsyncWrite + Send + Unpin + 'static,
+    P: DocumentProvider,
 {
-    pub nvim: Neovim<W>,
-    pub session: Arc<NeovimSession>,
+    pub provider: P,
     pub normal_font: Vec<String>,
     pub normal_font_size: f32,
     pub mono_font: Vec<String>,
@@ -23,81 +21,94 @@ where
 
 const DEFAULT_HIGHLIGHS: &[&str] = &["Normal", "NormalNC"];
 
-impl<W> MarkdownDocumentBuilder<W>
+/// Parsers, queries and highlights needed by the code blocks of a document.
+#[derive(Default)]
+struct DocumentResources {
+    parsers: HashMap<String, RefCell<Parser>>,
+    queries: HashMap<String, Query>,
+    highlights: HashMap<String, HighlightInfos>,
+}
+
+impl<P> MarkdownDocumentBuilder<P>
 where
-    W: AsyncWrite + Send + Unpin + 'static,
+    P: DocumentProvider,
 {
     pub async fn build(self, text: &str) -> anyhow::Result<Rc<dyn Widget>> {
-        let langs = {
-            let arena = comrak::Arena::new();
-            let root = comrak::parse_document(
-                &arena,
-                text,
-                &comrak::ComrakOptions::default(),
-            );
-            get_codeblock_infos(root)
-        };
-        let mut parsers = HashMap::new();
-        let mut quries = HashMap::new();
-        let mut highlights = HashMap::new();
-        for name in langs {
-            let info = self.session.load_ts_parser(&self.nvim, &name).await;
-            if let Ok(Some(info)) = info {
-                parsers.insert(name.clone(), RefCell::new(info));
+        let res = self.collect_resources(text).await;
+        // not `Send`, so only create it after all awaits
+        let mut font_collection = FontCollection::new();
+        font_collection.set_default_font_manager(FontMgr::new(), None);
+        build_document(&self.options(), &font_collection, res, text)
+    }
+
+    /// Build with the given fonts instead of the system fonts.
+    pub async fn build_with_fonts(
+        self, text: &str, font_collection: &FontCollection,
+    ) -> anyhow::Result<Rc<dyn Widget>> {
+        let res = self.collect_resources(text).await;
+        build_document(&self.options(), font_collection, res, text)
+    }
+
+    async fn collect_resources(&self, text: &str) -> DocumentResources {
+        let mut res = DocumentResources::default();
+        for name in codeblock_langs(text) {
+            if let Some(parser) = self.provider.parser(&name).await {
+                res.parsers.insert(name.clone(), RefCell::new(parser));
             }
-            let query = self
-                .session
-                .load_ts_query(&self.nvim, &name, "highlights")
-                .await;
-            if let Ok(query) = query {
-                quries.insert(name.clone(), query);
+            if let Some(query) = self.provider.highlight_query(&name).await {
+                res.queries.insert(name.clone(), query);
             }
         }
-        for (_, query) in &quries {
-            let groups = query.capture_names();
-            for group in groups {
-                let hl =
-                    self.session.get_highlight_info(&self.nvim, &group).await;
-                if let Ok(hl) = hl {
-                    highlights.insert(group.to_string(), hl);
+        for (_, query) in &res.queries {
+            for group in query.capture_names() {
+                if let Some(hl) = self.provider.highlight(group).await {
+                    res.highlights.insert(group.to_string(), hl);
                 }
             }
         }
         for name in DEFAULT_HIGHLIGHS {
-            let hl = self.session.get_highlight_info(&self.nvim, &name).await;
-            if let Ok(hl) = hl {
-                highlights.insert(name.to_string(), hl);
+            if let Some(hl) = self.provider.highlight(name).await {
+                res.highlights.insert(name.to_string(), hl);
             }
         }
+        res
+    }
 
-        let mut font_collection = FontCollection::new();
-        font_collection.set_default_font_manager(FontMgr::new(), None);
-        let opts = ConverterOptions {
-            normal_font: self.normal_font,
+    fn options(&self) -> ConverterOptions {
+        ConverterOptions {
+            normal_font: self.normal_font.clone(),
             normal_font_size: self.normal_font_size,
-            mono_font: self.mono_font,
+            mono_font: self.mono_font.clone(),
             mono_font_size: self.mono_font_size,
-        };
-        let mut converter = Converter {
-            nvim: self.nvim.clone(),
-            session: self.session.clone(),
-            opts: &opts,
-            font_collection: &font_collection,
-            parsers,
-            highlight_queries: quries,
-            highlight_infos: highlights,
-        };
-
-        let arena = comrak::Arena::new();
-        let root = comrak::parse_document(
-            &arena,
-            text,
-            &comrak::ComrakOptions::default(),
-        );
-        converter.visit_block_node(root, None)
+        }
     }
 }
 
+fn build_document(
+    opts: &ConverterOptions, font_collection: &FontCollection,
+    res: DocumentResources, text: &str,
+) -> anyhow::Result<Rc<dyn Widget>> {
+    let mut converter = Converter {
+        opts,
+        font_collection,
+        parsers: res.parsers,
+        highlight_queries: res.queries,
+        highlight_infos: res.highlights,
+    };
+
+    let arena = comrak::Arena::new();
+    let root =
+        comrak::parse_document(&arena, text, &comrak::ComrakOptions::default());
+    converter.visit_block_node(root, None)
+}
+
+fn codeblock_langs(text: &str) -> Vec<String> {
+    let arena = comrak::Arena::new();
+    let root =
+        comrak::parse_document(&arena, text, &comrak::ComrakOptions::default());
+    get_codeblock_infos(root)
+}
+
 fn visit_node_children<'a>(node: &'a AstNode<'a>, infos: &mut Vec<String>) {
     let children = node.children();
     for child in children {
@@ -120,3 +131,210 @@ fn get_codeblock_infos<'a>(root: &'a AstNode<'a>) -> Vec<String> {
     visit_node_children(root, &mut infos);
     infos
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        rc::Rc,
+        sync::{Arc, Mutex},
+    };
+
+    use async_trait::async_trait;
+    use tree_sitter::{Parser, Query};
+
+    use crate::{
+        nvim::{ChromeTheme, HighlightInfos, HighlightTheme, NeovimSession},
+        render::Chrome,
+        test_utils::{
+            assert_snapshot, render_widget_png, test_font_collection,
+            EmbedNvim, TEST_FONT, TEST_MONO_FONT,
+        },
+        widgets::{DocumentProvider, MarkdownDocumentBuilder, StaticProvider},
+    };
+
+    const THEME: &str = r##"{
+        "Normal": { "fg": "#c0caf5", "bg": "#1a1b26" },
+        "@keyword": { "fg": "#bb9af7", "italic": true },
+        "@string": { "fg": "#9ece6a" },
+        "@number": { "fg": "#ff9e64" },
+        "@comment": { "fg": "#565f89" },
+        "@function": { "fg": "#7aa2f7" }
+    }"##;
+
+    fn builder(
+        runtime_paths: Vec<PathBuf>,
+    ) -> anyhow::Result<MarkdownDocumentBuilder<StaticProvider>> {
+        let theme = HighlightTheme::from_json(THEME)?;
+        Ok(MarkdownDocumentBuilder {
+            provider: StaticProvider::new(theme, runtime_paths),
+            normal_font: vec![TEST_FONT.to_string()],
+            normal_font_size: 16.0,
+            mono_font: vec![TEST_MONO_FONT.to_string()],
+            mono_font_size: 14.0,
+        })
+    }
+
+    async fn check_markdown_snapshot(
+        name: &str, text: &str,
+    ) -> anyhow::Result<()> {
+        check_markdown_snapshot_in(name, text, vec![]).await
+    }
+
+    /// Like `check_markdown_snapshot`, with parsers from `runtime_paths`.
+    async fn check_markdown_snapshot_in(
+        name: &str, text: &str, runtime_paths: Vec<PathBuf>,
+    ) -> anyhow::Result<()> {
+        let fonts = test_font_collection();
+        let document = builder(runtime_paths)?
+            .build_with_fonts(text, &fonts)
+            .await?;
+        let provider =
+            StaticProvider::new(HighlightTheme::from_json(THEME)?, vec![]);
+        let chrome = Chrome::resolve(&provider, &ChromeTheme::hover()).await;
+        let container = chrome.container(document);
+        let png = render_widget_png(Rc::new(container), 400.0, 1000.0)?;
+        assert_snapshot(name, &png);
+        Ok(())
+    }
+
+    /// Records what the builder asks for and resolves nothing.
+    #[derive(Default)]
+    struct RecordingProvider {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RecordingProvider {
+        fn record(&self, request: String) {
+            self.requests.lock().unwrap().push(request);
+        }
+    }
+
+    #[async_trait]
+    impl DocumentProvider for RecordingProvider {
+        async fn highlight(&self, group: &str) -> Option<HighlightInfos> {
+            self.record(format!("highlight {}", group));
+            None
+        }
+
+        async fn parser(&self, lang: &str) -> Option<Parser> {
+            self.record(format!("parser {}", lang));
+            None
+        }
+
+        async fn highlight_query(&self, lang: &str) -> Option<Query> {
+            self.record(format!("query {}", lang));
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_code_block_resources() -> anyhow::Result<()> {
+        let text = "# Title\n\n```rust\nfn main() {}\n```\n\n- item\n\n  \
+                    ```lua\n  print(1)\n  ```";
+        let provider = RecordingProvider::default();
+        let requests = provider.requests.clone();
+        MarkdownDocumentBuilder {
+            provider,
+            normal_font: vec![TEST_FONT.to_string()],
+            normal_font_size: 16.0,
+            mono_font: vec![TEST_MONO_FONT.to_string()],
+            mono_font_size: 14.0,
+        }
+        .build_with_fonts(text, &test_font_collection())
+        .await?;
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [
+                "parser rust",
+                "query rust",
+                "parser lua",
+                "query lua",
+                "highlight Normal",
+                "highlight NormalNC",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_headings_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_headings",
+            "# Heading 1\n## Heading 2\n### Heading 3\n#### Heading 4",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_inline_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_inline",
+            "Plain, *emphasis*, **strong**, ***both***, `inline code` and \
+             a [link](https://example.com).\nA soft break, and a long line \
+             that has to wrap at the width of the document.",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_paragraphs_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_paragraphs",
+            "First paragraph.\n\nSecond paragraph.\n\n---\n\nAfter a \
+             thematic break.",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_bullet_list_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_bullet_list",
+            "- one\n- two with *emphasis*\n  - nested\n  - nested `code`\n\
+             - three",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_ordered_list_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_ordered_list",
+            "1. first\n2. second\n3. third\n\n4) paren\n5) delimiter",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_code_block_snapshot() -> anyhow::Result<()> {
+        check_markdown_snapshot(
+            "markdown_code_block",
+            "Before.\n\n```\nfn main() {\n    println!(\"hi\");\n}\n```\n\n\
+             After.",
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_highlighted_code_block_snapshot() -> anyhow::Result<()> {
+        // the lua parser and queries bundled with Neovim, colors from `THEME`
+        let nvim = EmbedNvim::new().await?;
+        let runtime_paths = NeovimSession::runtime_paths(&nvim.neovim).await?;
+        let provider = StaticProvider::new(
+            HighlightTheme::from_json(THEME)?,
+            runtime_paths.clone(),
+        );
+        assert!(provider.parser("lua").await.is_some());
+        assert!(provider.highlight_query("lua").await.is_some());
+
+        check_markdown_snapshot_in(
+            "markdown_code_block_lua",
+            "```lua\n-- greet\nlocal function greet(name)\n  \
+             return \"hello \" .. name .. 1\nend\n```",
+            runtime_paths,
+        )
+        .await
+    }
+}
@@ -1,6 +1,8 @@
 mod codeblock;
 mod converter;
 mod markdown_document;
+mod provider;
 
 pub use converter::ConverterOptions;
 pub use markdown_document::MarkdownDocumentBuilder;
+pub use provider::{DocumentProvider, NeovimProvider, StaticProvider};
@@ -0,0 +1,165 @@
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use futures::AsyncWrite;
+use nvim_rs::Neovim;
+use tree_sitter::{Parser, Query};
+
+use crate::{
+    nvim::{HighlightInfos, HighlightTheme, NeovimSession},
+    treesitter::TreeSitterLoader,
+};
+
+/// Where a markdown document gets its highlights and tree-sitter parsers.
+#[async_trait]
+pub trait DocumentProvider: Send + Sync {
+    /// Highlight of a group, e.g. `Normal` or a capture name of a highlight
+    /// query.
+    async fn highlight(&self, group: &str) -> Option<HighlightInfos>;
+
+    async fn parser(&self, lang: &str) -> Option<Parser>;
+
+    async fn highlight_query(&self, lang: &str) -> Option<Query>;
+}
+
+#[async_trait]
+impl<P> DocumentProvider for &P
+where
+    P: DocumentProvider + ?Sized,
+{
+    async fn highlight(&self, group: &str) -> Option<HighlightInfos> {
+        (**self).highlight(group).await
+    }
+
+    async fn parser(&self, lang: &str) -> Option<Parser> {
+        (**self).parser(lang).await
+    }
+
+    async fn highlight_query(&self, lang: &str) -> Option<Query> {
+        (**self).highlight_query(lang).await
+    }
+}
+
+/// Asks a connected Neovim, parsers are found in its runtime paths.
+pub struct NeovimProvider<W>
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    pub nvim: Neovim<W>,
+    pub session: Arc<NeovimSession>,
+}
+
+#[async_trait]
+impl<W> DocumentProvider for NeovimProvider<W>
+where
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    async fn highlight(&self, group: &str) -> Option<HighlightInfos> {
+        self.session.get_highlight_info(&self.nvim, group).await.ok()
+    }
+
+    async fn parser(&self, lang: &str) -> Option<Parser> {
+        self.session
+            .load_ts_parser(&self.nvim, lang)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    async fn highlight_query(&self, lang: &str) -> Option<Query> {
+        self.session
+            .load_ts_query(&self.nvim, lang, "highlights")
+            .await
+            .ok()
+    }
+}
+
+/// Highlights from an in-memory theme, parsers from the given runtime paths.
+///
+/// Clones share the cache of parsers.
+#[derive(Debug, Clone, Default)]
+pub struct StaticProvider {
+    pub theme: HighlightTheme,
+    pub runtime_paths: Vec<PathBuf>,
+    ts: Arc<TreeSitterLoader>,
+}
+
+impl StaticProvider {
+    pub fn new(theme: HighlightTheme, runtime_paths: Vec<PathBuf>) -> Self {
+        Self {
+            theme,
+            runtime_paths,
+            ts: Arc::new(TreeSitterLoader::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl DocumentProvider for StaticProvider {
+    async fn highlight(&self, group: &str) -> Option<HighlightInfos> {
+        // captures are defined as `@capture` in Neovim themes
+        let hl = if group.starts_with('@') {
+            None
+        } else {
+            self.theme.get(&format!("@{}", group))
+        };
+        hl.or_else(|| self.theme.get(group)).cloned()
+    }
+
+    async fn parser(&self, lang: &str) -> Option<Parser> {
+        self.ts.load_parser(&self.runtime_paths, lang).ok().flatten()
+    }
+
+    async fn highlight_query(&self, lang: &str) -> Option<Query> {
+        self.ts
+            .load_query(&self.runtime_paths, lang, "highlights")
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nvim::HighlightTheme;
+
+    use super::{DocumentProvider, StaticProvider};
+
+    const THEME: &str = r##"{
+        "Normal": { "fg": "#c0caf5", "bg": "#1a1b26" },
+        "keyword": { "fg": "#ff0000" },
+        "@keyword": { "fg": "#bb9af7" },
+        "string": { "fg": "#9ece6a" }
+    }"##;
+
+    #[tokio::test]
+    async fn test_static_highlights() -> anyhow::Result<()> {
+        let theme = HighlightTheme::from_json(THEME)?;
+        let provider = StaticProvider::new(theme.clone(), vec![]);
+
+        let normal = provider.highlight("Normal").await;
+        assert_eq!(normal.as_ref(), theme.get("Normal"));
+        // capture names prefer their `@` group
+        let keyword = provider.highlight("keyword").await;
+        assert_eq!(keyword.as_ref(), theme.get("@keyword"));
+        let function = provider.highlight("keyword.function").await;
+        assert_eq!(function.as_ref(), theme.get("@keyword"));
+        // and fall back to the plain group without one
+        let string = provider.highlight("string").await;
+        assert_eq!(string.as_ref(), theme.get("string"));
+        // `@` groups are not prefixed twice
+        let keyword = provider.highlight("@keyword").await;
+        assert_eq!(keyword.as_ref(), theme.get("@keyword"));
+        assert!(provider.highlight("comment").await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_static_missing_parser() -> anyhow::Result<()> {
+        let provider = StaticProvider::new(
+            HighlightTheme::from_json(THEME)?,
+            vec![std::env::temp_dir().join("ext-widget-no-runtime")],
+        );
+        assert!(provider.parser("rust").await.is_none());
+        assert!(provider.highlight_query("rust").await.is_none());
+        Ok(())
+    }
+}
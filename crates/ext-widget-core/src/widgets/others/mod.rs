@@ -1,3 +1,6 @@
 mod markdown;
 
-pub use markdown::{ConverterOptions, MarkdownDocumentBuilder};
+pub use markdown::{
+    ConverterOptions, DocumentProvider, MarkdownDocumentBuilder,
+    NeovimProvider, StaticProvider,
+};
@@ -1,9 +1,74 @@
+use std::{cell::RefCell, collections::HashSet, fmt::Debug, rc::Rc};
+
 use super::WidgetKey;
 
-pub struct BuildContext {}
+/// Keys of widgets whose state changed since the last rebuild, shared by a
+/// [`super::WidgetTree`] and the widgets built in it.
+#[derive(Clone, Default)]
+pub struct DirtyKeys(Rc<DirtyInner>);
+
+#[derive(Default)]
+struct DirtyInner {
+    keys: RefCell<HashSet<WidgetKey>>,
+    /// Called when the first key is marked, to schedule a rebuild.
+    waker: RefCell<Option<Box<dyn Fn()>>>,
+}
+
+impl Debug for DirtyKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("DirtyKeys").field(&self.0.keys).finish()
+    }
+}
+
+impl DirtyKeys {
+    pub fn mark(&self, key: WidgetKey) {
+        let first = {
+            let mut keys = self.0.keys.borrow_mut();
+            keys.insert(key) && keys.len() == 1
+        };
+        if first {
+            if let Some(waker) = self.0.waker.borrow().as_ref() {
+                waker();
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.keys.borrow().is_empty()
+    }
+
+    /// Take all marked keys, and clear them.
+    pub fn take(&self) -> HashSet<WidgetKey> {
+        std::mem::take(&mut self.0.keys.borrow_mut())
+    }
+
+    /// Call `waker` when a key is marked and none was before, once per
+    /// rebuild.
+    pub fn set_waker(&self, waker: impl Fn() + 'static) {
+        self.0.waker.replace(Some(Box::new(waker)));
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildContext {
+    /// Widgets being rebuilt.
+    rebuild: HashSet<WidgetKey>,
+    dirty: DirtyKeys,
+}
 
 impl BuildContext {
-    pub fn is_dirty(&self, _key: WidgetKey) -> bool {
-        false
+    pub fn new(rebuild: HashSet<WidgetKey>, dirty: DirtyKeys) -> Self {
+        Self { rebuild, dirty }
+    }
+
+    /// Returns whether the widget `key` should build again, instead of
+    /// reusing its last result.
+    pub fn is_dirty(&self, key: WidgetKey) -> bool {
+        self.rebuild.contains(&key)
+    }
+
+    /// Where widgets built in this context mark themselves dirty.
+    pub fn dirty_keys(&self) -> &DirtyKeys {
+        &self.dirty
     }
 }
@@ -5,7 +5,7 @@ mod traits_ext;
 mod tree;
 
 // re-export
-pub use build_context::BuildContext;
+pub use build_context::{BuildContext, DirtyKeys};
 pub use options::BoxOptions;
 pub use traits::{LayoutElement, Widget, WidgetKey};
 pub use tree::WidgetTree;
@@ -3,13 +3,20 @@ use std::fmt::Debug;
 use taffy::Rect;
 
 use crate::painting::{
-    Axis, BoxConstraints, FlexibleLength, Margin, Padding, RectSize,
+    Align, Axis, BoxConstraints, BoxSize, FlexibleLength, FlexibleLengthAuto,
+    GridCell, GridTemplate, Inset, Justify, LayoutMode, Margin, Padding,
+    Position, RectSize, Wrap,
 };
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoxOptions {
+    #[serde(default)]
+    pub display: LayoutMode,
     #[serde(default)]
     pub constraints: BoxConstraints,
+    /// Fixed size, within the constraints.
+    #[serde(default)]
+    pub size: BoxSize,
     #[serde(default)]
     pub padding: Padding,
     #[serde(default)]
@@ -20,11 +27,87 @@ pub struct BoxOptions {
     pub gap: RectSize<FlexibleLength>,
     #[serde(default)]
     pub border: f32,
+    /// How much of the free space of the parent this box takes, relative to
+    /// its siblings.
+    #[serde(default)]
+    pub grow: f32,
+    /// How much this box gives up when its parent overflows, relative to its
+    /// siblings.
+    #[serde(default = "default_shrink")]
+    pub shrink: f32,
+    /// The size on the main axis of the parent, before growing or shrinking.
+    #[serde(default)]
+    pub basis: FlexibleLengthAuto,
+    #[serde(default)]
+    pub align_items: Align,
+    /// Alignment of this box on the cross axis, instead of `align_items` of
+    /// its parent.
+    #[serde(default)]
+    pub align_self: Option<Align>,
+    /// Alignment of this box in its grid cell on the horizontal axis.
+    #[serde(default)]
+    pub justify_self: Option<Align>,
+    #[serde(default)]
+    pub justify_content: Justify,
+    #[serde(default)]
+    pub wrap: Wrap,
+    #[serde(default)]
+    pub position: Position,
+    #[serde(default)]
+    pub inset: Inset,
+    /// Boxes with higher z index are painted over their siblings, siblings
+    /// with the same one are painted in order.
+    #[serde(default)]
+    pub z_index: i32,
+    /// Tracks of the grid, if `display` is grid.
+    #[serde(default)]
+    pub template: GridTemplate,
+    /// Where this box is placed in its grid parent.
+    #[serde(default)]
+    pub cell: GridCell,
+}
+
+fn default_shrink() -> f32 {
+    1.0
+}
+
+impl Default for BoxOptions {
+    fn default() -> Self {
+        Self {
+            display: Default::default(),
+            constraints: Default::default(),
+            size: Default::default(),
+            padding: Default::default(),
+            margin: Default::default(),
+            axis: Default::default(),
+            gap: Default::default(),
+            border: 0.0,
+            grow: 0.0,
+            shrink: default_shrink(),
+            basis: Default::default(),
+            align_items: Default::default(),
+            align_self: None,
+            justify_self: None,
+            justify_content: Default::default(),
+            wrap: Default::default(),
+            position: Default::default(),
+            inset: Default::default(),
+            z_index: 0,
+            template: Default::default(),
+            cell: Default::default(),
+        }
+    }
 }
 
 impl From<BoxOptions> for taffy::Style {
     fn from(value: BoxOptions) -> Self {
+        let column = value.cell.column_line();
+        let row = value.cell.row_line();
         Self {
+            display: value.display.into(),
+            position: value.position.into(),
+            inset: value.inset.into(),
+            size: value.size.into(),
             margin: value.margin.into(),
             padding: value.padding.into(),
             min_size: taffy::Size {
@@ -36,7 +119,29 @@ impl From<BoxOptions> for taffy::Style {
                 height: value.constraints.max_height.into(),
             },
             flex_direction: value.axis.into(),
+            flex_wrap: value.wrap.into(),
+            flex_grow: value.grow,
+            flex_shrink: value.shrink,
+            flex_basis: value.basis.into(),
+            align_items: Some(value.align_items.into()),
+            align_self: value.align_self.map(Into::into),
+            justify_self: value.justify_self.map(Into::into),
+            justify_content: Some(value.justify_content.into()),
             gap: value.gap.into(),
+            grid_template_columns: value
+                .template
+                .columns
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            grid_template_rows: value
+                .template
+                .rows
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            grid_column: column,
+            grid_row: row,
             border: Rect {
                 left: taffy::LengthPercentage::Length(value.border),
                 right: taffy::LengthPercentage::Length(value.border),
@@ -47,3 +152,58 @@ impl From<BoxOptions> for taffy::Style {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::painting::{Align, FlexibleLengthAuto, Position};
+
+    use super::BoxOptions;
+
+    #[test]
+    fn test_flex_options() -> anyhow::Result<()> {
+        let options: BoxOptions = serde_json::from_str(
+            r#"{
+                "grow": 1,
+                "basis": "50%",
+                "align_items": "center",
+                "justify_content": "space_between",
+                "wrap": "wrap",
+                "size": { "width": 20, "height": "auto" },
+                "position": "absolute",
+                "inset": { "right": 0, "top": "10%" }
+            }"#,
+        )?;
+        assert_eq!(options.shrink, 1.0);
+        assert_eq!(options.align_items, Align::Center);
+        assert_eq!(options.position, Position::Absolute);
+        assert_eq!(options.size.width, FlexibleLengthAuto::Fixed(20.0));
+
+        let style: taffy::Style = options.into();
+        assert_eq!(style.flex_grow, 1.0);
+        assert_eq!(style.flex_shrink, 1.0);
+        assert_eq!(style.flex_basis, taffy::Dimension::Percent(0.5));
+        assert_eq!(style.align_items, Some(taffy::AlignItems::Center));
+        assert_eq!(
+            style.justify_content,
+            Some(taffy::JustifyContent::SpaceBetween)
+        );
+        assert_eq!(style.flex_wrap, taffy::FlexWrap::Wrap);
+        assert_eq!(style.size.height, taffy::Dimension::Auto);
+        assert_eq!(style.position, taffy::Position::Absolute);
+        assert_eq!(style.inset.right, taffy::LengthPercentageAuto::Length(0.0));
+        assert_eq!(style.inset.left, taffy::LengthPercentageAuto::Auto);
+
+        // nothing set is the default of taffy
+        let style: taffy::Style = BoxOptions::default().into();
+        assert_eq!(style.flex_shrink, taffy::Style::DEFAULT.flex_shrink);
+        assert_eq!(style.flex_basis, taffy::Style::DEFAULT.flex_basis);
+
+        // an axis not set is auto
+        let options: BoxOptions =
+            serde_json::from_str(r#"{ "size": { "width": 20 } }"#)?;
+        let style: taffy::Style = options.into();
+        assert_eq!(style.size.width, taffy::Dimension::Length(20.0));
+        assert_eq!(style.size.height, taffy::Dimension::Auto);
+        Ok(())
+    }
+}
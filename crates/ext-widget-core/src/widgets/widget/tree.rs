@@ -1,12 +1,18 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use anyhow::{bail, Context};
 use taffy::{AvailableSpace, NodeId, TaffyTree, TraversePartialTree};
 use tracing::{info, instrument, trace};
 
-use crate::painting::{Location, RectSize, RenderCtx, Renderer};
+use crate::painting::{
+    BoxConstraints, Location, RectSize, Region, RenderCtx, Renderer,
+};
 
-use super::{Widget, WidgetExt, WidgetKey};
+use super::{BuildContext, DirtyKeys, Widget, WidgetExt, WidgetKey};
 
 /// Widget tree
 pub struct WidgetTree {
@@ -14,6 +20,17 @@ pub struct WidgetTree {
     inner: TaffyTree<Rc<dyn Widget>>,
     last_size: Option<(f32, f32)>,
     relation: HashMap<WidgetKey, NodeId>,
+    /// Marked by stateful widgets built in this tree.
+    dirty: DirtyKeys,
+    /// Nodes built or mounted since the last paint.
+    changed: HashSet<NodeId>,
+    /// Where each node was last painted, relative to the root.
+    painted: HashMap<NodeId, Region>,
+    /// Where removed nodes were painted, since the last paint.
+    removed: Vec<Region>,
+    /// The z index of nodes, only the non-zero ones, so siblings are sorted
+    /// without asking their widgets for their style on each paint.
+    z_indices: HashMap<NodeId, i32>,
 }
 
 impl WidgetTree {
@@ -23,6 +40,11 @@ impl WidgetTree {
             inner: TaffyTree::new(),
             last_size: None,
             relation: HashMap::new(),
+            dirty: DirtyKeys::default(),
+            changed: HashSet::new(),
+            painted: HashMap::new(),
+            removed: vec![],
+            z_indices: HashMap::new(),
         }
     }
 
@@ -70,13 +92,119 @@ impl WidgetTree {
         Ok(())
     }
 
+    /// Replace the size constraints of the root, the layout is computed
+    /// again on the next [`WidgetTree::compute_layout`].
+    pub fn set_root_constraints(
+        &mut self, constraints: BoxConstraints,
+    ) -> anyhow::Result<()> {
+        let root = self.root.context("root is not set")?;
+        let mut style = self.inner.style(root)?.clone();
+        style.min_size = taffy::Size {
+            width: constraints.min_width.into(),
+            height: constraints.min_height.into(),
+        };
+        style.max_size = taffy::Size {
+            width: constraints.max_width.into(),
+            height: constraints.max_height.into(),
+        };
+        self.inner.set_style(root, style)?;
+        Ok(())
+    }
+
     /// Paint all nodes in the widget tree.
-    pub fn paint(&self, renderer: Rc<RefCell<Renderer>>) -> anyhow::Result<()> {
+    pub fn paint(
+        &mut self, renderer: Rc<RefCell<Renderer>>,
+    ) -> anyhow::Result<()> {
         if self.root.is_none() {
             bail!("root is not set")
         }
         let root = self.root.unwrap();
-        self.paint_node(root, renderer, Location { x: 0., y: 0. })
+        self.paint_node(root, renderer, Location { x: 0., y: 0. })?;
+        self.mark_painted(root)
+    }
+
+    /// Paint all nodes again within `region` only, usually the
+    /// [`WidgetTree::damage`]. The canvas outside of it is kept.
+    pub fn paint_region(
+        &mut self, renderer: Rc<RefCell<Renderer>>, region: Region,
+    ) -> anyhow::Result<()> {
+        let root = self.root.context("root is not set")?;
+        let rect: skia_safe::Rect = region.round_out().into();
+        {
+            let mut renderer = renderer.borrow_mut();
+            let canvas = renderer.canvas();
+            canvas.save();
+            canvas.clip_rect(rect, None, None);
+            canvas.clear(skia_safe::Color::TRANSPARENT);
+        }
+        let result =
+            self.paint_node(root, renderer.clone(), Location { x: 0., y: 0. });
+        renderer.borrow_mut().canvas().restore();
+        result?;
+        self.mark_painted(root)
+    }
+
+    /**
+     * The region to paint again after [`WidgetTree::rebuild`] and
+     * [`WidgetTree::compute_layout`]: where rebuilt, mounted, removed or
+     * moved nodes were painted and are now. None if nothing changed since
+     * the last paint.
+     */
+    pub fn damage(&self) -> anyhow::Result<Option<Region>> {
+        let root = self.root.context("root is not set")?;
+        let mut damage = self
+            .removed
+            .iter()
+            .fold(Region::default(), |damage, region| damage.union(region));
+        let mut regions = HashMap::new();
+        self.node_regions(root, Location { x: 0., y: 0. }, &mut regions)?;
+        for (node, region) in regions {
+            let painted = self.painted.get(&node);
+            if !self.changed.contains(&node) && painted == Some(&region) {
+                continue;
+            }
+            damage = damage.union(&region);
+            if let Some(painted) = painted {
+                damage = damage.union(painted);
+            }
+        }
+        Ok((!damage.is_empty()).then_some(damage))
+    }
+
+    fn mark_painted(&mut self, root: NodeId) -> anyhow::Result<()> {
+        let mut painted = HashMap::new();
+        self.node_regions(root, Location { x: 0., y: 0. }, &mut painted)?;
+        self.painted = painted;
+        self.changed.clear();
+        self.removed.clear();
+        Ok(())
+    }
+
+    /// Regions of `node` and all its children, relative to the root.
+    fn node_regions(
+        &self, node: NodeId, top_left: Location,
+        regions: &mut HashMap<NodeId, Region>,
+    ) -> anyhow::Result<()> {
+        let layout = self.inner.layout(node)?;
+        let top_left = top_left + layout.location;
+        regions.insert(node, Region::new(top_left, layout.size.into()));
+        for c in self.inner.child_ids(node) {
+            self.node_regions(c, top_left, regions)?;
+        }
+        Ok(())
+    }
+
+    /// Where the widget `key` is laid out, relative to the root.
+    pub fn region(&self, key: WidgetKey) -> anyhow::Result<Region> {
+        let mut node = *self.relation.get(&key).context("No such widget")?;
+        let layout = self.inner.layout(node)?;
+        let size = layout.size.into();
+        let mut location = Location::from(layout.location);
+        while let Some(parent) = self.inner.parent(node) {
+            location += self.inner.layout(parent)?.location;
+            node = parent;
+        }
+        Ok(Region::new(location, size))
     }
 
     pub fn result_size(&self) -> anyhow::Result<RectSize<f32>> {
@@ -112,7 +240,10 @@ impl WidgetTree {
             };
             widget.paint(&mut ctx)?;
         }
-        for c in self.inner.child_ids(node) {
+        // children are painted in order, the ones with higher z index last
+        let mut children: Vec<_> = self.inner.child_ids(node).collect();
+        children.sort_by_key(|c| self.z_indices.get(c).copied().unwrap_or(0));
+        for c in children {
             self.paint_node(c, renderer.clone(), top_left)?;
         }
         Ok(())
@@ -133,17 +264,162 @@ impl WidgetTree {
     pub fn new_leaf(
         &mut self, widget: Rc<dyn Widget>,
     ) -> anyhow::Result<NodeId> {
-        let mut style: taffy::Style = widget.style().into();
-        style.display = taffy::Display::Flex;
-        let node = self.inner.new_leaf_with_context(style, widget.clone())?;
+        let context = BuildContext::new(HashSet::new(), self.dirty.clone());
+        self.mount(widget, &context)
+    }
+
+    fn mount(
+        &mut self, widget: Rc<dyn Widget>, context: &BuildContext,
+    ) -> anyhow::Result<NodeId> {
+        if widget.need_build() {
+            widget.build(context)?;
+        }
+        let options = widget.style();
+        let z_index = options.z_index;
+        let node = self
+            .inner
+            .new_leaf_with_context(options.into(), widget.clone())?;
+        self.set_z_index(node, z_index);
         self.relation.insert(widget.key(), node);
+        self.changed.insert(node);
         for child in widget.children() {
-            let cid = self.new_leaf(child.clone())?;
+            let cid = self.mount(child, context)?;
             self.inner.add_child(node, cid)?;
         }
         Ok(node)
     }
 
+    /// Returns whether any widget is marked dirty since the last
+    /// [`WidgetTree::rebuild`].
+    pub fn needs_rebuild(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Call `waker` when a widget of the tree is marked dirty, so the tree
+    /// can be rebuilt and painted again, see [`DirtyKeys::set_waker`].
+    pub fn set_waker(&self, waker: impl Fn() + 'static) {
+        self.dirty.set_waker(waker);
+    }
+
+    /**
+     * Build the widgets marked dirty again, returns false if there is none.
+     *
+     * Only the dirty subtrees are visited, and their nodes are updated in
+     * place. New children are reconciled with the old ones by
+     * [`WidgetKey`]: the same widget keeps its node and layout, a new widget
+     * with the same key is built into the old node, other widgets are
+     * mounted, and old children left are removed.
+     */
+    #[instrument(skip(self))]
+    pub fn rebuild(&mut self) -> anyhow::Result<bool> {
+        let keys = self.dirty.take();
+        if keys.is_empty() {
+            return Ok(false);
+        }
+        let mut nodes: Vec<_> = keys
+            .iter()
+            .filter_map(|key| self.relation.get(key).map(|n| (*key, *n)))
+            .map(|(key, node)| (self.depth(node), key, node))
+            .collect();
+        // parents first, they may rebuild or remove their children
+        nodes.sort_by_key(|(depth, _, _)| *depth);
+        info!("Rebuild {} dirty widgets", nodes.len());
+        let context = BuildContext::new(keys, self.dirty.clone());
+        for (_, key, node) in nodes {
+            if self.relation.get(&key) == Some(&node) {
+                self.rebuild_node(node, &context)?;
+            }
+        }
+        Ok(true)
+    }
+
+    fn rebuild_node(
+        &mut self, node: NodeId, context: &BuildContext,
+    ) -> anyhow::Result<()> {
+        let widget =
+            self.inner.get_node_context(node).context("No widget?")?.clone();
+        widget.build(context)?;
+        let options = widget.style();
+        self.set_z_index(node, options.z_index);
+        self.inner.set_style(node, options.into())?;
+        self.changed.insert(node);
+        self.reconcile(node, widget.children(), context)
+    }
+
+    fn reconcile(
+        &mut self, node: NodeId, children: Vec<Rc<dyn Widget>>,
+        context: &BuildContext,
+    ) -> anyhow::Result<()> {
+        let mut old = HashMap::new();
+        for child in self.inner.children(node)? {
+            let widget =
+                self.inner.get_node_context(child).context("No widget?")?;
+            old.insert(widget.key(), child);
+        }
+        let mut ids = Vec::with_capacity(children.len());
+        for child in children {
+            let id = match old.remove(&child.key()) {
+                Some(id) => {
+                    let current = self
+                        .inner
+                        .get_node_context(id)
+                        .context("No widget?")?;
+                    if !Rc::ptr_eq(current, &child) {
+                        self.inner.set_node_context(id, Some(child))?;
+                        self.rebuild_node(id, context)?;
+                    }
+                    id
+                }
+                None => self.mount(child, context)?,
+            };
+            ids.push(id);
+        }
+        for (_, child) in old {
+            self.unmount(child)?;
+        }
+        self.inner.set_children(node, &ids)?;
+        Ok(())
+    }
+
+    /// Remove `node` and all its children, where they were painted is
+    /// damaged.
+    fn unmount(&mut self, node: NodeId) -> anyhow::Result<()> {
+        for child in self.inner.children(node)? {
+            self.unmount(child)?;
+        }
+        if let Some(widget) = self.inner.get_node_context(node) {
+            let key = widget.key();
+            // the widget may be mounted again elsewhere
+            if self.relation.get(&key) == Some(&node) {
+                self.relation.remove(&key);
+            }
+        }
+        self.changed.remove(&node);
+        self.z_indices.remove(&node);
+        if let Some(region) = self.painted.remove(&node) {
+            self.removed.push(region);
+        }
+        self.inner.remove(node)?;
+        Ok(())
+    }
+
+    fn set_z_index(&mut self, node: NodeId, z_index: i32) {
+        if z_index == 0 {
+            self.z_indices.remove(&node);
+        } else {
+            self.z_indices.insert(node, z_index);
+        }
+    }
+
+    fn depth(&self, mut node: NodeId) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.inner.parent(node) {
+            depth += 1;
+            node = parent;
+        }
+        depth
+    }
+
     pub fn debug_tree(&self) -> anyhow::Result<Vec<String>> {
         if self.root.is_none() {
             bail!("root is not set")
@@ -1 +1,9 @@
 mod nvim_bridge;
+
+use nvim_oxi::Dictionary;
+
+/// Entry of `require("ext_widget_lib")`.
+#[nvim_oxi::module]
+fn ext_widget_lib() -> nvim_oxi::Result<Dictionary> {
+    nvim_bridge::module()
+}
@@ -0,0 +1,629 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use ext_widget_core::{
+    doctor::{run_checks, DoctorOptions},
+    env::in_tmux,
+    live::RenderedImages,
+    logger::{install_logger, set_log_filter, LogOptions},
+    nvim::{
+        add_image_set, clear_session_images, placement_anchors,
+        reposition_image_sets, HighlightTheme, NeovimSession, NvimTermSize,
+        WindowConfig,
+    },
+    placement::{ImagePlacement, PlacementContext},
+    protocol::{Capabilities, HelloInfo},
+    render::{DisplayMetrics, HoverWidget},
+    term::{TermSizeInfo, TermWriter},
+    widgets::StaticProvider,
+};
+use nvim_oxi::{
+    api::{self, types::LogLevel},
+    conversion::FromObject,
+    libuv::AsyncHandle,
+    Dictionary, Function, Object,
+};
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use tokio::runtime::Runtime;
+use tracing::{info, warn};
+
+static BRIDGE: OnceCell<Bridge> = OnceCell::new();
+
+/// Functions the Lua client calls with `notify`.
+const NOTIFICATIONS: [&str; 4] = [
+    "layout_changed",
+    "scroll_down_hover",
+    "scroll_up_hover",
+    "update_config",
+];
+
+/// The tty of Neovim, where images are written to.
+const TTY_EXPR: &str = r#"io.popen("tty 2>/dev/null"):read("*a")"#;
+
+/// Work sent from other threads to the main thread.
+type MainJob = Box<dyn FnOnce() -> anyhow::Result<()> + Send>;
+
+/**
+ * The state of the in-process mode, the counterpart of a connection of the
+ * server.
+ *
+ * Calls from Lua run on the main thread of Neovim, futures of the core are
+ * driven by a current thread runtime until they finish. Hovers are built on
+ * the render thread, which wakes the main thread through a libuv handle to
+ * show them, so Neovim keeps responding meanwhile. Widgets whose state
+ * changes are repainted by a relayout the same way.
+ */
+struct Bridge {
+    runtime: Runtime,
+    session: NeovimSession,
+    /// Highlights are refreshed on each hover, parsers are cached. Hovers
+    /// are built with a clone on the render thread.
+    provider: Mutex<StaticProvider>,
+    /// Hovers on the render thread, a hover stopped meanwhile is dropped
+    /// once it's rendered.
+    pending_hovers: Mutex<HashSet<NonZeroU32>>,
+    main_jobs: Arc<Mutex<Vec<MainJob>>>,
+    wake_main: AsyncHandle,
+}
+
+/// What is needed to show a hover after it's rendered.
+struct PendingHover {
+    id: NonZeroU32,
+    ctx: PlacementContext,
+    window: WindowConfig,
+    term_size: TermSizeInfo,
+    writer: Arc<tokio::sync::Mutex<TermWriter>>,
+}
+
+impl Bridge {
+    fn new() -> anyhow::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let session = NeovimSession::new();
+        let repaint = AsyncHandle::new(|| {
+            nvim_oxi::schedule(|_| -> nvim_oxi::Result<()> {
+                if let Err(e) = layout_changed("repaint".to_string()) {
+                    api::err_writeln(&e.to_string());
+                }
+                Ok(())
+            });
+            Ok::<_, Infallible>(())
+        })?;
+        // several changes before the main thread wakes up repaint once
+        session.widgets.on_dirty(move |_| {
+            let _ = repaint.send();
+        });
+        let main_jobs: Arc<Mutex<Vec<MainJob>>> = Arc::default();
+        let jobs = main_jobs.clone();
+        let wake_main = AsyncHandle::new(move || {
+            let jobs = jobs.clone();
+            nvim_oxi::schedule(move |_| -> nvim_oxi::Result<()> {
+                run_main_jobs(&jobs);
+                Ok(())
+            });
+            Ok::<_, Infallible>(())
+        })?;
+        Ok(Self {
+            runtime,
+            session,
+            provider: Mutex::new(StaticProvider::default()),
+            pending_hovers: Mutex::new(HashSet::new()),
+            main_jobs,
+            wake_main,
+        })
+    }
+
+    /// Run `job` on the main thread, errors are written like the server
+    /// does. Can be called from any thread.
+    fn on_main_thread(
+        &self, job: impl FnOnce() -> anyhow::Result<()> + Send + 'static,
+    ) {
+        self.main_jobs.lock().push(Box::new(job));
+        if let Err(e) = self.wake_main.send() {
+            warn!("Failed to wake the main thread: {}", e);
+        }
+    }
+
+    fn tty_writer(
+        &self,
+    ) -> anyhow::Result<Arc<tokio::sync::Mutex<TermWriter>>> {
+        if let Some(writer) = self.session.tty_writer() {
+            return Ok(writer);
+        }
+        let tty: String = luaeval(TTY_EXPR, Object::nil())?;
+        let writer = self
+            .runtime
+            .block_on(TermWriter::new_tmux_tty(tty.trim(), in_tmux()))?;
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        *self.session.tty_writer.lock() = Some(writer.clone());
+        Ok(writer)
+    }
+}
+
+fn bridge() -> anyhow::Result<&'static Bridge> {
+    BRIDGE.get_or_try_init(Bridge::new)
+}
+
+/// Jobs sent after the main thread is woken run on the next wake up.
+fn run_main_jobs(jobs: &Mutex<Vec<MainJob>>) {
+    let jobs = std::mem::take(&mut *jobs.lock());
+    for job in jobs {
+        if let Err(e) = job() {
+            api::err_writeln(&format!("{:#}", e));
+        }
+    }
+}
+
+/// Evaluate a Lua expression, `_A` in it is `arg`.
+fn luaeval<R: FromObject>(expr: &str, arg: Object) -> anyhow::Result<R> {
+    Ok(api::call_function("luaeval", (expr, arg))?)
+}
+
+/// Evaluate a Lua expression, and deserialize its value through json.
+fn lua_json<T: DeserializeOwned>(expr: &str) -> anyhow::Result<T> {
+    let json: String =
+        luaeval(&format!("vim.json.encode({})", expr), Object::nil())?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn object_to_json(obj: Object) -> anyhow::Result<String> {
+    luaeval("vim.json.encode(_A)", obj)
+}
+
+fn json_to_object(json: String) -> anyhow::Result<Object> {
+    luaeval("vim.json.decode(_A)", Object::from(json))
+}
+
+fn set_id(id: u32) -> anyhow::Result<NonZeroU32> {
+    NonZeroU32::new(id).context("image set id should not be 0")
+}
+
+fn start_hover(md: String) -> anyhow::Result<u32> {
+    if md.is_empty() {
+        bail!("hover expects non-empty markdown");
+    }
+    let bridge = bridge()?;
+    let theme: String =
+        luaeval("vim.json.encode(vim.api.nvim_get_hl(0, {}))", Object::nil())?;
+    let theme = HighlightTheme::from_json(&theme)?;
+    let runtime_paths: Vec<PathBuf> =
+        lua_json("vim.api.nvim_list_runtime_paths()")?;
+    let term_size: NvimTermSize =
+        lua_json(r#"require("external-widget.utils").get_term_size()"#)?;
+    let term_size = TermSizeInfo::new_from_nvim_term(term_size);
+    let ctx = placement_context(0)?.context("The cursor is not visible")?;
+    let writer = bridge.tty_writer()?;
+    let (cfg, chrome, scale) = {
+        let config = bridge.session.config.lock();
+        (
+            config.hover.clone(),
+            config.theme.hover.clone(),
+            config.scale,
+        )
+    };
+    let metrics = DisplayMetrics::new(scale, &term_size);
+    let id = bridge.session.images.lock().alloc_set_id();
+
+    let provider = {
+        let mut provider = bridge.provider.lock();
+        provider.theme = theme;
+        provider.runtime_paths = runtime_paths;
+        provider.clone()
+    };
+    let max_width =
+        metrics.width_of_cells(ctx.bounds(&cfg.window.placement).width);
+    let hover = PendingHover {
+        id,
+        ctx,
+        window: cfg.window.clone(),
+        term_size,
+        writer,
+    };
+    let build = move || async move {
+        HoverWidget::build(provider, &md, &cfg, &chrome, metrics).await
+    };
+    bridge.pending_hovers.lock().insert(id);
+    let st = std::time::Instant::now();
+    let done = move |rendered| {
+        info!("build hover doc image cost: {:?}", st.elapsed().as_millis());
+        bridge.on_main_thread(move || finish_hover(hover, rendered));
+    };
+    if let Err(e) = bridge
+        .session
+        .widgets
+        .spawn_render(id, build, metrics, max_width, done)
+    {
+        bridge.pending_hovers.lock().remove(&id);
+        return Err(e);
+    }
+    Ok(u32::from(id))
+}
+
+/// Show a hover once it's rendered, on the main thread.
+fn finish_hover(
+    hover: PendingHover, rendered: anyhow::Result<RenderedImages>,
+) -> anyhow::Result<()> {
+    let bridge = bridge()?;
+    let PendingHover {
+        id,
+        ctx,
+        window,
+        term_size,
+        writer,
+    } = hover;
+    // stopped while it was rendered
+    if !bridge.pending_hovers.lock().remove(&id) {
+        bridge.session.widgets.remove(id);
+        return Ok(());
+    }
+    let (images, image_size) = rendered?;
+    let (area, placement) = bridge.runtime.block_on(async {
+        let image_set = add_image_set(&bridge.session, id, images).await?;
+        let placement = ImagePlacement::new(
+            &ctx,
+            window.placement.clone(),
+            (window.x_offset, window.y_offset),
+            &term_size,
+            image_size,
+        );
+        let area = placement.area.context("The image is not placed")?;
+        let (x, y) = area.pixel_position(&term_size);
+        let mut writer = writer.lock().await;
+        image_set.render_at(&mut writer, x, y).await?;
+        anyhow::Ok((area, placement))
+    })?;
+    bridge.session.placements.lock().insert(id, placement);
+
+    let _: Object = luaeval(
+        r#"require("external-widget.hover").open_placeholder(_A[1], _A[2])"#,
+        json_to_object(serde_json::to_string(&(area, id))?)?,
+    )?;
+    Ok(())
+}
+
+fn stop_hover(id: u32) -> anyhow::Result<u32> {
+    let bridge = bridge()?;
+    bridge.pending_hovers.lock().remove(&set_id(id)?);
+    bridge.session.placements.lock().remove(&set_id(id)?);
+    bridge.session.widgets.remove(set_id(id)?);
+    let image = bridge.session.images.lock().remove_image_set(set_id(id)?);
+    if let Some(image) = image {
+        let writer = bridge.tty_writer()?;
+        bridge.runtime.block_on(async {
+            let mut writer = writer.lock().await;
+            image.delete_image(&mut writer, true).await
+        })?;
+    }
+    Ok(id)
+}
+
+fn scroll_hover(id: u32, down: bool) -> anyhow::Result<u32> {
+    let bridge = bridge()?;
+    let image = bridge.session.images.lock().find_image_set(set_id(id)?);
+    if let Some(image) = image {
+        let writer = bridge.tty_writer()?;
+        bridge.runtime.block_on(async {
+            let mut writer = writer.lock().await;
+            if down {
+                image.next_image(&mut writer).await
+            } else {
+                image.previous_image(&mut writer).await
+            }
+        })?;
+    }
+    Ok(id)
+}
+
+/// The layout around `win` (0 for the current window), None if the window
+/// or its cursor is not visible.
+fn placement_context(win: i64) -> anyhow::Result<Option<PlacementContext>> {
+    lua_json(&format!(
+        r#"require("external-widget.utils").placement_context({})"#,
+        win
+    ))
+}
+
+/// The autocmd event is only logged.
+fn layout_changed(event: String) -> anyhow::Result<bool> {
+    let bridge = bridge()?;
+    let anchors = placement_anchors(&bridge.session);
+    if anchors.is_empty() {
+        return Ok(true);
+    }
+    info!("Layout changed: {}", event);
+    let term_size: NvimTermSize =
+        lua_json(r#"require("external-widget.utils").get_term_size()"#)?;
+    let term_size = TermSizeInfo::new_from_nvim_term(term_size);
+    let contexts = anchors
+        .into_iter()
+        .map(|win| Ok((win, placement_context(win)?)))
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    let moves = bridge.runtime.block_on(reposition_image_sets(
+        &bridge.session,
+        &term_size,
+        &contexts,
+    ))?;
+    if !moves.is_empty() {
+        let _: Object = luaeval(
+            r#"require("external-widget.hover").move_placeholders(_A)"#,
+            json_to_object(serde_json::to_string(&moves)?)?,
+        )?;
+    }
+    Ok(true)
+}
+
+/// Errors are written like the server does, the Lua client doesn't check the
+/// result of notifications.
+fn update_config(config: Object) -> anyhow::Result<bool> {
+    let patch: serde_json::Value =
+        serde_json::from_str(&object_to_json(config)?)
+            .context("Deserialize config failed")?;
+    match bridge()?.session.update_config(patch) {
+        Ok(unknown_keys) => {
+            for issue in unknown_keys {
+                let msg = format!("external-widget: ignored config {}", issue);
+                api::notify(&msg, LogLevel::Warn, &Dictionary::new())?;
+            }
+            Ok(true)
+        }
+        Err(errors) => {
+            api::err_writeln(&errors.to_string());
+            Ok(false)
+        }
+    }
+}
+
+fn list_images() -> anyhow::Result<Object> {
+    let infos = bridge()?.session.images.lock().list_image_sets();
+    json_to_object(serde_json::to_string(&infos)?)
+}
+
+fn clear_images() -> anyhow::Result<u32> {
+    let bridge = bridge()?;
+    let count = bridge.runtime.block_on(clear_session_images(&bridge.session));
+    Ok(count as u32)
+}
+
+fn health() -> anyhow::Result<Object> {
+    let bridge = bridge()?;
+    let tty: String = luaeval(TTY_EXPR, Object::nil())?;
+    let runtime_paths = lua_json("vim.api.nvim_list_runtime_paths()")?;
+    let opts = {
+        let config = bridge.session.config.lock();
+        DoctorOptions {
+            normal_font: config.hover.normal_font.clone(),
+            mono_font: config.hover.mono_font.clone(),
+            runtime_paths,
+            tty: Some(tty.trim().to_string()).filter(|tty| !tty.is_empty()),
+        }
+    };
+    let results = bridge.runtime.block_on(run_checks(&opts));
+    json_to_object(serde_json::to_string(&results)?)
+}
+
+fn hello(methods: Vec<String>) -> anyhow::Result<Object> {
+    let bridge = bridge()?;
+    let term_size: Option<NvimTermSize> =
+        lua_json(r#"require("external-widget.utils").get_term_size()"#).ok();
+    let capabilities =
+        bridge.runtime.block_on(Capabilities::detect(term_size.as_ref()));
+    let notifications = NOTIFICATIONS.map(String::from).to_vec();
+    let info = HelloInfo::new(methods, notifications, capabilities);
+    json_to_object(serde_json::to_string(&info)?)
+}
+
+fn set_log_level(level: String) -> anyhow::Result<bool> {
+    set_log_filter(&level)?;
+    Ok(true)
+}
+
+fn into_oxi_error(e: anyhow::Error) -> nvim_oxi::Error {
+    api::Error::Other(format!("{:#}", e)).into()
+}
+
+/// Wrap a function returning anyhow errors into a Lua function.
+fn lua_fn<A, R, F>(f: F) -> Object
+where
+    F: Fn(A) -> anyhow::Result<R> + 'static,
+    A: nvim_oxi::lua::Poppable,
+    R: nvim_oxi::lua::Pushable,
+{
+    Object::from(Function::from_fn(move |args: A| {
+        f(args).map_err(into_oxi_error)
+    }))
+}
+
+/// The same API as the RPC handlers of the server.
+pub(crate) fn module() -> nvim_oxi::Result<Dictionary> {
+    bridge().map_err(into_oxi_error)?;
+    let log_dir: String = luaeval(r#"vim.fn.stdpath("log")"#, Object::nil())
+        .map_err(into_oxi_error)?;
+    // fails if the module is loaded again, the first logger is kept
+    let _ = install_logger(&LogOptions {
+        path: Some(PathBuf::from(log_dir).join("ext-widget.log")),
+        ..Default::default()
+    });
+
+    let mut functions = vec![
+        ("start_hover", lua_fn(start_hover)),
+        ("stop_hover", lua_fn(stop_hover)),
+        ("scroll_down_hover", lua_fn(|id: u32| scroll_hover(id, true))),
+        ("scroll_up_hover", lua_fn(|id: u32| scroll_hover(id, false))),
+        ("update_config", lua_fn(update_config)),
+        ("layout_changed", lua_fn(layout_changed)),
+        ("list_images", lua_fn(|()| list_images())),
+        ("clear_images", lua_fn(|()| clear_images())),
+        ("health", lua_fn(|()| health())),
+        ("set_log_level", lua_fn(set_log_level)),
+    ];
+    let methods: Vec<String> = functions
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(["hello".to_string()])
+        .collect();
+    // the client info is only checked by the Lua side
+    let hello_fn = lua_fn(move |_client: Object| hello(methods.clone()));
+    functions.push(("hello", hello_fn));
+    Ok(Dictionary::from_iter(functions))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroU32, sync::Arc};
+
+    use ext_widget_core::{
+        nvim::{add_image_set, NvimTermSize, WindowConfig},
+        painting::RectSize,
+        protocol::HelloInfo,
+        term::{
+            proto::decode_commands, TermRecorder, TermSizeInfo, TermWriter,
+        },
+    };
+    use nvim_oxi::api;
+
+    use super::{
+        bridge, finish_hover, hello, object_to_json, scroll_hover, start_hover,
+        stop_hover, PendingHover, NOTIFICATIONS,
+    };
+
+    /// Write images into memory instead of the tty.
+    fn record_tty() -> TermRecorder {
+        let (writer, recorder) = TermWriter::new_recorder(false);
+        let writer = Arc::new(tokio::sync::Mutex::new(writer));
+        *bridge().unwrap().session.tty_writer.lock() = Some(writer);
+        recorder
+    }
+
+    /// Ids of the images placed by `bytes`.
+    fn placed_ids(bytes: &[u8]) -> Vec<u32> {
+        decode_commands(bytes)
+            .unwrap()
+            .into_iter()
+            .filter(|d| d.command.action.to_string().starts_with("a=p,"))
+            .map(|d| d.command.id.unwrap().0.get())
+            .collect()
+    }
+
+    fn pending_hover(id: NonZeroU32) -> PendingHover {
+        let ctx = serde_json::from_str(
+            r#"{
+                "cursor": [2, 4],
+                "window": { "row": 0, "col": 0, "width": 80, "height": 22 },
+                "editor": { "row": 0, "col": 0, "width": 80, "height": 22 },
+                "cmdline": { "row": 23, "col": 0, "width": 80, "height": 1 }
+            }"#,
+        )
+        .unwrap();
+        let term_size = TermSizeInfo::new_from_nvim_term(NvimTermSize {
+            row: 24,
+            col: 80,
+            xpixel: 800,
+            ypixel: 480,
+        });
+        PendingHover {
+            id,
+            ctx,
+            window: WindowConfig::default(),
+            term_size,
+            writer: bridge().unwrap().session.tty_writer().unwrap(),
+        }
+    }
+
+    #[nvim_oxi::test]
+    fn test_hello_lists_notifications() {
+        let methods = vec!["start_hover".to_string(), "hello".to_string()];
+        let info = object_to_json(hello(methods).unwrap()).unwrap();
+        let info: HelloInfo = serde_json::from_str(&info).unwrap();
+        assert_eq!(info.methods, ["hello", "start_hover"]);
+        assert_eq!(info.notifications, NOTIFICATIONS);
+    }
+
+    #[nvim_oxi::test]
+    fn test_start_hover_rejects_empty() {
+        assert!(start_hover(String::new()).is_err());
+    }
+
+    #[nvim_oxi::test]
+    fn test_scroll_hover() {
+        let recorder = record_tty();
+        let bridge = bridge().unwrap();
+        let id = bridge.session.images.lock().alloc_set_id();
+        let pages = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let writer = bridge.session.tty_writer().unwrap();
+        bridge
+            .runtime
+            .block_on(async {
+                let image_set =
+                    add_image_set(&bridge.session, id, pages).await?;
+                image_set.render_at(&mut *writer.lock().await, 0, 0).await
+            })
+            .unwrap();
+        let first = placed_ids(&recorder.take())[0];
+
+        // up wraps around to the last page, down goes back to the first one
+        scroll_hover(id.get(), false).unwrap();
+        assert_eq!(placed_ids(&recorder.take()), [first + 2]);
+        scroll_hover(id.get(), true).unwrap();
+        assert_eq!(placed_ids(&recorder.take()), [first]);
+        // unknown hovers are ignored
+        scroll_hover(id.get() + 1, true).unwrap();
+        assert!(recorder.take().is_empty());
+    }
+
+    #[nvim_oxi::test]
+    fn test_finish_hover() {
+        let root = concat!(env!("CARGO_MANIFEST_DIR"), "/../..");
+        api::command(&format!("set rtp+={}", root)).unwrap();
+        let recorder = record_tty();
+        let bridge = bridge().unwrap();
+        let id = bridge.session.images.lock().alloc_set_id();
+        let size = RectSize {
+            width: 100.0,
+            height: 40.0,
+        };
+
+        bridge.pending_hovers.lock().insert(id);
+        finish_hover(pending_hover(id), Ok((vec![b"png".to_vec()], size)))
+            .unwrap();
+        assert!(!bridge.pending_hovers.lock().contains(&id));
+        assert!(bridge.session.placements.lock().contains_key(&id));
+        assert_eq!(placed_ids(&recorder.take()).len(), 1);
+        // the placeholder window is opened
+        assert_eq!(api::list_wins().count(), 2);
+    }
+
+    #[nvim_oxi::test]
+    fn test_stop_hover_while_rendering() {
+        let recorder = record_tty();
+        let bridge = bridge().unwrap();
+        let id = bridge.session.images.lock().alloc_set_id();
+        let size = RectSize {
+            width: 100.0,
+            height: 40.0,
+        };
+
+        bridge.pending_hovers.lock().insert(id);
+        stop_hover(id.get()).unwrap();
+        assert!(!bridge.pending_hovers.lock().contains(&id));
+        // the rendered hover is dropped instead of shown
+        finish_hover(pending_hover(id), Ok((vec![b"png".to_vec()], size)))
+            .unwrap();
+        assert!(bridge.session.images.lock().list_image_sets().is_empty());
+        assert!(recorder.take().is_empty());
+
+        // a failed render is reported, and the hover is not pending anymore
+        bridge.pending_hovers.lock().insert(id);
+        let failed = finish_hover(pending_hover(id), Err(anyhow::anyhow!("x")));
+        assert!(failed.is_err());
+        assert!(!bridge.pending_hovers.lock().contains(&id));
+    }
+}
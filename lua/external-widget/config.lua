@@ -1,43 +1,123 @@
 local Rpc = require("external-widget.rpc")
+local Version = require("external-widget.version")
+
+---@class ExtWidget.PlacementConfig
+---@field anchor 'cursor' | 'window' | 'editor' | 'pum' | nil
+---@field sides ('below' | 'above' | 'right' | 'left')[]? tried in order, default { "below", "above" }
+---@field avoid ('cursor_line' | 'cmdline' | 'floats')[]?
+---@field constrain 'editor' | 'window' | nil
 
 ---@class ExtWidget.WindowConfig
 ---@field max_width number?
 ---@field max_height number?
----@field x_offset number?
----@field y_offset number?
+---@field x_offset number? cells away from the anchor
+---@field y_offset number? cells away from the anchor
+---@field placement ExtWidget.PlacementConfig?
 
 ---@class ExtWidget.HoverConfig
 ---@field normal_font string[]?
----@field normal_font_size number?
+---@field normal_font_size number|string|nil pixels, or e.g. "12pt", "1.0 cell"
 ---@field mono_font string[]?
----@field mono_font_size number?
+---@field mono_font_size number|string|nil pixels, or e.g. "12pt", "1.0 cell"
 ---@field window ExtWidget.WindowConfig?
 
+---@class ExtWidget.ImageConfig
+---@field max_cache_bytes number?
+
+--- The server logs to `ext-widget.log` in `stdpath("log")`. A server shared
+--- by several Neovim instances has one log filter, the last one set wins.
+---@class ExtWidget.LogConfig
+---@field level string? log filter directives, e.g. "debug"
+---@field notify boolean? forward WARN and ERROR logs to `vim.notify`
+
+---@class ExtWidget.BorderTheme
+---@field width number?
+---@field color string|number|nil a color like "#rrggbb", or a highlight group whose foreground is used
+---@field radius number?
+
+---@class ExtWidget.ShadowTheme
+---@field color string|number a color, or a highlight group whose background is used
+---@field blur number?
+---@field x_offset number?
+---@field y_offset number?
+
+---@class ExtWidget.ChromeTheme
+---@field background string|number|nil a color, or a highlight group like "NormalFloat"
+---@field border ExtWidget.BorderTheme?
+---@field padding number?
+---@field shadow ExtWidget.ShadowTheme?
+---@field opacity number? opacity of the background, in [0, 1]
+
+---@class ExtWidget.ThemeConfig
+---@field hover ExtWidget.ChromeTheme?
+---@field diagnostics ExtWidget.ChromeTheme?
+---@field notify ExtWidget.ChromeTheme?
+
 ---@class ExtWidget.Config
----@field connect 'embed' | string
+---@field connect 'embed' | 'pipe' | 'inprocess' | string "pipe:<path>" connects to the server listening on the socket at path, other strings are tcp addresses
+---@field scale number|'auto'|nil device pixels per logical pixel, defaults to 1, "auto" derives it from the cell height
 ---@field hover ExtWidget.HoverConfig?
+---@field image ExtWidget.ImageConfig?
+---@field log ExtWidget.LogConfig?
+---@field theme ExtWidget.ThemeConfig?
+---@field on_mismatch 'warn' | 'rebuild' | nil what to do if the binary doesn't match the plugin, default "warn"
 
 ---@type ExtWidget.Config
 local default_config = {
   connect = "embed",
 }
 
+---@param log ExtWidget.LogConfig?
+---@return string[]
+local function log_args(log)
+  log = log or {}
+  local args = { "--log-file", vim.fn.stdpath("log") .. "/ext-widget.log" }
+  if log.level ~= nil then
+    vim.list_extend(args, { "--log-filter", log.level })
+  end
+  return args
+end
+
+--- The config without fields only for the plugin, the server warns about
+--- unknown keys.
+---@param config ExtWidget.Config
+---@return table
+local function server_config(config)
+  config = vim.deepcopy(config)
+  config.connect = nil
+  config.on_mismatch = nil
+  return config
+end
+
 ---@param config ExtWidget.Config
 local function setup(config)
   config = config or {}
   local connect = vim.F.if_nil(config.connect, "127.0.0.1:7000")
+  local args = log_args(config.log)
   local client
   if connect == "embed" then
-    client = Rpc.Client.new_embed()
+    client = Rpc.Client.new_embed(args)
+  elseif connect == "pipe" then
+    client = Rpc.Client.new_pipe(args)
+  elseif vim.startswith(connect, "pipe:") then
+    client = Rpc.Client.new_pipe(args, connect:sub(#"pipe:" + 1))
+  elseif connect == "inprocess" then
+    client = Rpc.Client.new_inprocess()
   else
-    client = Rpc.Client.new_tcp(connect)
+    client = Rpc.Client.new_tcp(connect, args)
   end
   Rpc.setup_global_client(client)
-  -- clear connect field
-  config.connect = nil
-  client:notify("update_config", config)
+  client.server_info = Version.check(client, config.on_mismatch or "warn")
+  client:notify("update_config", server_config(config))
+end
+
+--- Change some options after setup, other options keep their values.
+---@param config ExtWidget.Config
+local function update(config)
+  Rpc.get_global_client():notify("update_config", server_config(config))
 end
 
 return {
   setup = setup,
+  update = update,
 }
@@ -0,0 +1,88 @@
+local Rpc = require("external-widget.rpc")
+local Utils = require("external-widget.utils")
+local Version = require("external-widget.version")
+
+local M = {}
+
+---@class ExtWidget.CheckResult
+---@field name string
+---@field status 'info' | 'pass' | 'warn' | 'fail'
+---@field message string
+---@field hint string?
+
+---@param result ExtWidget.CheckResult
+local function report(result)
+  local msg = result.name .. ": " .. result.message
+  local advice = result.hint and { result.hint } or nil
+  if result.status == "info" then
+    vim.health.info(msg)
+  elseif result.status == "pass" then
+    vim.health.ok(msg)
+  elseif result.status == "warn" then
+    vim.health.warn(msg, advice)
+  else
+    vim.health.error(msg, advice)
+  end
+end
+
+function M.check()
+  vim.health.start("external-widget")
+
+  local cmd = Utils.get_package_path() .. "/target/release/ext-widget"
+  if vim.fn.executable(cmd) == 1 then
+    vim.health.ok("binary: " .. cmd)
+  else
+    vim.health.error("binary not found: " .. cmd, {
+      "run `cargo build --release` in the plugin directory",
+    })
+    return
+  end
+
+  local client = Rpc.get_global_client()
+  if client == nil then
+    vim.health.warn("not connected", {
+      "call `require('external-widget').setup()` first",
+      "or run `ext-widget doctor` in the terminal",
+    })
+    return
+  end
+
+  local info = client.server_info
+  local source = Version.source()
+  if info == nil then
+    vim.health.warn("the binary doesn't answer `hello`", {
+      "run `cargo build --release` in the plugin directory",
+      "or set `on_mismatch = 'rebuild'` in the config",
+    })
+  elseif source == nil then
+    vim.health.info(
+      string.format(
+        "version %s, protocol %d, no sources to compare with",
+        info.version,
+        info.protocol
+      )
+    )
+  elseif
+    info.version == source.version and info.protocol == source.protocol
+  then
+    vim.health.ok(
+      string.format("version %s, protocol %d", info.version, info.protocol)
+    )
+  else
+    vim.health.warn("the binary doesn't match the plugin " .. source.version, {
+      "run `cargo build --release` in the plugin directory",
+      "or set `on_mismatch = 'rebuild'` in the config",
+    })
+  end
+
+  local ok, results = pcall(client.request, client, "health")
+  if not ok then
+    vim.health.error("health request failed: " .. tostring(results))
+    return
+  end
+  for _, result in ipairs(results) do
+    report(result)
+  end
+end
+
+return M
@@ -6,6 +6,14 @@ local hover_group = vim.api.nvim_create_augroup("external_widget_hover", {
 
 local current_image_id = nil
 
+--- `hide` of `nvim_win_set_config` is new in Neovim 0.10, before it hidden
+--- placeholders are closed and opened again.
+local has_hide = vim.fn.has("nvim-0.10") == 1
+
+--- Buffers of placeholder windows closed to hide them, by image set.
+---@type table<number, number>
+local hidden_placeholders = {}
+
 ---@param win number
 ---@param client ExtWidget.Client?
 local function close_hover(win, client)
@@ -17,7 +25,16 @@ local function close_hover(win, client)
     local image_id = current_image_id
     current_image_id = nil
 
-    vim.api.nvim_win_close(win, true)
+    if vim.api.nvim_win_is_valid(win) then
+      vim.api.nvim_win_close(win, true)
+    end
+    -- the placeholder may have been opened again in another window
+    for _, w in ipairs(vim.api.nvim_list_wins()) do
+      if vim.w[w].ext_widget_image == image_id then
+        vim.api.nvim_win_close(w, true)
+      end
+    end
+    hidden_placeholders[image_id] = nil
     vim.o.eventignore = ""
     client:request("stop_hover", image_id)
     return true
@@ -45,7 +62,11 @@ local close_shortcuts = {
   "h",
   "l",
 }
-local function setup_dummy_buffer(win, buffer)
+---@param win number
+---@param buffer number
+---@param image_id number the image set covered by the window
+local function setup_dummy_buffer(win, buffer, image_id)
+  vim.w[win].ext_widget_image = image_id
   vim.api.nvim_create_autocmd(
     { "FocusLost", "WinLeave", "WinClosed", "VimLeavePre" },
     {
@@ -80,6 +101,107 @@ local function setup_dummy_buffer(win, buffer)
   end
 end
 
+---@class ExtWidget.CellArea
+---@field row number 0-based row in the editor
+---@field col number 0-based column in the editor
+---@field width number
+---@field height number
+
+--- Open the window under the hover image, it takes the focus and closes the
+--- hover when left. It covers the same cells as the image, so no text shows
+--- through it.
+---@param area ExtWidget.CellArea
+---@param image_id number
+local function open_placeholder(area, image_id)
+  local buf = vim.api.nvim_create_buf(false, true)
+  local win = vim.api.nvim_open_win(buf, true, {
+    relative = "editor",
+    row = area.row,
+    col = area.col,
+    width = math.max(area.width, 1),
+    height = math.max(area.height, 1),
+    style = "minimal",
+    zindex = 1,
+  })
+  setup_dummy_buffer(win, buf, image_id)
+end
+
+---@class ExtWidget.PlaceholderMove
+---@field id number the image set
+---@field area ExtWidget.CellArea? nil if the image is hidden
+
+---@param win number
+---@param id number the image set
+local function hide_placeholder(win, id)
+  if has_hide then
+    vim.api.nvim_win_set_config(win, { hide = true })
+    return
+  end
+  hidden_placeholders[id] = vim.api.nvim_win_get_buf(win)
+  -- the scratch buffer is kept, and its autocmds must not close the hover
+  local eventignore = vim.o.eventignore
+  vim.o.eventignore = "all"
+  vim.api.nvim_win_close(win, true)
+  vim.o.eventignore = eventignore
+end
+
+---@param area ExtWidget.CellArea
+---@param id number the image set
+local function show_placeholder(area, id)
+  local buf = hidden_placeholders[id]
+  hidden_placeholders[id] = nil
+  if buf == nil or not vim.api.nvim_buf_is_valid(buf) then
+    return
+  end
+  local win = vim.api.nvim_open_win(buf, false, {
+    relative = "editor",
+    row = area.row,
+    col = area.col,
+    width = math.max(area.width, 1),
+    height = math.max(area.height, 1),
+    style = "minimal",
+    zindex = 1,
+  })
+  vim.w[win].ext_widget_image = id
+end
+
+--- Let the placeholder windows follow their images after the server placed
+--- them again.
+---@param moves ExtWidget.PlaceholderMove[]
+local function move_placeholders(moves)
+  local wins = {}
+  for _, win in ipairs(vim.api.nvim_list_wins()) do
+    local id = vim.w[win].ext_widget_image
+    if id ~= nil then
+      wins[id] = win
+    end
+  end
+  for _, move in ipairs(moves) do
+    local win = wins[move.id]
+    local area = move.area
+    if area == nil or area == vim.NIL then
+      if win ~= nil then
+        hide_placeholder(win, move.id)
+      end
+    elseif win == nil then
+      -- closed to hide it, or the placeholder is closed already
+      show_placeholder(area, move.id)
+    else
+      local config = {
+        relative = "editor",
+        row = area.row,
+        col = area.col,
+        width = math.max(area.width, 1),
+        height = math.max(area.height, 1),
+      }
+      if has_hide then
+        config.hide = false
+      end
+      vim.api.nvim_win_set_config(win, config)
+    end
+  end
+end
+
 ---@param client ExtWidget.Client
 ---@param err any
 ---@param res lsp.Hover
@@ -125,4 +247,6 @@ end
 return {
   show_hover = show_hover,
   setup_dummy_buffer = setup_dummy_buffer,
+  open_placeholder = open_placeholder,
+  move_placeholders = move_placeholders,
 }
@@ -1,10 +1,38 @@
 local Config = require("external-widget.config")
+local Rpc = require("external-widget.rpc")
 
 ---@param config ExtWidget.Config
 local function setup(config)
   Config.setup(config)
 end
 
+--- Update part of the config, e.g. `{ hover = { normal_font_size = 16 } }`.
+--- Errors are reported with the path of the key, and nothing is changed.
+---@param config ExtWidget.Config
+local function update_config(config)
+  Config.update(config)
+end
+
+--- List all images of the current client, for debugging.
+local function list_images()
+  return Rpc.get_global_client():request("list_images")
+end
+
+--- Delete all images of the current client.
+local function clear_images()
+  return Rpc.get_global_client():request("clear_images")
+end
+
+--- Change the log filter of the server at runtime.
+---@param level string log filter directives, e.g. "debug"
+local function set_log_level(level)
+  return Rpc.get_global_client():request("set_log_level", level)
+end
+
 return {
   setup = setup,
+  update_config = update_config,
+  list_images = list_images,
+  clear_images = clear_images,
+  set_log_level = set_log_level,
 }
@@ -2,8 +2,10 @@ local Utils = require("external-widget.utils")
 
 ---@class ExtWidget.Client
 ---@field private ch number
----@field private kind 'embed' | 'tcp'
+---@field private kind 'embed' | 'tcp' | 'pipe' | 'inprocess'
 ---@field private process vim.SystemObj?
+---@field private lib table? the `ext_widget_lib` module, if in-process
+---@field server_info ExtWidget.HelloInfo? the reply of `hello`
 local Client = {}
 
 local function try_port()
@@ -24,11 +26,12 @@ local function try_port()
   return port
 end
 
+---@param args string[] extra arguments of the server
 ---@return ExtWidget.Client
-function Client.new_embed()
+function Client.new_embed(args)
   local cmd = Utils.get_package_path()
   cmd = cmd .. "/target/release/ext-widget"
-  local ch = vim.fn.jobstart({ cmd, "embed" }, {
+  local ch = vim.fn.jobstart(vim.list_extend({ cmd, "embed" }, args or {}), {
     rpc = true,
     detach = true,
     on_exit = function(_, code)
@@ -47,68 +50,152 @@ function Client.new_embed()
   end
 end
 
+--- Connect to a server at `addr`, retrying while it starts.
+---@param mode 'tcp' | 'pipe'
 ---@param addr string
----@return ExtWidget.Client
-function Client.new_tcp(_addr)
-  local cmd = Utils.get_package_path()
-  cmd = cmd .. "/target/release/ext-widget"
-  local port = try_port()
-  local addr = "127.0.0.1:" .. port
-
-  local process = vim.system { cmd, "serve", "--addr", addr }
-  local ch
-  local max_try = 100
+---@param max_try integer
+---@return number
+local function sockconnect(mode, addr, max_try)
   while true do
-    local succ, ret = pcall(vim.fn.sockconnect, "tcp", addr, {
+    local succ, ret = pcall(vim.fn.sockconnect, mode, addr, {
       rpc = 1,
     })
-    if succ then
-      ch = ret
-      break
+    if succ and ret > 0 then
+      return ret
     end
-    vim.wait(10)
     max_try = max_try - 1
-    if max_try == 0 then
+    if max_try <= 0 then
       error("Failed to connect external-widget process")
     end
+    vim.wait(10)
   end
+end
 
-  if ch > 0 then
+--- Start a server listening on `addr` and connect to it.
+---@param mode 'tcp' | 'pipe'
+---@param addr string
+---@param serve_args string[] arguments of `serve` to listen on `addr`
+---@param args string[] extra arguments of the server
+---@return ExtWidget.Client
+local function spawn_server(mode, addr, serve_args, args)
+  local cmd = Utils.get_package_path()
+  cmd = cmd .. "/target/release/ext-widget"
+  local process = vim.system(
+    vim.list_extend(
+      vim.list_extend({ cmd, "serve" }, serve_args),
+      vim.list_extend({ "--idle-timeout", "60" }, args or {})
+    )
+  )
+  return setmetatable({
+    ch = sockconnect(mode, addr, 100),
+    kind = mode,
+    process = process,
+  }, { __index = Client })
+end
+
+---@param addr string
+---@param args string[] extra arguments of the server
+---@return ExtWidget.Client
+function Client.new_tcp(_addr, args)
+  local addr = "127.0.0.1:" .. try_port()
+  return spawn_server("tcp", addr, { "--addr", addr }, args)
+end
+
+---@param args string[] extra arguments of the server
+---@param path string? socket of a running server, start a private one if nil
+---@return ExtWidget.Client
+function Client.new_pipe(args, path)
+  if path ~= nil then
     return setmetatable({
-      ch = ch,
-      kind = "tcp",
-      process = process,
+      ch = sockconnect("pipe", vim.fn.expand(path), 1),
+      kind = "pipe",
     }, { __index = Client })
-  else
-    error("Failed to connect external-widget process")
   end
+  path = vim.fn.stdpath("run")
+    .. "/ext-widget-"
+    .. vim.fn.getpid()
+    .. ".sock"
+  return spawn_server("pipe", path, { "--socket", path }, args)
+end
+
+--- Load the library into Neovim instead of starting a server.
+---@return ExtWidget.Client
+function Client.new_inprocess()
+  local target = Utils.get_package_path() .. "/target/release/"
+  for _, pattern in ipairs { "lib?.so", "lib?.dylib" } do
+    if not package.cpath:find(target .. pattern, 1, true) then
+      package.cpath = package.cpath .. ";" .. target .. pattern
+    end
+  end
+  local ok, lib = pcall(require, "ext_widget_lib")
+  if not ok then
+    error("Failed to load ext_widget_lib: " .. tostring(lib))
+  end
+  return setmetatable({
+    kind = "inprocess",
+    lib = lib,
+  }, { __index = Client })
 end
 
 function Client:close()
-  if self.kind == "embed" then
+  if self.kind == "inprocess" then
+    return
+  elseif self.kind == "embed" then
     vim.fn.jobstop(self.ch)
   else
     vim.fn.chanclose(self.ch)
-    self.process:kill(9)
+    -- SIGTERM, so the server can clean up its images and socket. A server
+    -- we only connected to keeps running for other clients.
+    if self.process ~= nil then
+      self.process:kill(15)
+    end
   end
 end
 
 function Client:setup_autocmd()
   vim.api.nvim_create_autocmd("VimLeavePre", {
     callback = function()
+      -- delete all images before the terminal is handed back
+      pcall(self.request, self, "clear_images")
       self:close()
     end,
   })
+
+  -- images are placed again when the layout changes, bursts of events (e.g.
+  -- WinScrolled with WinResized) are sent once
+  local pending = false
+  vim.api.nvim_create_autocmd(
+    { "VimResized", "WinScrolled", "WinResized", "TabEnter" },
+    {
+      callback = function(ev)
+        if pending then
+          return
+        end
+        pending = true
+        vim.schedule(function()
+          pending = false
+          self:notify("layout_changed", ev.event)
+        end)
+      end,
+    }
+  )
 end
 
 ---@param method string
 ---@param ... any
 ---@return nil
 function Client:request(method, ...)
+  if self.kind == "inprocess" then
+    return self.lib[method](...)
+  end
   return vim.rpcrequest(self.ch, method, ...)
 end
 
 function Client:notify(method, ...)
+  if self.kind == "inprocess" then
+    self.lib[method](...)
+    return
+  end
   return vim.rpcnotify(self.ch, method, ...)
 end
 
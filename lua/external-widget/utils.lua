@@ -40,4 +40,96 @@ function M.get_term_size()
   }
 end
 
+--- Position of the cursor of `win` in the editor, as 0-based (row, col), or
+--- nil if the cursor line is scrolled out of the window.
+---@param win number? the current window if nil
+---@return integer[]?
+function M.cursor_position(win)
+  win = win or vim.api.nvim_get_current_win()
+  local cursor = vim.api.nvim_win_get_cursor(win)
+  local pos = vim.fn.screenpos(win, cursor[1], cursor[2] + 1)
+  if pos.row == 0 then
+    return nil
+  end
+  return { pos.row - 1, pos.col - 1 }
+end
+
+---@param win number
+---@return ExtWidget.CellArea
+local function window_area(win)
+  local pos = vim.api.nvim_win_get_position(win)
+  return {
+    row = pos[1],
+    col = pos[2],
+    width = vim.api.nvim_win_get_width(win),
+    height = vim.api.nvim_win_get_height(win),
+  }
+end
+
+--- The layout of the editor around the cursor of `win`, to place widgets.
+--- All areas are 0-based cells of the editor. Returns nil if the window is
+--- not in the current tab, or its cursor is not visible.
+---@param win number? the current window if nil or 0
+function M.placement_context(win)
+  if win == nil or win == 0 then
+    win = vim.api.nvim_get_current_win()
+  end
+  if
+    not vim.api.nvim_win_is_valid(win)
+    or vim.api.nvim_win_get_tabpage(win) ~= vim.api.nvim_get_current_tabpage()
+  then
+    return nil
+  end
+  local cursor = M.cursor_position(win)
+  if cursor == nil then
+    return nil
+  end
+
+  local tabline = vim.o.showtabline == 2
+    or (vim.o.showtabline == 1 and #vim.api.nvim_list_tabpages() > 1)
+  local top = tabline and 1 or 0
+  local cmdline_row = vim.o.lines - vim.o.cmdheight
+  local statusline = vim.o.laststatus == 3 and 1 or 0
+
+  local floats = {}
+  for _, w in ipairs(vim.api.nvim_tabpage_list_wins(0)) do
+    local config = vim.api.nvim_win_get_config(w)
+    -- placeholders of our own images move with them
+    if
+      w ~= win
+      and config.relative ~= ""
+      and not config.hide
+      and vim.w[w].ext_widget_image == nil
+    then
+      table.insert(floats, window_area(w))
+    end
+  end
+
+  local pum = vim.fn.pum_getpos()
+  return {
+    win = win,
+    cursor = cursor,
+    window = window_area(win),
+    editor = {
+      row = top,
+      col = 0,
+      width = vim.o.columns,
+      height = cmdline_row - statusline - top,
+    },
+    cmdline = {
+      row = cmdline_row,
+      col = 0,
+      width = vim.o.columns,
+      height = vim.o.cmdheight,
+    },
+    pum = pum.row ~= nil and {
+      row = pum.row,
+      col = pum.col,
+      width = pum.width,
+      height = pum.height,
+    } or nil,
+    floats = floats,
+  }
+end
+
 return M
@@ -0,0 +1,149 @@
+local Utils = require("external-widget.utils")
+
+local M = {}
+
+---@class ExtWidget.SourceVersion
+---@field version string
+---@field protocol number
+
+---@type ExtWidget.SourceVersion|false|nil
+local source_version = nil
+
+---@param path string
+---@param pattern string
+---@return string?
+local function match_file(path, pattern)
+  local file = io.open(path, "r")
+  if file == nil then
+    return nil
+  end
+  local content = file:read("*a")
+  file:close()
+  return content:match(pattern)
+end
+
+--- The version of the crates in the plugin directory, which the binary is
+--- built from, so the plugin never carries a copy that could drift. Nil if
+--- the sources can't be read.
+---@return ExtWidget.SourceVersion?
+function M.source()
+  if source_version == nil then
+    local core = Utils.get_package_path() .. "/crates/ext-widget-core"
+    local version =
+      match_file(core .. "/Cargo.toml", '\nversion%s*=%s*"([^"]+)"')
+    local protocol = match_file(
+      core .. "/src/protocol.rs",
+      "PROTOCOL_VERSION: u32 = (%d+);"
+    )
+    source_version = false
+    if version ~= nil and protocol ~= nil then
+      source_version = { version = version, protocol = tonumber(protocol) }
+    end
+  end
+  return source_version or nil
+end
+
+---@class ExtWidget.Capabilities
+---@field tmux boolean
+---@field tmux_passthrough boolean?
+---@field ssh boolean
+---@field pixel_size boolean
+
+---@class ExtWidget.HelloInfo
+---@field version string
+---@field protocol number
+---@field methods string[]
+---@field notifications string[]
+---@field capabilities ExtWidget.Capabilities
+
+---@param msg string
+---@param level number
+local function notify(msg, level)
+  vim.notify("external-widget: " .. msg, level)
+end
+
+--- Run `cargo build --release` in the plugin directory.
+function M.rebuild()
+  notify("rebuilding the binary", vim.log.levels.INFO)
+  vim.system(
+    { "cargo", "build", "--release" },
+    { cwd = Utils.get_package_path(), text = true },
+    function(res)
+      vim.schedule(function()
+        if res.code == 0 then
+          notify("rebuilt, restart Neovim to use it", vim.log.levels.INFO)
+        else
+          notify("rebuild failed: " .. res.stderr, vim.log.levels.ERROR)
+        end
+      end)
+    end
+  )
+end
+
+--- Whether `err` of `hello` means the binary predates it, other errors are
+--- real failures.
+---@param client ExtWidget.Client
+---@param err any
+---@return boolean
+local function is_unknown_hello(client, err)
+  if client.kind == "inprocess" then
+    return client.lib.hello == nil
+  end
+  local msg = tostring(err)
+  -- `Unknown request` is what binaries before `hello` answered
+  return msg:find("Unknown method: hello", 1, true) ~= nil
+    or msg:find("Unknown request: hello", 1, true) ~= nil
+end
+
+--- Say hello to the server, and compare its version with the sources in the
+--- plugin directory.
+---@param client ExtWidget.Client
+---@param on_mismatch 'warn' | 'rebuild'
+---@return ExtWidget.HelloInfo?
+function M.check(client, on_mismatch)
+  local source = M.source()
+  local ok, info
+  if source ~= nil then
+    ok, info = pcall(client.request, client, "hello", source)
+  else
+    ok, info = pcall(client.request, client, "hello")
+  end
+  local problem
+  if not ok then
+    if not is_unknown_hello(client, info) then
+      notify("hello failed: " .. tostring(info), vim.log.levels.ERROR)
+      return nil
+    end
+    problem = "the binary is too old to answer `hello`"
+  elseif source == nil then
+    return info
+  elseif info.protocol ~= source.protocol then
+    problem = string.format(
+      "protocol %d of the binary differs from %d of the plugin",
+      info.protocol,
+      source.protocol
+    )
+  elseif info.version ~= source.version then
+    problem = string.format(
+      "binary %s differs from plugin %s",
+      info.version,
+      source.version
+    )
+  end
+  if problem == nil then
+    return info
+  end
+
+  if on_mismatch == "rebuild" then
+    notify(problem, vim.log.levels.WARN)
+    M.rebuild()
+  else
+    notify(
+      problem .. ", run `cargo build --release` in the plugin directory",
+      vim.log.levels.WARN
+    )
+  end
+  return ok and info or nil
+end
+
+return M